@@ -0,0 +1,274 @@
+use ldr_tools::glam::{vec3, EulerRot, Quat, Vec3};
+use ldr_wgpu::{
+    calculate_camera_data, fit_camera, orbit_pivot, orthographic_height, CameraData, CameraShake,
+    Projection, UpAxis, FOV_Y,
+};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::*,
+    keyboard::{Key, ModifiersState, NamedKey},
+};
+
+/// Orbit, pan, and zoom camera controls driven by window events.
+#[derive(Default)]
+pub struct CameraController {
+    // The target camera state modified by user input.
+    translation: Vec3,
+    rotation_xyz: Vec3,
+    /// The point in the model to rotate around.
+    pivot: Vec3,
+    /// The initial camera that frames the whole scene.
+    home_translation: Vec3,
+    home_rotation_xyz: Vec3,
+    /// The bounds of the scene for framing the camera at the current window size.
+    bounds: (Vec3, Vec3),
+    is_mouse_left_clicked: bool,
+    is_mouse_right_clicked: bool,
+    modifiers: ModifiersState,
+    previous_cursor_position: PhysicalPosition<f64>,
+    /// The duration in seconds for animated view changes like resetting the camera.
+    transition_duration: f32,
+    transition: Option<CameraTransition>,
+    is_orthographic: bool,
+    /// Optional motion applied on top of the camera without changing its state.
+    shake: Option<CameraShake>,
+    shake_time: f32,
+}
+
+// Keyboard steps roughly match a short mouse drag or a single scroll wheel notch.
+const KEY_ROTATION_STEP: f32 = 0.1;
+const KEY_PAN_PIXELS: f32 = 20.0;
+const KEY_ZOOM_STEP: f32 = 0.1;
+
+/// The camera state at the start of an animated view change.
+struct CameraTransition {
+    translation: Vec3,
+    rotation_xyz: Vec3,
+    elapsed: f32,
+}
+
+impl CameraController {
+    pub fn new(transition_duration: f32, shake: Option<CameraShake>) -> Self {
+        Self {
+            translation: vec3(0.0, -0.5, -200.0),
+            transition_duration,
+            shake,
+            ..Default::default()
+        }
+    }
+
+    /// Frame the whole scene and orbit around its center.
+    /// This also becomes the view for resetting the camera.
+    pub fn frame_scene(&mut self, bounds: (Vec3, Vec3), size: PhysicalSize<u32>) {
+        let (translation, rotation_xyz) = fit_camera(bounds, size.width, size.height, FOV_Y);
+        self.pivot = orbit_pivot(bounds);
+        self.bounds = bounds;
+        self.translation = translation;
+        self.rotation_xyz = rotation_xyz;
+        self.home_translation = translation;
+        self.home_rotation_xyz = rotation_xyz;
+    }
+
+    /// The current translation and rotation to use for rendering.
+    pub fn camera(&self) -> (Vec3, Vec3) {
+        match &self.transition {
+            Some(transition) => {
+                let t = transition.elapsed / self.transition_duration;
+                // Smoothstep easing to avoid abrupt starts and stops.
+                let t = t * t * (3.0 - 2.0 * t);
+
+                let translation = transition.translation.lerp(self.translation, t);
+
+                let start = euler_to_quat(transition.rotation_xyz);
+                let end = euler_to_quat(self.rotation_xyz);
+                let (z, x, y) = start.slerp(end, t).to_euler(EulerRot::ZXY);
+
+                (translation, vec3(x, y, z))
+            }
+            None => (self.translation, self.rotation_xyz),
+        }
+    }
+
+    pub fn camera_data(&self, size: PhysicalSize<u32>, up_axis: UpAxis) -> CameraData {
+        let (mut translation, mut rotation) = self.camera();
+        if let Some(shake) = &self.shake {
+            let (translation_offset, rotation_offset) = shake.offset(self.shake_time);
+            translation += translation_offset;
+            rotation += rotation_offset;
+        }
+
+        // Match the size of objects at the pivot to make toggling projections seamless.
+        // This also allows zooming in orthographic mode by changing the distance.
+        let projection = if self.is_orthographic {
+            Projection::Orthographic {
+                height: orthographic_height(translation.z.abs(), FOV_Y),
+            }
+        } else {
+            Projection::Perspective { fov_y: FOV_Y }
+        };
+
+        calculate_camera_data(
+            size.width,
+            size.height,
+            translation,
+            rotation,
+            self.pivot,
+            up_axis,
+            projection,
+        )
+    }
+
+    /// Move the camera to a new view with an animated transition if enabled.
+    pub fn snap_to(&mut self, translation: Vec3, rotation_xyz: Vec3) {
+        if self.transition_duration > 0.0 {
+            let (current_translation, current_rotation) = self.camera();
+            self.transition = Some(CameraTransition {
+                translation: current_translation,
+                rotation_xyz: current_rotation,
+                elapsed: 0.0,
+            });
+        }
+        self.translation = translation;
+        self.rotation_xyz = rotation_xyz;
+    }
+
+    /// Advance any active transition by `delta` seconds.
+    /// Returns `true` if the camera changed and needs to be updated.
+    pub fn update(&mut self, delta: f32) -> bool {
+        // Camera shake changes the view every frame.
+        let is_shaking = self.shake.is_some();
+        if is_shaking {
+            self.shake_time += delta;
+        }
+
+        match &mut self.transition {
+            Some(transition) => {
+                transition.elapsed += delta;
+                if transition.elapsed >= self.transition_duration {
+                    self.transition = None;
+                }
+                true
+            }
+            None => is_shaking,
+        }
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent, size: PhysicalSize<u32>) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } if event.state == ElementState::Pressed => {
+                match &event.logical_key {
+                    Key::Named(NamedKey::Home) => {
+                        self.snap_to(self.home_translation, self.home_rotation_xyz)
+                    }
+                    Key::Character(c) if c.as_str() == "f" => {
+                        // Fit the current window size, which may differ from the initial size.
+                        let (translation, rotation_xyz) =
+                            fit_camera(self.bounds, size.width, size.height, FOV_Y);
+                        self.snap_to(translation, rotation_xyz)
+                    }
+                    // Hold shift to pan instead of rotating like with the mouse.
+                    Key::Named(NamedKey::ArrowLeft) if self.modifiers.shift_key() => {
+                        self.pan(-KEY_PAN_PIXELS, 0.0, size)
+                    }
+                    Key::Named(NamedKey::ArrowRight) if self.modifiers.shift_key() => {
+                        self.pan(KEY_PAN_PIXELS, 0.0, size)
+                    }
+                    Key::Named(NamedKey::ArrowUp) if self.modifiers.shift_key() => {
+                        self.pan(0.0, -KEY_PAN_PIXELS, size)
+                    }
+                    Key::Named(NamedKey::ArrowDown) if self.modifiers.shift_key() => {
+                        self.pan(0.0, KEY_PAN_PIXELS, size)
+                    }
+                    Key::Named(NamedKey::ArrowLeft) => self.rotation_xyz.y -= KEY_ROTATION_STEP,
+                    Key::Named(NamedKey::ArrowRight) => self.rotation_xyz.y += KEY_ROTATION_STEP,
+                    Key::Named(NamedKey::ArrowUp) => self.rotation_xyz.x -= KEY_ROTATION_STEP,
+                    Key::Named(NamedKey::ArrowDown) => self.rotation_xyz.x += KEY_ROTATION_STEP,
+                    // Accept = to avoid requiring shift for + on most keyboard layouts.
+                    Key::Character(c) if matches!(c.as_str(), "+" | "=") => {
+                        self.zoom(KEY_ZOOM_STEP)
+                    }
+                    Key::Character(c) if c.as_str() == "-" => self.zoom(-KEY_ZOOM_STEP),
+                    Key::Character(c) if c.as_str() == "p" => {
+                        self.is_orthographic = !self.is_orthographic
+                    }
+                    _ => (),
+                }
+            }
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            WindowEvent::MouseInput { button, state, .. } => {
+                // Track mouse clicks to only rotate when dragging while clicked.
+                match (button, state) {
+                    (MouseButton::Left, ElementState::Pressed) => self.is_mouse_left_clicked = true,
+                    (MouseButton::Left, ElementState::Released) => {
+                        self.is_mouse_left_clicked = false
+                    }
+                    (MouseButton::Right, ElementState::Pressed) => {
+                        self.is_mouse_right_clicked = true
+                    }
+                    (MouseButton::Right, ElementState::Released) => {
+                        self.is_mouse_right_clicked = false
+                    }
+                    _ => (),
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if self.is_mouse_left_clicked && self.modifiers.shift_key() {
+                    let delta_x = position.x - self.previous_cursor_position.x;
+
+                    // Roll the camera to tilt the horizon.
+                    self.rotation_xyz.z += (delta_x * 0.01) as f32;
+                } else if self.is_mouse_left_clicked {
+                    let delta_x = position.x - self.previous_cursor_position.x;
+                    let delta_y = position.y - self.previous_cursor_position.y;
+
+                    // Swap XY so that dragging left/right rotates left/right.
+                    self.rotation_xyz.x += (delta_y * 0.01) as f32;
+                    self.rotation_xyz.y += (delta_x * 0.01) as f32;
+                } else if self.is_mouse_right_clicked {
+                    let delta_x = position.x - self.previous_cursor_position.x;
+                    let delta_y = position.y - self.previous_cursor_position.y;
+                    self.pan(delta_x as f32, delta_y as f32, size);
+                }
+                // Always update the position to avoid jumps when moving between clicks.
+                self.previous_cursor_position = *position;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                // TODO: Add tests for handling scroll events properly?
+                let amount = match delta {
+                    MouseScrollDelta::LineDelta(_x, y) => *y * 0.1,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 * 0.005,
+                };
+                self.zoom(amount);
+            }
+            _ => (),
+        }
+    }
+
+    /// Translate the camera by an equivalent distance to `delta_x` and `delta_y` in pixels.
+    fn pan(&mut self, delta_x: f32, delta_y: f32, size: PhysicalSize<u32>) {
+        // The viewport height and vertical field of view define the conversion.
+        let fac = FOV_Y.sin() * self.translation.z.abs() / size.height as f32;
+
+        // Negate y so that dragging up "drags" the model up.
+        self.translation.x += delta_x * fac;
+        self.translation.y -= delta_y * fac;
+    }
+
+    /// Move towards the pivot by `amount` times the current distance.
+    fn zoom(&mut self, amount: f32) {
+        // Scale zoom speed with distance to make it easier to zoom out large scenes.
+        // Clamp to prevent the user from zooming through the origin.
+        let delta_z = amount * self.translation.z.abs();
+        self.translation.z = (self.translation.z + delta_z).min(-1.0);
+    }
+}
+
+fn euler_to_quat(rotation_xyz: Vec3) -> Quat {
+    // Match the rotation order used for the view matrix.
+    Quat::from_euler(
+        EulerRot::ZXY,
+        rotation_xyz.z,
+        rotation_xyz.x,
+        rotation_xyz.y,
+    )
+}
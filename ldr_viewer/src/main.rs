@@ -1,36 +1,46 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use camera::CameraController;
 use futures::executor::block_on;
-use ldr_tools::{
-    glam::{vec3, Vec3},
-    GeometrySettings, StudType,
+use ldr_tools::{GeometrySettings, LDrawColor, LDrawSceneInstanced};
+use ldr_wgpu::{
+    CameraData, CameraShake, GroundPlane, LoadProgress, OutlineSettings, QualityPreset,
+    QualitySettings, RenderMode, Tonemap, UpAxis,
 };
-use ldr_wgpu::{calculate_camera_data, FOV_Y};
 use log::{debug, error, info};
 use winit::{
-    dpi::PhysicalPosition,
     event::*,
-    event_loop::EventLoop,
+    event_loop::{ControlFlow, EventLoop},
+    keyboard::Key,
     window::{Window, WindowBuilder},
 };
 
+mod camera;
+
 struct State<'a> {
     surface: wgpu::Surface<'a>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     required_features: wgpu::Features,
     config: wgpu::SurfaceConfiguration,
-}
-
-#[derive(Default)]
-struct InputState {
-    translation: Vec3,
-    rotation_xyz: Vec3,
-    is_mouse_left_clicked: bool,
-    is_mouse_right_clicked: bool,
-    previous_cursor_position: PhysicalPosition<f64>,
+    device_lost: Arc<AtomicBool>,
+    /// The supported MSAA sample count closest to the requested count.
+    sample_count: ldr_wgpu::SampleCount,
 }
 
 impl<'a> State<'a> {
-    async fn new(window: &'a Window, format: wgpu::TextureFormat) -> Self {
+    async fn new(
+        window: &'a Window,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        gpu_timing: bool,
+    ) -> Self {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             ..Default::default()
@@ -47,7 +57,18 @@ impl<'a> State<'a> {
         debug!("{:#?}", adapter.get_info());
 
         let supported_features = adapter.features();
-        let required_features = ldr_wgpu::required_features(supported_features);
+        let mut required_features = ldr_wgpu::required_features(supported_features);
+        if gpu_timing {
+            required_features |= ldr_wgpu::gpu_timing_features(supported_features);
+        }
+
+        let supported_sample_count = ldr_wgpu::supported_sample_count(&adapter, sample_count);
+        if supported_sample_count.get() != sample_count {
+            info!(
+                "Using {}x MSAA instead of {sample_count}x",
+                supported_sample_count.get()
+            );
+        }
 
         let (device, queue) = adapter
             .request_device(
@@ -61,6 +82,14 @@ impl<'a> State<'a> {
             .await
             .unwrap();
 
+        // Recreate the device on the next frame instead of crashing.
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            error!("Device lost: {reason:?} {message}");
+            lost.store(true, Ordering::Relaxed);
+        });
+
         let size = window.inner_size();
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -80,68 +109,78 @@ impl<'a> State<'a> {
             queue,
             config,
             required_features,
+            device_lost,
+            sample_count: supported_sample_count,
         }
     }
+
+    fn resize(
+        &mut self,
+        renderer: &mut ldr_wgpu::Renderer,
+        size: winit::dpi::PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+    ) {
+        self.config.width = size.width;
+        self.config.height = size.height;
+        self.surface.configure(&self.device, &self.config);
+
+        renderer.resize(&self.device, size.width, size.height, format);
+    }
 }
 
-impl InputState {
-    pub fn handle_input(&mut self, event: &WindowEvent, size: winit::dpi::PhysicalSize<u32>) {
-        match event {
-            WindowEvent::KeyboardInput { .. } => {}
-            WindowEvent::MouseInput { button, state, .. } => {
-                // Track mouse clicks to only rotate when dragging while clicked.
-                match (button, state) {
-                    (MouseButton::Left, ElementState::Pressed) => self.is_mouse_left_clicked = true,
-                    (MouseButton::Left, ElementState::Released) => {
-                        self.is_mouse_left_clicked = false
-                    }
-                    (MouseButton::Right, ElementState::Pressed) => {
-                        self.is_mouse_right_clicked = true
-                    }
-                    (MouseButton::Right, ElementState::Released) => {
-                        self.is_mouse_right_clicked = false
-                    }
-                    _ => (),
-                }
-            }
-            WindowEvent::CursorMoved { position, .. } => {
-                if self.is_mouse_left_clicked {
-                    let delta_x = position.x - self.previous_cursor_position.x;
-                    let delta_y = position.y - self.previous_cursor_position.y;
-
-                    // Swap XY so that dragging left/right rotates left/right.
-                    self.rotation_xyz.x += (delta_y * 0.01) as f32;
-                    self.rotation_xyz.y += (delta_x * 0.01) as f32;
-                } else if self.is_mouse_right_clicked {
-                    let delta_x = position.x - self.previous_cursor_position.x;
-                    let delta_y = position.y - self.previous_cursor_position.y;
-
-                    // Translate an equivalent distance in screen space based on the camera.
-                    // The viewport height and vertical field of view define the conversion.
-                    let fac = FOV_Y.sin() * self.translation.z.abs() / size.height as f32;
-
-                    // Negate y so that dragging up "drags" the model up.
-                    self.translation.x += delta_x as f32 * fac;
-                    self.translation.y -= delta_y as f32 * fac;
-                }
-                // Always update the position to avoid jumps when moving between clicks.
-                self.previous_cursor_position = *position;
-            }
-            WindowEvent::MouseWheel { delta, .. } => {
-                // TODO: Add tests for handling scroll events properly?
-                // Scale zoom speed with distance to make it easier to zoom out large scenes.
-                let delta_z = match delta {
-                    MouseScrollDelta::LineDelta(_x, y) => *y * self.translation.z.abs() * 0.1,
-                    MouseScrollDelta::PixelDelta(p) => {
-                        p.y as f32 * self.translation.z.abs() * 0.005
-                    }
-                };
+/// Create all the GPU resources for rendering.
+/// This is also used to recover after losing the device.
+fn create_renderer<'a>(
+    window: &'a Window,
+    format: wgpu::TextureFormat,
+    camera_data: &CameraData,
+    scene: &LDrawSceneInstanced,
+    color_table: &HashMap<u32, LDrawColor>,
+    quality: &QualitySettings,
+    sample_count: u32,
+    gpu_timing: bool,
+) -> (State<'a>, ldr_wgpu::Renderer, ldr_wgpu::RenderData) {
+    let state = block_on(State::new(window, format, sample_count, gpu_timing));
 
-                // Clamp to prevent the user from zooming through the origin.
-                self.translation.z = (self.translation.z + delta_z).min(-1.0);
-            }
-            _ => (),
-        }
+    let size = window.inner_size();
+    let mut renderer = ldr_wgpu::Renderer::new(
+        &state.device,
+        size.width,
+        size.height,
+        camera_data,
+        format,
+        state.sample_count,
+        state.required_features,
+    );
+    renderer.set_quality(&state.queue, quality);
+
+    let render_data = ldr_wgpu::RenderData::new_with_progress(
+        &state.device,
+        scene,
+        color_table,
+        &quality.scene,
+        |progress| match progress {
+            // Avoid flooding the log for scenes with thousands of parts.
+            LoadProgress::ProcessingParts { processed, total }
+            | LoadProgress::CombiningParts { processed, total }
+                if processed % 1000 != 0 && processed != total => {}
+            _ => info!("{progress}"),
+        },
+    )
+    .unwrap_or_else(|e| {
+        error!("Failed to load scene: {e}");
+        std::process::exit(1)
+    });
+    info!("{:?}", render_data.scene().memory_usage());
+
+    (state, renderer, render_data)
+}
+
+fn next_render_mode(mode: RenderMode) -> RenderMode {
+    match mode {
+        RenderMode::ShadedWithEdges => RenderMode::Shaded,
+        RenderMode::Shaded => RenderMode::Wireframe,
+        RenderMode::Wireframe => RenderMode::ShadedWithEdges,
     }
 }
 
@@ -157,6 +196,57 @@ fn main() {
     let ldraw_path = &args[1];
     let path = &args[2];
 
+    // Models converted from other applications may not use LDraw's -Y up.
+    let up_axis = match args
+        .iter()
+        .position(|a| a == "--up-axis")
+        .and_then(|i| args.get(i + 1))
+        .map(|a| a.as_str())
+    {
+        Some("x") => UpAxis::X,
+        Some("z") => UpAxis::Z,
+        _ => UpAxis::Y,
+    };
+
+    let mut quality = args
+        .iter()
+        .position(|a| a == "--quality")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse::<QualityPreset>().ok())
+        .unwrap_or_default()
+        .settings();
+
+    let transition_duration = args
+        .iter()
+        .position(|a| a == "--transition-duration")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse().ok())
+        .unwrap_or(0.25);
+
+    // Color each part differently to check how a model is split into parts.
+    let debug_color_seed: Option<u64> = args
+        .iter()
+        .position(|a| a == "--debug-colors")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse().ok());
+
+    // Lower sample counts are faster on low end GPUs.
+    let sample_count = args
+        .iter()
+        .position(|a| a == "--msaa")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse().ok())
+        .unwrap_or(4);
+
+    // Limit the frame rate independent of the present mode.
+    let frame_budget = args
+        .iter()
+        .position(|a| a == "--max-fps")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse::<f64>().ok())
+        .filter(|fps| *fps > 0.0)
+        .map(|fps| std::time::Duration::from_secs_f64(1.0 / fps));
+
     let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new()
         .with_title(concat!("ldr_wgpu ", env!("CARGO_PKG_VERSION")))
@@ -166,46 +256,146 @@ fn main() {
     // Choose a format that's guaranteed to be supported.
     let format = wgpu::TextureFormat::Bgra8UnormSrgb;
 
-    let mut state = block_on(State::new(&window, format));
-
-    let mut input_state = InputState {
-        translation: vec3(0.0, -0.5, -200.0),
-        rotation_xyz: Vec3::ZERO,
-        ..Default::default()
-    };
-
-    let size = window.inner_size();
-
-    let camera_data = calculate_camera_data(
-        size.width,
-        size.height,
-        input_state.translation,
-        input_state.rotation_xyz,
-    );
+    // Subtle handheld style camera motion for recording clips.
+    let shake = args
+        .iter()
+        .any(|a| a == "--camera-shake")
+        .then(CameraShake::default);
 
-    let mut renderer = ldr_wgpu::Renderer::new(
-        &state.device,
-        size.width,
-        size.height,
-        &camera_data,
-        format,
-        state.required_features,
-    );
+    let mut camera = CameraController::new(transition_duration, shake);
+    let mut render_mode = RenderMode::default();
+    let mut previous_frame = std::time::Instant::now();
 
     // Weld vertices to take advantage of vertex caching/batching on the GPU.
     let start = std::time::Instant::now();
     let settings = GeometrySettings {
         triangulate: true,
         weld_vertices: true,
-        stud_type: StudType::HighContrast,
+        stud_type: quality.stud_type,
         ..Default::default()
     };
-    let scene = ldr_tools::load_file_instanced(path, ldraw_path, &[], &settings);
+    // Multi-part files can contain several models like the subassemblies in building instructions.
+    let submodel = args
+        .iter()
+        .position(|a| a == "--submodel")
+        .and_then(|i| args.get(i + 1));
+    let mut scene = match submodel {
+        Some(name) => ldr_wgpu::load_submodel(path, ldraw_path, name, &settings),
+        None => ldr_wgpu::load_scene_with_progress(path, ldraw_path, &settings, |progress| {
+            info!("{progress}")
+        }),
+    }
+    .unwrap_or_else(|e| {
+        error!("Failed to load scene: {e}");
+        std::process::exit(1)
+    });
     info!("Load scene: {:?}", start.elapsed());
 
-    let color_table = ldr_tools::load_color_table(ldraw_path);
+    let mut color_table = ldr_tools::load_color_table(ldraw_path);
+    // Lighter edges for dark colors and chrome use the EDGE values from LDConfig.
+    let mut edge_colors =
+        std::fs::read_to_string(std::path::Path::new(ldraw_path).join("LDConfig.ldr"))
+            .map(|text| ldr_wgpu::edge_colors(&text))
+            .unwrap_or_default();
+    // Alternate color definitions like LDCfgalt.ldr can look more realistic.
+    if let Some(ldconfig) = args
+        .iter()
+        .position(|a| a == "--ldconfig")
+        .and_then(|i| args.get(i + 1))
+    {
+        let text = std::fs::read_to_string(ldconfig).unwrap();
+        ldr_wgpu::apply_color_config(&mut color_table, &text);
+        edge_colors.extend(ldr_wgpu::edge_colors(&text));
+    }
+    // Models can define their own custom colors with !COLOUR lines.
+    match std::fs::read_to_string(path) {
+        Ok(text) => {
+            ldr_wgpu::apply_inline_colors(&mut color_table, &text);
+            edge_colors.extend(ldr_wgpu::edge_colors(&text));
+        }
+        Err(e) => error!("Failed to read inline colors: {e}"),
+    }
+    if let Some(seed) = debug_color_seed {
+        ldr_wgpu::apply_debug_part_colors(&mut scene, &mut color_table, seed);
+    }
+    quality.scene.edge_colors = edge_colors;
+    // Reuse processed part geometry from previous runs to speed up loading.
+    quality.scene.cache_dir = args
+        .iter()
+        .position(|a| a == "--cache")
+        .and_then(|i| args.get(i + 1))
+        .map(Into::into);
+    quality.scene.deduplicate_instances = args.iter().any(|a| a == "--deduplicate");
+
+    // Periodically log the GPU time for each pass to diagnose performance issues.
+    let gpu_timing = args.iter().any(|a| a == "--gpu-timing");
+
+    let camera_data = camera.camera_data(window.inner_size(), up_axis);
+    let (state, mut renderer, render_data) = create_renderer(
+        &window,
+        format,
+        &camera_data,
+        &scene,
+        &color_table,
+        &quality,
+        sample_count,
+        gpu_timing,
+    );
+
+    // Catch shadows on the ground below the model for presentation renders.
+    let ground_plane = args.iter().any(|a| a == "--ground-shadow").then(|| {
+        let (min, max) = render_data.scene().bounds();
+        GroundPlane {
+            up_axis,
+            // LDraw uses -Y up, so the lowest point of the model has the largest Y.
+            height: match up_axis {
+                UpAxis::Y => max.y,
+                UpAxis::Z => min.z,
+                UpAxis::X => min.x,
+            },
+            ..Default::default()
+        }
+    });
+    renderer.set_ground_plane(&state.queue, ground_plane);
+
+    let outline = args
+        .iter()
+        .any(|a| a == "--outline")
+        .then(OutlineSettings::default);
+    renderer.set_outline(&state.queue, outline);
+
+    let tonemap = args
+        .iter()
+        .position(|a| a == "--tonemap")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse::<Tonemap>().ok())
+        .unwrap_or_default();
+    renderer.set_tonemap(&state.queue, tonemap);
+
+    // Refine the image over several frames whenever the camera stops moving.
+    let accumulation = args
+        .iter()
+        .position(|a| a == "--accumulate")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse().ok());
+    renderer.set_accumulation(&state.device, accumulation);
 
-    let mut render_data = ldr_wgpu::RenderData::new(&state.device, &scene, &color_table);
+    renderer.set_gpu_timing(&state.device, &state.queue, gpu_timing);
+    let mut frame_count = 0u64;
+
+    // Draw everything to check if missing parts are a culling bug or a geometry bug.
+    let mut culling_enabled = !args.iter().any(|a| a == "--no-culling");
+    renderer.set_culling_enabled(culling_enabled);
+
+    // Frame the whole model since the default camera may not fit large or small models.
+    // Orbit around the center of the model instead of the origin.
+    let size = window.inner_size();
+    let bounds = render_data.scene().bounds();
+    camera.frame_scene(bounds, size);
+    renderer.update_camera(&state.queue, &camera.camera_data(size, up_axis));
+
+    // Keep the GPU resources optional to drop them before recreating them.
+    let mut gpu = Some((state, renderer, render_data));
 
     event_loop
         .run(|event, target| match event {
@@ -215,62 +405,130 @@ fn main() {
             } if window_id == window.id() => match event {
                 WindowEvent::CloseRequested => target.exit(),
                 WindowEvent::Resized(size) => {
-                    state.config.width = size.width;
-                    state.config.height = size.height;
-                    state.surface.configure(&state.device, &state.config);
-
-                    renderer.resize(&state.device, size.width, size.height, format);
-
-                    let camera_data = calculate_camera_data(
-                        size.width,
-                        size.height,
-                        input_state.translation,
-                        input_state.rotation_xyz,
-                    );
+                    let (state, renderer, _) = gpu.as_mut().unwrap();
+                    state.resize(renderer, *size, format);
+
+                    let camera_data = camera.camera_data(*size, up_axis);
+                    renderer.update_camera(&state.queue, &camera_data);
+
+                    window.request_redraw();
+                }
+                WindowEvent::ScaleFactorChanged { .. } => {
+                    // Moving between monitors with different DPI changes the physical size.
+                    // Not all platforms send a resize event afterwards.
+                    let size = window.inner_size();
+                    let (state, renderer, _) = gpu.as_mut().unwrap();
+                    state.resize(renderer, size, format);
+
+                    let camera_data = camera.camera_data(size, up_axis);
                     renderer.update_camera(&state.queue, &camera_data);
 
                     window.request_redraw();
                 }
-                WindowEvent::ScaleFactorChanged { .. } => {}
                 WindowEvent::RedrawRequested => {
+                    let (state, _, _) = gpu.as_ref().unwrap();
+                    if state.device_lost.load(Ordering::Relaxed) {
+                        info!("Recreating device and renderer");
+                        // Release the old surface and buffers before creating new ones.
+                        gpu = None;
+                        let camera_data = camera.camera_data(window.inner_size(), up_axis);
+                        let (state, renderer, _) = gpu.insert(create_renderer(
+                            &window,
+                            format,
+                            &camera_data,
+                            &scene,
+                            &color_table,
+                            &quality,
+                            sample_count,
+                            gpu_timing,
+                        ));
+                        renderer.set_render_mode(render_mode);
+                        renderer.set_ground_plane(&state.queue, ground_plane);
+                        renderer.set_outline(&state.queue, outline);
+                        renderer.set_tonemap(&state.queue, tonemap);
+                        renderer.set_accumulation(&state.device, accumulation);
+                        renderer.set_gpu_timing(&state.device, &state.queue, gpu_timing);
+                        renderer.set_culling_enabled(culling_enabled);
+                    }
+                    let (state, renderer, render_data) = gpu.as_mut().unwrap();
+
+                    // Animate view changes using the elapsed time since the last frame.
+                    let delta = previous_frame.elapsed().as_secs_f32();
+                    previous_frame = std::time::Instant::now();
+                    if camera.update(delta) {
+                        let size = window.inner_size();
+                        let camera_data = camera.camera_data(size, up_axis);
+                        renderer.update_camera(&state.queue, &camera_data);
+                    }
+
                     match state.surface.get_current_texture() {
                         Ok(output) => {
                             let output_view = output
                                 .texture
                                 .create_view(&wgpu::TextureViewDescriptor::default());
 
-                            renderer.render(
-                                &state.device,
-                                &state.queue,
-                                &mut render_data,
-                                &output_view,
-                            );
+                            renderer.render(&state.device, &state.queue, render_data, &output_view);
                             output.present();
+
+                            frame_count += 1;
+                            if gpu_timing && frame_count % 120 == 0 {
+                                let mut timings: Vec<_> =
+                                    renderer.last_frame_timings().into_iter().collect();
+                                timings.sort();
+                                info!("GPU timings: {timings:?}");
+                            }
                         }
                         Err(wgpu::SurfaceError::Lost) => {
                             let size = window.inner_size();
                             renderer.resize(&state.device, size.width, size.height, format)
                         }
-                        Err(wgpu::SurfaceError::OutOfMemory) => target.exit(),
+                        Err(wgpu::SurfaceError::OutOfMemory) => {
+                            // Try to free memory by recreating everything on the next frame.
+                            error!("Out of memory");
+                            state.device_lost.store(true, Ordering::Relaxed);
+                        }
                         Err(e) => error!("{e:?}"),
                     }
-                    window.request_redraw();
+
+                    // Wait for the remaining frame time without blocking other events.
+                    match frame_budget {
+                        Some(frame_budget) => target.set_control_flow(ControlFlow::WaitUntil(
+                            previous_frame + frame_budget,
+                        )),
+                        None => window.request_redraw(),
+                    }
                 }
                 _ => {
                     let size = window.inner_size();
-                    input_state.handle_input(event, size);
-
-                    let camera_data = calculate_camera_data(
-                        size.width,
-                        size.height,
-                        input_state.translation,
-                        input_state.rotation_xyz,
-                    );
+                    camera.handle_window_event(event, size);
+
+                    let (state, renderer, _) = gpu.as_mut().unwrap();
+                    if let WindowEvent::KeyboardInput { event, .. } = event {
+                        if event.state == ElementState::Pressed
+                            && event.logical_key == Key::Character("w".into())
+                        {
+                            render_mode = next_render_mode(render_mode);
+                            renderer.set_render_mode(render_mode);
+                        }
+                        if event.state == ElementState::Pressed
+                            && event.logical_key == Key::Character("c".into())
+                        {
+                            culling_enabled = !culling_enabled;
+                            renderer.set_culling_enabled(culling_enabled);
+                            info!("Culling enabled: {culling_enabled}");
+                        }
+                    }
+
+                    let camera_data = camera.camera_data(size, up_axis);
                     renderer.update_camera(&state.queue, &camera_data);
 
                     window.request_redraw();
                 }
             },
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                target.set_control_flow(ControlFlow::Wait);
+                window.request_redraw();
+            }
             _ => (),
         })
         .unwrap();
@@ -18,6 +18,14 @@ fn main() {
     );
     write_shader("src/shader/scan.wgsl", format!("{out_dir}/scan.rs"));
     write_shader("src/shader/scan_add.wgsl", format!("{out_dir}/scan_add.rs"));
+    write_shader("src/shader/bloom.wgsl", format!("{out_dir}/bloom.rs"));
+    write_shader("src/shader/blit.wgsl", format!("{out_dir}/blit.rs"));
+    write_shader(
+        "src/shader/accumulate.wgsl",
+        format!("{out_dir}/accumulate.rs"),
+    );
+    write_shader("src/shader/grid.wgsl", format!("{out_dir}/grid.rs"));
+    write_shader("src/shader/edge.wgsl", format!("{out_dir}/edge.rs"));
 }
 
 fn write_shader(wgsl_path: &str, output_path: String) {
@@ -0,0 +1,217 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bytemuck::Pod;
+
+use crate::{color::hash, geometry::IndexedVertexData, scene::SceneSettings};
+
+const MAGIC: &[u8; 4] = b"LDRC";
+
+// Increment this when changing the vertex formats or geometry processing.
+// Files from older versions are ignored and eventually overwritten.
+const VERSION: u32 = 1;
+
+// Parts with identical geometry have the same key and can be saved at the same time.
+static TEMP_FILE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// A key for the processed geometry that changes if the input part or any settings change.
+///
+/// Hashing the geometry instead of the part name avoids stale data
+/// when updating the LDraw library or changing the stud type.
+pub fn cache_key(geometry: &ldr_tools::LDrawGeometry, settings: &SceneSettings) -> u64 {
+    let settings_bytes = [
+        settings.optimize_vertex_cache as u64,
+        settings
+            .optimize_overdraw
+            .map(f32::to_bits)
+            .unwrap_or(u32::MAX) as u64,
+        settings.max_triangles_per_part.unwrap_or(usize::MAX) as u64,
+        settings.smoothing_angle.to_bits() as u64,
+    ];
+
+    let inputs: [&[u8]; 5] = [
+        bytemuck::cast_slice(&settings_bytes),
+        bytemuck::cast_slice(&geometry.vertices),
+        bytemuck::cast_slice(&geometry.vertex_indices),
+        bytemuck::cast_slice(&geometry.face_colors),
+        bytemuck::cast_slice(&geometry.edge_line_indices),
+    ];
+    inputs
+        .iter()
+        .fold(VERSION as u64, |seed, bytes| hash(bytes, seed))
+}
+
+/// Load the processed geometry for `key` from `dir`.
+/// Returns `None` if the file is missing, truncated, or from a different version.
+pub fn load(dir: &Path, key: u64) -> Option<IndexedVertexData> {
+    let bytes = std::fs::read(cache_path(dir, key)).ok()?;
+    from_bytes(&bytes)
+}
+
+/// Save the processed geometry for `key` to `dir`, creating the folder if needed.
+pub fn save(dir: &Path, key: u64, vertex_data: &IndexedVertexData) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+
+    // Write to a temporary file first so other processes never read a partial file.
+    // Each call uses a unique file to avoid interleaving writes from other threads.
+    let path = cache_path(dir, key);
+    let count = TEMP_FILE_COUNT.fetch_add(1, Ordering::Relaxed);
+    let temp_path = path.with_extension(format!("{}.{count}.tmp", std::process::id()));
+    std::fs::write(&temp_path, to_bytes(vertex_data))?;
+    std::fs::rename(temp_path, path)
+}
+
+fn cache_path(dir: &Path, key: u64) -> std::path::PathBuf {
+    dir.join(format!("{key:016x}.bin"))
+}
+
+fn to_bytes(vertex_data: &IndexedVertexData) -> Vec<u8> {
+    let counts = [
+        vertex_data.vertices.len() as u32,
+        vertex_data.vertex_indices.len() as u32,
+        vertex_data.edge_vertices.len() as u32,
+        vertex_data.edge_indices.len() as u32,
+    ];
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(bytemuck::cast_slice(&counts));
+    bytes.extend_from_slice(bytemuck::bytes_of(&vertex_data.bounds));
+    bytes.extend_from_slice(bytemuck::cast_slice(&vertex_data.vertices));
+    bytes.extend_from_slice(bytemuck::cast_slice(&vertex_data.vertex_indices));
+    bytes.extend_from_slice(bytemuck::cast_slice(&vertex_data.edge_vertices));
+    bytes.extend_from_slice(bytemuck::cast_slice(&vertex_data.edge_indices));
+    bytes
+}
+
+fn from_bytes(bytes: &[u8]) -> Option<IndexedVertexData> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC || reader.read::<u32>()? != VERSION {
+        return None;
+    }
+
+    let [vertex_count, index_count, edge_vertex_count, edge_index_count] =
+        reader.read::<[u32; 4]>()?;
+    let vertex_data = IndexedVertexData {
+        bounds: reader.read()?,
+        vertices: reader.read_vec(vertex_count as usize)?,
+        vertex_indices: reader.read_vec(index_count as usize)?,
+        edge_vertices: reader.read_vec(edge_vertex_count as usize)?,
+        edge_indices: reader.read_vec(edge_index_count as usize)?,
+    };
+
+    // Trailing data means the file doesn't match the expected layout.
+    reader.bytes.is_empty().then_some(vertex_data)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if count > self.bytes.len() {
+            return None;
+        }
+        let (taken, remaining) = self.bytes.split_at(count);
+        self.bytes = remaining;
+        Some(taken)
+    }
+
+    fn read<T: Pod>(&mut self) -> Option<T> {
+        // The file contents aren't guaranteed to be aligned for T.
+        self.take(std::mem::size_of::<T>())
+            .map(bytemuck::pod_read_unaligned)
+    }
+
+    fn read_vec<T: Pod>(&mut self, count: usize) -> Option<Vec<T>> {
+        let bytes = self.take(count.checked_mul(std::mem::size_of::<T>())?)?;
+        let mut values = vec![T::zeroed(); count];
+        bytemuck::cast_slice_mut(&mut values).copy_from_slice(bytes);
+        Some(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glam::{vec2, vec3, vec4, Vec3};
+
+    fn vertex_data() -> IndexedVertexData {
+        IndexedVertexData {
+            vertices: vec![crate::shader::model::VertexInput {
+                position: vec3(1.0, 2.0, 3.0),
+                normal: vec4(0.0, 1.0, 0.0, 0.0),
+                color: 0xFF0000FF,
+            }],
+            vertex_indices: vec![0, 0, 0],
+            edge_vertices: vec![crate::shader::edge::VertexInput {
+                start: Vec3::ZERO,
+                end: Vec3::X,
+                corner: vec2(1.0, -1.0),
+                color: 0xFF000000,
+            }],
+            edge_indices: vec![0, 0, 0, 0, 0, 0],
+            bounds: crate::shader::culling::InstanceBounds {
+                sphere: vec4(1.0, 2.0, 3.0, 0.5),
+                min_xyz: vec4(0.5, 1.5, 2.5, 0.0),
+                max_xyz: vec4(1.5, 2.5, 3.5, 0.0),
+            },
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let expected = vertex_data();
+        let actual = from_bytes(&to_bytes(&expected)).unwrap();
+
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&expected.vertices),
+            bytemuck::cast_slice::<_, u8>(&actual.vertices)
+        );
+        assert_eq!(expected.vertex_indices, actual.vertex_indices);
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&expected.edge_vertices),
+            bytemuck::cast_slice::<_, u8>(&actual.edge_vertices)
+        );
+        assert_eq!(expected.edge_indices, actual.edge_indices);
+        assert_eq!(
+            bytemuck::bytes_of(&expected.bounds),
+            bytemuck::bytes_of(&actual.bounds)
+        );
+    }
+
+    #[test]
+    fn bytes_truncated() {
+        let bytes = to_bytes(&vertex_data());
+        assert!(from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(from_bytes(&[]).is_none());
+    }
+
+    #[test]
+    fn save_same_key_in_parallel() {
+        let dir = std::env::temp_dir().join(format!("ldr_wgpu_cache_{}", std::process::id()));
+        let vertex_data = vertex_data();
+
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| save(&dir, 1, &vertex_data).unwrap());
+            }
+        });
+        let loaded = load(&dir, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vertex_data.vertex_indices, loaded.unwrap().vertex_indices);
+    }
+
+    #[test]
+    fn bytes_other_version() {
+        let mut bytes = to_bytes(&vertex_data());
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(from_bytes(&bytes).is_none());
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+
+use ldr_tools::{LDrawColor, LDrawSceneInstanced};
+
+/// Replace colors with the values from an alternate LDConfig file like `LDCfgalt.ldr`.
+///
+/// Only the RGBA values for codes already in `color_table` are updated.
+/// The `ldconfig` is the text of the file and uses the same `!COLOUR` syntax as `LDConfig.ldr`.
+pub fn apply_color_config(color_table: &mut HashMap<u32, LDrawColor>, ldconfig: &str) {
+    for colour in ldconfig.lines().filter_map(parse_colour) {
+        if let Some(color) = color_table.get_mut(&colour.code) {
+            color.rgba_linear = colour.rgba_linear;
+        }
+    }
+}
+
+/// Add colors defined inline with `0 !COLOUR` lines in a model file like an `.ldr` or `.mpd`.
+///
+/// Unlike [apply_color_config], codes not already in `color_table` are also added.
+/// Inline definitions take priority over the LDConfig colors with the same code.
+/// Only the text of the model file itself is searched and not any referenced files.
+pub fn apply_inline_colors(color_table: &mut HashMap<u32, LDrawColor>, model: &str) {
+    for colour in model.lines().filter_map(parse_colour) {
+        if let Some(color) = color_table.get_mut(&colour.code) {
+            color.rgba_linear = colour.rgba_linear;
+        } else if let Some(template) = color_template(color_table) {
+            // Use the remaining fields like the finish from an existing color.
+            let color = LDrawColor {
+                name: colour.name.to_string(),
+                rgba_linear: colour.rgba_linear,
+                ..template.clone()
+            };
+            color_table.insert(colour.code, color);
+        }
+    }
+}
+
+/// The color to copy the remaining fields from for new inline colors.
+/// This uses a fixed code so new colors don't depend on the table's iteration order.
+fn color_template(color_table: &HashMap<u32, LDrawColor>) -> Option<&LDrawColor> {
+    color_table.get(&CURRENT_COLOR).or_else(|| {
+        color_table
+            .iter()
+            .min_by_key(|(code, _)| **code)
+            .map(|(_, color)| color)
+    })
+}
+
+/// A color code after replacing the special codes that depend on the parent color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorCode {
+    /// A code in the color table.
+    Code(u32),
+    /// The edge color for the code from [edge_colors].
+    Edge(u32),
+}
+
+// https://www.ldraw.org/article/547.html
+const CURRENT_COLOR: u32 = 16;
+const EDGE_COLOR: u32 = 24;
+
+/// Replace code 16 with `current_color` and code 24 with the edge color of `current_color`.
+///
+/// Nested parts should use the resolved color of their parent subfile reference as `current_color`.
+pub fn resolve_color(face_color: u32, current_color: ColorCode) -> ColorCode {
+    match face_color {
+        CURRENT_COLOR => current_color,
+        // The edge color's edge color isn't defined, so keep the same edge color.
+        EDGE_COLOR => match current_color {
+            ColorCode::Code(code) | ColorCode::Edge(code) => ColorCode::Edge(code),
+        },
+        code => ColorCode::Code(code),
+    }
+}
+
+/// The linear RGBA for `color` using white for missing colors and black for missing edge colors.
+pub fn color_rgba(
+    color: ColorCode,
+    color_table: &HashMap<u32, LDrawColor>,
+    edge_colors: &HashMap<u32, [f32; 4]>,
+) -> [f32; 4] {
+    match color {
+        ColorCode::Code(code) => resolved_color(code, color_table).rgba_linear,
+        ColorCode::Edge(code) => edge_colors
+            .get(&code)
+            .copied()
+            .unwrap_or([0.0, 0.0, 0.0, 1.0]),
+    }
+}
+
+/// An LDraw color code and its linear RGBA color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolvedColor {
+    pub code: u32,
+    pub rgba_linear: [f32; 4],
+}
+
+/// The color for `code` or white if `code` isn't in `color_table`.
+pub fn resolved_color(code: u32, color_table: &HashMap<u32, LDrawColor>) -> ResolvedColor {
+    ResolvedColor {
+        code,
+        rgba_linear: color_table
+            .get(&code)
+            .map(|c| c.rgba_linear)
+            .unwrap_or([1.0; 4]),
+    }
+}
+
+/// Edge line colors by color code from the `EDGE` values in an LDConfig file like `LDConfig.ldr`.
+///
+/// Edges referencing another color code use that code's `VALUE` from the same file.
+/// Colors without an `EDGE` are omitted and should be drawn with black edges.
+pub fn edge_colors(ldconfig: &str) -> HashMap<u32, [f32; 4]> {
+    let colours: Vec<_> = ldconfig.lines().filter_map(parse_colour).collect();
+    let values: HashMap<_, _> = colours.iter().map(|c| (c.code, c.rgba_linear)).collect();
+
+    colours
+        .iter()
+        .filter_map(|c| {
+            let rgba = match c.edge? {
+                Edge::Rgb([r, g, b]) => [r, g, b, 1.0],
+                Edge::Code(code) => *values.get(&code)?,
+            };
+            Some((c.code, rgba))
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+struct Colour<'a> {
+    name: &'a str,
+    code: u32,
+    rgba_linear: [f32; 4],
+    edge: Option<Edge>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Edge {
+    Rgb([f32; 3]),
+    /// The edge uses the `VALUE` of another color code.
+    Code(u32),
+}
+
+// https://www.ldraw.org/article/299.html
+fn parse_colour(line: &str) -> Option<Colour<'_>> {
+    let mut words = line.split_whitespace();
+    if words.next()? != "0" || words.next()? != "!COLOUR" {
+        return None;
+    }
+    let name = words.next()?;
+
+    let mut code = None;
+    let mut value = None;
+    let mut edge = None;
+    let mut alpha = 255u8;
+    while let Some(word) = words.next() {
+        match word {
+            "CODE" => code = words.next()?.parse().ok(),
+            "VALUE" => value = parse_hex_rgb(words.next()?),
+            "EDGE" => edge = parse_edge(words.next()?),
+            "ALPHA" => alpha = words.next()?.parse().ok()?,
+            // Material parameters reuse keywords like VALUE and ALPHA.
+            "MATERIAL" => break,
+            _ => (),
+        }
+    }
+
+    let [r, g, b] = value?.map(srgb_to_linear);
+    Some(Colour {
+        name,
+        code: code?,
+        rgba_linear: [r, g, b, alpha as f32 / 255.0],
+        edge,
+    })
+}
+
+fn parse_edge(word: &str) -> Option<Edge> {
+    if word.starts_with('#') {
+        parse_hex_rgb(word).map(|rgb| Edge::Rgb(rgb.map(srgb_to_linear)))
+    } else {
+        word.parse().ok().map(Edge::Code)
+    }
+}
+
+fn parse_hex_rgb(hex: &str) -> Option<[f32; 3]> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| {
+        u8::from_str_radix(hex.get(i..i + 2)?, 16)
+            .ok()
+            .map(|c| c as f32 / 255.0)
+    };
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// A stable pseudorandom RGBA color for debug visualizations like coloring by part or instance.
+///
+/// The same `key` and `seed` always produce the same color across runs and platforms.
+/// Changing the `seed` reassigns all colors, which can help distinguish similar adjacent colors.
+pub fn debug_color(key: &[u8], seed: u64) -> [f32; 4] {
+    let hash = hash(key, seed);
+
+    // Use separate bits of the hash for each component.
+    // Limit saturation and value to avoid colors that are too dark or washed out.
+    let hue = (hash & 0xFFFF) as f32 / 65536.0;
+    let saturation = 0.5 + 0.4 * ((hash >> 16) & 0xFF) as f32 / 255.0;
+    let value = 0.7 + 0.3 * ((hash >> 24) & 0xFF) as f32 / 255.0;
+
+    let [r, g, b] = hsv_to_rgb(hue, saturation, value);
+    [r, g, b, 1.0]
+}
+
+/// Color each part in `scene` with [debug_color] for its name instead of its LDraw color.
+///
+/// This adds a new code to `color_table` for each part instead of changing the existing colors.
+/// Faces with a fixed color instead of code 16 like printed details keep their color.
+pub fn apply_debug_part_colors(
+    scene: &mut LDrawSceneInstanced,
+    color_table: &mut HashMap<u32, LDrawColor>,
+    seed: u64,
+) {
+    // Use the remaining fields like the finish from the current color.
+    let Some(template) = color_table.get(&CURRENT_COLOR).cloned() else {
+        return;
+    };
+
+    // Sort the names to assign the same codes across runs.
+    let mut names: Vec<_> = scene
+        .geometry_world_transforms
+        .keys()
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    names.dedup();
+
+    let first_code = color_table.keys().max().map_or(0, |code| code + 1);
+    let mut codes = HashMap::new();
+    for (code, name) in (first_code..).zip(names) {
+        let color = LDrawColor {
+            name: name.clone(),
+            rgba_linear: debug_color(name.as_bytes(), seed),
+            ..template.clone()
+        };
+        color_table.insert(code, color);
+        codes.insert(name, code);
+    }
+
+    // Combine the instances of each part since every color now uses the same code.
+    let transforms = std::mem::take(&mut scene.geometry_world_transforms);
+    for ((name, _), part_transforms) in transforms {
+        let code = codes[&name];
+        scene
+            .geometry_world_transforms
+            .entry((name, code))
+            .or_default()
+            .extend(part_transforms);
+    }
+}
+
+pub(crate) fn hash(key: &[u8], seed: u64) -> u64 {
+    // FNV-1a is simple and doesn't change between Rust versions like std's default hasher.
+    // https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = OFFSET;
+    for byte in seed.to_le_bytes().iter().chain(key) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+
+    // Mix the bits so that similar keys still produce very different colors.
+    // https://xoshiro.di.unimi.it/splitmix64.c
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [f32; 3] {
+    // https://en.wikipedia.org/wiki/HSL_and_HSV#HSV_to_RGB_alternative
+    let f = |n: f32| {
+        let k = (n + hue * 6.0) % 6.0;
+        value - value * saturation * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+    [f(5.0), f(3.0), f(1.0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_colour_opaque() {
+        assert_eq!(
+            Some(Colour {
+                name: "White",
+                code: 15,
+                rgba_linear: [1.0, 1.0, 1.0, 1.0],
+                edge: Some(Edge::Rgb([179.0 / 255.0; 3].map(srgb_to_linear)))
+            }),
+            parse_colour("0 !COLOUR White CODE 15 VALUE #FFFFFF EDGE #B3B3B3")
+        );
+    }
+
+    #[test]
+    fn parse_colour_alpha_material() {
+        assert_eq!(
+            Some(Colour {
+                name: "Glitter",
+                code: 114,
+                rgba_linear: [1.0, 0.0, 0.0, 128.0 / 255.0],
+                edge: Some(Edge::Rgb([0.0; 3]))
+            }),
+            parse_colour(
+                "0 !COLOUR Glitter CODE 114 VALUE #FF0000 EDGE #000000 ALPHA 128 MATERIAL GLITTER VALUE #FFFFFF FRACTION 0.2"
+            )
+        );
+    }
+
+    #[test]
+    fn parse_colour_other_lines() {
+        assert_eq!(None, parse_colour("0 // LDraw Colour Configuration File"));
+        assert_eq!(None, parse_colour("0 !COLOUR Missing_Code VALUE #FFFFFF"));
+        assert_eq!(None, parse_colour(""));
+    }
+
+    #[test]
+    fn parse_colour_inline_custom_code() {
+        // Inline definitions in models often use codes outside the standard range.
+        assert_eq!(
+            Some(Colour {
+                name: "Custom_Black",
+                code: 10000,
+                rgba_linear: [0.0, 0.0, 0.0, 1.0],
+                edge: Some(Edge::Rgb([89.0 / 255.0; 3].map(srgb_to_linear)))
+            }),
+            parse_colour("  0  !COLOUR  Custom_Black  CODE 10000  VALUE #000000  EDGE #595959")
+        );
+    }
+
+    #[test]
+    fn edge_colors_hex_and_code() {
+        let ldconfig = "0 !COLOUR Black CODE 0 VALUE #000000 EDGE #FFFFFF\n\
+            0 !COLOUR Chrome_Silver CODE 383 VALUE #E0E0E0 EDGE 0 CHROME\n\
+            0 !COLOUR Main_Colour CODE 16 VALUE #FFFF80\n\
+            0 !COLOUR Missing CODE 500 VALUE #FFFFFF EDGE 1";
+
+        let edge_colors = edge_colors(ldconfig);
+        assert_eq!(2, edge_colors.len());
+        assert_eq!(Some(&[1.0, 1.0, 1.0, 1.0]), edge_colors.get(&0));
+        assert_eq!(Some(&[0.0, 0.0, 0.0, 1.0]), edge_colors.get(&383));
+    }
+
+    #[test]
+    fn resolve_color_inherit_chain() {
+        // A part in red referencing a subpart in 16 referencing a primitive in 16.
+        let subpart = resolve_color(16, ColorCode::Code(4));
+        assert_eq!(ColorCode::Code(4), resolve_color(16, subpart));
+        assert_eq!(ColorCode::Edge(4), resolve_color(24, subpart));
+        assert_eq!(ColorCode::Code(1), resolve_color(1, subpart));
+
+        // Explicit colors stop inheriting for any nested references.
+        let subpart = resolve_color(15, ColorCode::Code(4));
+        assert_eq!(ColorCode::Code(15), resolve_color(16, subpart));
+    }
+
+    #[test]
+    fn resolve_color_nested_edge() {
+        let edge = resolve_color(24, ColorCode::Code(4));
+        assert_eq!(ColorCode::Edge(4), resolve_color(16, edge));
+        assert_eq!(ColorCode::Edge(4), resolve_color(24, edge));
+    }
+
+    #[test]
+    fn color_rgba_missing() {
+        let edge_colors = [(4, [0.5, 0.0, 0.0, 1.0])].into();
+        assert_eq!(
+            [1.0; 4],
+            color_rgba(ColorCode::Code(4), &HashMap::new(), &edge_colors)
+        );
+        assert_eq!(
+            [0.5, 0.0, 0.0, 1.0],
+            color_rgba(ColorCode::Edge(4), &HashMap::new(), &edge_colors)
+        );
+        assert_eq!(
+            [0.0, 0.0, 0.0, 1.0],
+            color_rgba(ColorCode::Edge(1), &HashMap::new(), &edge_colors)
+        );
+    }
+
+    #[test]
+    fn debug_color_deterministic() {
+        assert_eq!(debug_color(b"3001.dat", 0), debug_color(b"3001.dat", 0));
+    }
+
+    #[test]
+    fn debug_color_seed() {
+        assert_ne!(debug_color(b"3001.dat", 0), debug_color(b"3001.dat", 1));
+    }
+
+    #[test]
+    fn debug_color_similar_keys() {
+        assert_ne!(debug_color(b"3001.dat", 0), debug_color(b"3002.dat", 0));
+    }
+
+    #[test]
+    fn debug_color_range() {
+        for i in 0u32..100 {
+            let color = debug_color(&i.to_le_bytes(), 7);
+            assert!(color.iter().all(|c| (0.0..=1.0).contains(c)));
+            assert_eq!(1.0, color[3]);
+        }
+    }
+
+    #[test]
+    fn hsv_to_rgb_primaries() {
+        assert_eq!([1.0, 0.0, 0.0], hsv_to_rgb(0.0, 1.0, 1.0));
+        assert_eq!([0.0, 1.0, 0.0], hsv_to_rgb(1.0 / 3.0, 1.0, 1.0));
+        assert_eq!([0.0, 0.0, 1.0], hsv_to_rgb(2.0 / 3.0, 1.0, 1.0));
+        assert_eq!([0.5, 0.5, 0.5], hsv_to_rgb(0.25, 0.0, 0.5));
+    }
+}
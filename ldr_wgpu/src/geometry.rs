@@ -1,26 +1,41 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::HashMap;
 
-use glam::Vec3;
+use glam::{vec2, Vec3};
 use ldr_tools::LDrawColor;
 
-use crate::normal::triangle_face_vertex_normals;
+use crate::{
+    color::{color_rgba, resolve_color, ColorCode},
+    normal::{index_normals, triangle_face_vertex_normals},
+};
 
+/// Indexed triangle and edge geometry for a single part.
 #[derive(Clone)]
 pub struct IndexedVertexData {
-    pub vertices: Vec<crate::shader::model::VertexInput>,
-    pub vertex_indices: Vec<u32>,
-    pub edge_indices: Vec<u32>,
-    pub bounds: crate::shader::culling::InstanceBounds,
+    pub(crate) vertices: Vec<crate::shader::model::VertexInput>,
+    pub(crate) vertex_indices: Vec<u32>,
+    /// Vertices for the edge lines with a separate index space from the triangles.
+    pub(crate) edge_vertices: Vec<crate::shader::edge::VertexInput>,
+    /// Triangle list indices into [Self::edge_vertices] with two triangles per edge.
+    pub(crate) edge_indices: Vec<u32>,
+    pub(crate) bounds: crate::shader::culling::InstanceBounds,
 }
 
 impl IndexedVertexData {
-    pub fn from_geometry(geometry: &ldr_tools::LDrawGeometry) -> Self {
-        // TODO: Edge colors?
+    /// Process `geometry` with adjacent faces smoothed together if the angle between them
+    /// is less than `smoothing_angle` in radians like [crate::SceneSettings::smoothing_angle].
+    pub fn from_geometry(geometry: &ldr_tools::LDrawGeometry, smoothing_angle: f32) -> Self {
         // TODO: missing color codes?
         // TODO: publicly expose color handling logic in ldr_tools.
         // TODO: handle the case where the face color list is empty?
-        let (filtered_adjacent_faces, face_vertex_normals) =
-            triangle_face_vertex_normals(&geometry.vertices, &geometry.vertex_indices);
+        let (_, face_vertex_normals) = triangle_face_vertex_normals(
+            &geometry.vertices,
+            &geometry.vertex_indices,
+            smoothing_angle,
+        );
+
+        // Different sets of adjacent faces can still produce the same normal.
+        // Indexing the normals avoids duplicating these vertices.
+        let (normals, normal_indices) = index_normals(&face_vertex_normals);
 
         // TODO: make this its own function?
         // Reindex the geometry now that all attributes have been calculated.
@@ -28,7 +43,6 @@ impl IndexedVertexData {
 
         let mut vertices = Vec::new();
         let mut vertex_indices = Vec::new();
-        let mut edge_indices = Vec::new();
 
         for (i, vertex_index) in geometry.vertex_indices.iter().enumerate() {
             // Assume faces are already triangulated.
@@ -39,9 +53,6 @@ impl IndexedVertexData {
                 .get(face_index)
                 .unwrap_or(&geometry.face_colors[0]);
 
-            // Each normal is uniquely determined by the set of filtered adjacent faces
-            let adjacent_faces = filtered_adjacent_faces[i].clone();
-
             let vertex_position = geometry.vertices[*vertex_index as usize];
 
             // Store separate indices for position and color.
@@ -50,11 +61,11 @@ impl IndexedVertexData {
             // TODO: Pass this as a parameter?
             let face_vertex_key = VertexKey {
                 position_index: *vertex_index,
-                adjacent_faces,
+                normal_index: normal_indices[i],
                 color: *face_color,
             };
 
-            let vertex_normal = face_vertex_normals[i];
+            let vertex_normal = normals[normal_indices[i] as usize];
 
             // Initially insert colors using the LDraw color code.
             // This will later be replaced by an RGBA color.
@@ -70,56 +81,110 @@ impl IndexedVertexData {
             vertex_indices.push(new_index);
         }
 
-        // The vertices have been reindexed, so use the mapping from earlier.
-        // This ensures the sharp edge indices reference the correct vertices.
-        // TODO: This color specific indexing probably won't work for normals.
-        // TODO: Just create a separate vertex buffer without normals.
-        edge_indices.extend(geometry.edge_line_indices.iter().flat_map(|[v0, v1]| {
-            // Assume all black edges for now.
-            let i0 = insert_vertex(
-                VertexKey {
-                    position_index: *v0,
-                    adjacent_faces: BTreeSet::new(),
-                    color: 0,
-                },
-                geometry.vertices[*v0 as usize],
-                Vec3::ZERO,
-                0xFF000000,
-                &mut vertex_cache,
-                &mut vertices,
-            );
-            let i1 = insert_vertex(
-                VertexKey {
-                    position_index: *v1,
-                    adjacent_faces: BTreeSet::new(),
-                    color: 0,
-                },
-                geometry.vertices[*v1 as usize],
-                Vec3::ZERO,
-                0xFF000000,
-                &mut vertex_cache,
-                &mut vertices,
-            );
-
-            [i0, i1]
-        }));
+        // Edges don't use normals or face colors, so store them separately from the triangles.
+        let (edge_vertices, edge_indices) = edge_vertex_data(geometry);
 
         let bounds = calculate_bounds(&geometry.vertices);
 
         Self {
             vertices,
             vertex_indices,
+            edge_vertices,
             edge_indices,
             bounds,
         }
     }
 
-    pub fn replace_colors(&mut self, current_color: u32, color_table: &HashMap<u32, LDrawColor>) {
+    /// Convert face color codes to RGBA colors and color edges using `current_color`.
+    ///
+    /// Edge lines use the color from `edge_colors` for `current_color` or black if not present.
+    pub fn replace_colors(
+        &mut self,
+        current_color: u32,
+        color_table: &HashMap<u32, LDrawColor>,
+        edge_colors: &HashMap<u32, [f32; 4]>,
+    ) {
         // Convert a color code to an RGBA color.
+        let current_color = ColorCode::Code(current_color);
         for vertex in &mut self.vertices {
-            vertex.color = rgba_color(vertex.color, current_color, color_table);
+            let color = resolve_color(vertex.color, current_color);
+            vertex.color = pack_rgba(color_rgba(color, color_table, edge_colors));
+        }
+
+        // LDraw edges almost always use code 24 for the edge color of the current color.
+        let edge_color = pack_rgba(color_rgba(
+            resolve_color(24, current_color),
+            color_table,
+            edge_colors,
+        ));
+        for vertex in &mut self.edge_vertices {
+            vertex.color = edge_color;
+        }
+    }
+
+    /// The position of each vertex.
+    pub fn positions(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.vertices.iter().map(|v| v.position)
+    }
+
+    /// The normal of each vertex.
+    pub fn normals(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.vertices.iter().map(|v| v.normal.truncate())
+    }
+
+    /// The linear RGBA color of each vertex with 8 bits per channel.
+    pub fn colors(&self) -> impl Iterator<Item = [u8; 4]> + '_ {
+        self.vertices.iter().map(|v| v.color.to_le_bytes())
+    }
+
+    /// Triangle list indices into the vertices.
+    pub fn vertex_indices(&self) -> &[u32] {
+        &self.vertex_indices
+    }
+
+    /// The position of each edge vertex before expanding the edges into screen space quads.
+    pub fn edge_positions(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.edge_vertices
+            .iter()
+            .map(|v| v.start.lerp(v.end, v.corner.x))
+    }
+
+    /// Triangle list indices into [Self::edge_positions] with two triangles per edge.
+    pub fn edge_indices(&self) -> &[u32] {
+        &self.edge_indices
+    }
+}
+
+fn edge_vertex_data(
+    geometry: &ldr_tools::LDrawGeometry,
+) -> (Vec<crate::shader::edge::VertexInput>, Vec<u32>) {
+    let mut edge_vertices = Vec::new();
+    let mut edge_indices = Vec::new();
+
+    // Expand each segment to a quad in the shader for antialiased lines.
+    // The vertices can't be shared since they each store the full segment.
+    for [v0, v1] in &geometry.edge_line_indices {
+        let start = geometry.vertices[*v0 as usize];
+        let end = geometry.vertices[*v1 as usize];
+
+        let base_index = edge_vertices.len() as u32;
+        for corner in [
+            vec2(0.0, -1.0),
+            vec2(0.0, 1.0),
+            vec2(1.0, 1.0),
+            vec2(1.0, -1.0),
+        ] {
+            edge_vertices.push(crate::shader::edge::VertexInput {
+                start,
+                end,
+                corner,
+                color: BLACK,
+            });
         }
+        edge_indices.extend([0, 1, 2, 0, 2, 3].map(|i| base_index + i));
     }
+
+    (edge_vertices, edge_indices)
 }
 
 fn calculate_bounds(positions: &[Vec3]) -> crate::shader::culling::InstanceBounds {
@@ -158,9 +223,9 @@ fn calculate_bounds(positions: &[Vec3]) -> crate::shader::culling::InstanceBound
 struct VertexKey {
     position_index: u32,
     color: u32,
-    // Normals are determined by the set of adjacent faces.
+    // Vertices with the same position can have different normals.
     // This helps define smoothing groups in the mesh.
-    adjacent_faces: BTreeSet<usize>,
+    normal_index: u32,
 }
 
 #[derive(Default)]
@@ -210,14 +275,110 @@ fn insert_vertex(
     }
 }
 
-fn rgba_color(color: u32, current_color: u32, color_table: &HashMap<u32, LDrawColor>) -> u32 {
-    let replaced_color = if color == 16 { current_color } else { color };
+const BLACK: u32 = 0xFF000000;
+
+fn pack_rgba(rgba: [f32; 4]) -> u32 {
+    // TODO: What is the GPU endianness?
+    u32::from_le_bytes(rgba.map(|f| (f * 255.0) as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glam::{vec3, Vec4};
+
+    #[test]
+    fn replace_colors_preserves_indices() {
+        let vertex = |position, color| crate::shader::model::VertexInput {
+            position,
+            normal: Vec4::ZERO,
+            color,
+        };
+        let mut vertex_data = IndexedVertexData {
+            vertices: vec![
+                vertex(vec3(0.0, 0.0, 0.0), 16),
+                vertex(vec3(1.0, 0.0, 0.0), 16),
+                vertex(vec3(0.0, 1.0, 0.0), 4),
+            ],
+            vertex_indices: vec![2, 0, 1],
+            edge_vertices: Vec::new(),
+            edge_indices: Vec::new(),
+            bounds: calculate_bounds(&[]),
+        };
+
+        vertex_data.replace_colors(4, &HashMap::new(), &HashMap::new());
+
+        assert_eq!(vec![2, 0, 1], vertex_data.vertex_indices);
+        assert_eq!(
+            vec![
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0)
+            ],
+            vertex_data
+                .vertices
+                .iter()
+                .map(|v| v.position)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn replace_colors_face_edge_color() {
+        let vertex = |color| crate::shader::model::VertexInput {
+            position: Vec3::ZERO,
+            normal: Vec4::ZERO,
+            color,
+        };
+        let mut vertex_data = IndexedVertexData {
+            vertices: vec![vertex(24), vertex(16)],
+            vertex_indices: Vec::new(),
+            edge_vertices: Vec::new(),
+            edge_indices: Vec::new(),
+            bounds: calculate_bounds(&[]),
+        };
+
+        let edge_colors = [(0, [1.0, 0.0, 0.0, 1.0])].into();
+
+        // Faces with code 24 use the edge color instead of the color table.
+        vertex_data.replace_colors(0, &HashMap::new(), &edge_colors);
+        assert_eq!(
+            vec![0xFF0000FF, 0xFFFFFFFF],
+            vertex_data
+                .vertices
+                .iter()
+                .map(|v| v.color)
+                .collect::<Vec<_>>()
+        );
+    }
 
-    color_table
-        .get(&replaced_color)
-        .map(|c| {
-            // TODO: What is the GPU endianness?
-            u32::from_le_bytes(c.rgba_linear.map(|f| (f * 255.0) as u8))
-        })
-        .unwrap_or(0xFFFFFFFF)
+    #[test]
+    fn replace_colors_edge_color() {
+        let edge_vertex = |color| crate::shader::edge::VertexInput {
+            start: Vec3::ZERO,
+            end: Vec3::X,
+            corner: vec2(0.0, 1.0),
+            color,
+        };
+        let mut vertex_data = IndexedVertexData {
+            vertices: Vec::new(),
+            vertex_indices: Vec::new(),
+            edge_vertices: vec![edge_vertex(BLACK), edge_vertex(BLACK)],
+            edge_indices: Vec::new(),
+            bounds: calculate_bounds(&[]),
+        };
+
+        let edge_colors = [(0, [1.0, 1.0, 1.0, 1.0])].into();
+
+        vertex_data.replace_colors(0, &HashMap::new(), &edge_colors);
+        assert!(vertex_data
+            .edge_vertices
+            .iter()
+            .all(|v| v.color == 0xFFFFFFFF));
+
+        // Colors without an edge color fall back to black.
+        vertex_data.replace_colors(4, &HashMap::new(), &edge_colors);
+        assert!(vertex_data.edge_vertices.iter().all(|v| v.color == BLACK));
+    }
 }
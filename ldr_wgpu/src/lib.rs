@@ -1,33 +1,76 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 use futures::executor::block_on;
-use glam::{vec4, Mat4, Vec4};
-use ldr_tools::{LDrawColor, LDrawSceneInstanced};
+use glam::{vec2, vec3, vec4, Mat4, Vec2, Vec3, Vec4};
+use ldr_tools::{LDrawColor, LDrawSceneInstanced, StudType};
 use log::{debug, info};
-use scene::{draw_indirect, IndirectSceneData};
+use scene::{draw_indirect, draw_indirect_transparent, draw_indirect_uncompacted};
 use texture::create_depth_pyramid_texture;
 use wgpu::util::DeviceExt;
 
 use crate::{
     pipeline::*,
-    scene::load_render_data,
-    texture::{create_depth_texture, create_output_msaa_view},
+    scene::{check_instances, load_render_data},
+    texture::{
+        create_bloom_view, create_color_view, create_depth_texture, create_output_msaa_view,
+        create_pick_texture, padded_bytes_per_row, unpadded_rows,
+    },
+    timing::GpuTimer,
 };
 
+mod cache;
+mod color;
 mod geometry;
 mod normal;
 mod pipeline;
 mod scene;
 mod shader;
 mod texture;
+mod timing;
+mod turntable;
+
+pub use color::{
+    apply_color_config, apply_debug_part_colors, apply_inline_colors, color_rgba, debug_color,
+    edge_colors, resolve_color, resolved_color, ColorCode, ResolvedColor,
+};
+pub use geometry::IndexedVertexData;
+pub use scene::{
+    grid_layout, load_scene, load_scene_with_progress, load_submodel, part_vertex_data,
+    submodel_names, Bounds, CullingResults, DrawIndexedIndirect, IndirectData, IndirectSceneData,
+    InstanceGroup, InvalidInstance, LoadProgress, Material, MaterialDrawRange, MemoryReport,
+    PartDrawRange, SceneError, SceneSettings, VisibilityOverride,
+};
+pub use turntable::{render_turntable, Turntable};
 
 const MSAA_SAMPLES: u32 = 4;
 const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+// Use a floating point format to preserve bright values for post processing.
+const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// A partially transparent orange that keeps the part color visible.
+const DEFAULT_SELECTION_COLOR: [f32; 4] = [1.0, 0.4, 0.0, 0.6];
+
+// The instance index and depth bits for picking.
+const PICK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Uint;
 
 pub const FOV_Y: f32 = 0.5;
+// A neutral gray that works for both light and dark colors.
+const DEFAULT_CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.2,
+    g: 0.2,
+    b: 0.2,
+    a: 1.0,
+};
 const Z_NEAR: f32 = 0.1;
 // The far plane can be infinity since we use reversed-z.
 const Z_FAR: f32 = f32::INFINITY;
+// Orthographic projections require a finite far plane.
+const Z_FAR_ORTHOGRAPHIC: f32 = 100000.0;
+// The width of edge lines in pixels.
+const EDGE_WIDTH: f32 = 1.5;
 
 fn depth_stencil_reversed() -> wgpu::DepthStencilState {
     wgpu::DepthStencilState {
@@ -48,14 +91,570 @@ fn depth_op_reversed() -> wgpu::Operations<f32> {
     }
 }
 
+/// The axis pointing up in the model's coordinate system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpAxis {
+    /// The LDraw convention of -Y up.
+    #[default]
+    Y,
+    /// +Z up as used by many modeling and CAD applications.
+    Z,
+    /// +X up.
+    X,
+}
+
+impl UpAxis {
+    /// The rotation from the model's coordinates to wgpu's +Y up coordinates.
+    pub fn correction(&self) -> Mat4 {
+        match self {
+            UpAxis::Y => Mat4::from_rotation_x(180.0f32.to_radians()),
+            UpAxis::Z => Mat4::from_rotation_x(-90.0f32.to_radians()),
+            UpAxis::X => Mat4::from_rotation_z(90.0f32.to_radians()),
+        }
+    }
+
+    // The coordinate axis in the model's coordinates ignoring the sign of up.
+    fn axis(&self) -> Vec3 {
+        match self {
+            UpAxis::Y => Vec3::Y,
+            UpAxis::Z => Vec3::Z,
+            UpAxis::X => Vec3::X,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CameraData {
     view: Mat4,
+    projection: Mat4,
     view_projection: Mat4,
     // https://vkguide.dev/docs/gpudriven/compute_culling/
     frustum: Vec4,
     p00: f32,
     p11: f32,
     position: Vec4,
+    viewport_size: Vec4,
+    frustum_offset: Vec4,
+    z_near: f32,
+    z_far: f32,
+}
+
+/// The distances to the near and far planes of the camera along the view direction.
+///
+/// Reversed-z with a floating point depth buffer already distributes precision
+/// roughly logarithmically, so the near plane has the largest effect on z-fighting.
+/// Increasing `near` improves precision for large scenes at the cost of clipping nearby parts.
+/// An infinite `far` has almost no precision cost for perspective projections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthRange {
+    near: f32,
+    far: f32,
+}
+
+impl DepthRange {
+    /// Returns an error unless `near` is positive and less than `far`.
+    /// The `far` plane may be [f32::INFINITY].
+    pub fn new(near: f32, far: f32) -> Result<Self, InvalidDepthRange> {
+        // This also rejects NaN.
+        if near > 0.0 && near.is_finite() && far > near {
+            Ok(Self { near, far })
+        } else {
+            Err(InvalidDepthRange { near, far })
+        }
+    }
+
+    pub fn near(&self) -> f32 {
+        self.near
+    }
+
+    pub fn far(&self) -> f32 {
+        self.far
+    }
+}
+
+impl Default for DepthRange {
+    fn default() -> Self {
+        Self {
+            near: Z_NEAR,
+            far: Z_FAR,
+        }
+    }
+}
+
+/// The near and far values passed to [DepthRange::new] were out of range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidDepthRange {
+    pub near: f32,
+    pub far: f32,
+}
+
+impl std::fmt::Display for InvalidDepthRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "depth range near {} and far {} must be positive with near less than far",
+            self.near, self.far
+        )
+    }
+}
+
+impl std::error::Error for InvalidDepthRange {}
+
+/// A device created outside the renderer doesn't support the renderer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnsupportedDevice {
+    /// The device didn't enable these features from [REQUIRED_FEATURES].
+    MissingFeatures(wgpu::Features),
+    /// At least one of the device's limits is worse than [REQUIRED_LIMITS].
+    InsufficientLimits,
+}
+
+impl std::fmt::Display for UnsupportedDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnsupportedDevice::MissingFeatures(features) => {
+                write!(f, "device is missing required features {features:?}")
+            }
+            UnsupportedDevice::InsufficientLimits => {
+                write!(f, "device limits are lower than the required limits")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnsupportedDevice {}
+
+/// Check that a device created outside the renderer supports [REQUIRED_FEATURES]
+/// and [REQUIRED_LIMITS].
+pub fn check_device(device: &wgpu::Device) -> Result<(), UnsupportedDevice> {
+    check_features_limits(device.features(), &device.limits())
+}
+
+fn check_features_limits(
+    features: wgpu::Features,
+    limits: &wgpu::Limits,
+) -> Result<(), UnsupportedDevice> {
+    let missing_features = REQUIRED_FEATURES - features;
+    if !missing_features.is_empty() {
+        Err(UnsupportedDevice::MissingFeatures(missing_features))
+    } else if !REQUIRED_LIMITS.check_limits(limits) {
+        Err(UnsupportedDevice::InsufficientLimits)
+    } else {
+        Ok(())
+    }
+}
+
+/// How [FilmGrain] is combined with the rendered colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GrainBlend {
+    /// Lighten light areas and darken dark areas while preserving contrast.
+    #[default]
+    Overlay,
+    /// Scale the colors to only affect brighter areas.
+    Multiply,
+    /// Add the noise with the same strength everywhere.
+    Add,
+}
+
+/// A noise overlay for stylized renders like film grain or printed instructions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilmGrain {
+    /// The strength of the noise from `0.0` to `1.0`.
+    pub intensity: f32,
+    /// The size of each grain in pixels.
+    pub size: f32,
+    /// The same seed always produces the same noise pattern.
+    pub seed: u32,
+    pub blend: GrainBlend,
+}
+
+impl Default for FilmGrain {
+    fn default() -> Self {
+        Self {
+            intensity: 0.1,
+            size: 1.0,
+            seed: 0,
+            blend: GrainBlend::Overlay,
+        }
+    }
+}
+
+/// The camera projection used for rendering.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// A perspective projection with the vertical field of view in radians.
+    Perspective { fov_y: f32 },
+    /// An orthographic projection with the visible height in world units.
+    Orthographic { height: f32 },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self::Perspective { fov_y: FOV_Y }
+    }
+}
+
+/// The [Projection::Orthographic] height that matches the on screen size of objects `distance`
+/// units from the camera for a [Projection::Perspective] with vertical field of view `fov_y`.
+///
+/// This avoids sudden changes in size when toggling between projections.
+pub fn orthographic_height(distance: f32, fov_y: f32) -> f32 {
+    2.0 * distance * (fov_y * 0.5).tan()
+}
+
+/// Screen space shadows for small details like studs and nearby parts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContactShadows {
+    /// The world space direction the light travels.
+    pub light_direction: Vec3,
+    /// How much to darken shadowed areas from `0.0` to `1.0`.
+    pub intensity: f32,
+    /// The maximum distance to search for occluders.
+    pub length: f32,
+    /// The assumed thickness of geometry in the depth buffer.
+    pub thickness: f32,
+}
+
+impl Default for ContactShadows {
+    fn default() -> Self {
+        Self {
+            light_direction: Vec3::new(-1.0, -1.0, -1.0).normalize(),
+            intensity: 0.5,
+            // One stud is 20 LDU.
+            length: 20.0,
+            thickness: 10.0,
+        }
+    }
+}
+
+/// A directional key light combined with a dim fill light from the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSettings {
+    /// The world space direction the light travels.
+    /// This uses the model's coordinates like [ContactShadows::light_direction].
+    pub direction: Vec3,
+    /// Linear RGB color and intensity of the key light.
+    pub color: [f32; 3],
+    /// The constant lighting from `0.0` to `1.0` applied to all surfaces.
+    pub ambient: f32,
+}
+
+impl Default for LightSettings {
+    fn default() -> Self {
+        Self {
+            // Light from above and to the front left for LDraw's -Y up.
+            direction: Vec3::new(0.5, 1.0, 0.75).normalize(),
+            color: [0.6, 0.6, 0.6],
+            ambient: 0.25,
+        }
+    }
+}
+
+/// Screen space ambient occlusion using only the depth buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ssao {
+    /// The world space radius to check for occluders.
+    pub radius: f32,
+    /// How much to darken occluded areas from `0.0` to `1.0`.
+    pub intensity: f32,
+    /// The number of samples per pixel from `1` to `64`.
+    /// More samples reduce noise at the cost of performance.
+    pub samples: u32,
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self {
+            // Slightly smaller than a stud to darken the gaps between parts.
+            radius: 8.0,
+            intensity: 0.75,
+            samples: 8,
+        }
+    }
+}
+
+/// Distance fog that fades the scene toward a color for added depth perception.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    /// Linear RGB color at full fog density.
+    /// This should usually match the background.
+    pub color: [f32; 3],
+    /// The distance from the camera in LDraw units where the fog begins.
+    pub start: f32,
+    /// How quickly the fog thickens past `start`.
+    pub density: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            color: [0.2, 0.2, 0.2],
+            start: 0.0,
+            // Half the visibility after roughly 10000 units.
+            density: 0.00007,
+        }
+    }
+}
+
+/// A ground plane below the model that only shows the model's shadows.
+/// Unshadowed areas are transparent, so the plane composites over any background.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroundPlane {
+    /// The axis perpendicular to the plane.
+    pub up_axis: UpAxis,
+    /// The coordinate of the plane along `up_axis`.
+    /// LDraw uses -Y up, so this should be the maximum Y of the model's bounds
+    /// or the minimum coordinate for [UpAxis::Z] and [UpAxis::X].
+    pub height: f32,
+    /// Linear RGBA color for fully shadowed areas.
+    pub color: [f32; 4],
+    /// The world space direction the light travels.
+    pub light_direction: Vec3,
+    /// The maximum distance from the plane to search for occluders.
+    pub shadow_length: f32,
+}
+
+impl Default for GroundPlane {
+    fn default() -> Self {
+        Self {
+            up_axis: UpAxis::default(),
+            height: 0.0,
+            color: [0.0, 0.0, 0.0, 0.5],
+            light_direction: LightSettings::default().direction,
+            // Ten studs.
+            shadow_length: 200.0,
+        }
+    }
+}
+
+/// Screen space outlines around silhouettes and sharp creases for a technical drawing look.
+///
+/// Edge lines already mark creases, so only silhouettes are outlined
+/// when the [RenderMode] draws edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlineSettings {
+    /// Linear RGBA color of the outline.
+    pub color: [f32; 4],
+    /// The width of the outline in pixels.
+    pub thickness: f32,
+    /// The minimum change in depth as a fraction of the distance from the camera.
+    pub depth_threshold: f32,
+    /// The minimum angle in degrees between surface normals for creases.
+    pub normal_threshold: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: [0.0, 0.0, 0.0, 1.0],
+            thickness: 1.0,
+            depth_threshold: 0.05,
+            normal_threshold: 60.0,
+        }
+    }
+}
+
+/// A reference grid on the ground plane in LDraw units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    /// The distance between lines.
+    pub spacing: f32,
+    /// The maximum distance of the grid from the origin along X and Z.
+    pub extent: f32,
+    /// The Y coordinate of the grid plane.
+    /// LDraw uses -Y up, so this should be the maximum Y of the model.
+    pub height: f32,
+    /// Linear RGBA color for each line.
+    pub color: [f32; 4],
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Self {
+            // One line per stud.
+            spacing: 20.0,
+            extent: 1000.0,
+            height: 0.0,
+            color: [0.5, 0.5, 0.5, 0.5],
+        }
+    }
+}
+
+/// The image behind the scene where nothing is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    /// A solid premultiplied color like [wgpu::Color::TRANSPARENT].
+    Color(wgpu::Color),
+    /// A checkerboard pattern to make transparent areas easier to see.
+    Checker(Checker),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::Color(DEFAULT_CLEAR_COLOR)
+    }
+}
+
+/// A screen space checkerboard pattern.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checker {
+    /// The width of each square in pixels.
+    pub size: f32,
+    /// Linear RGBA colors for alternating squares.
+    pub colors: [[f32; 4]; 2],
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            colors: [[0.2, 0.2, 0.2, 1.0], [0.3, 0.3, 0.3, 1.0]],
+        }
+    }
+}
+
+/// Which parts of the geometry to draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// Shaded faces without edge lines.
+    Shaded,
+    /// Only edge lines like a CAD wireframe.
+    /// Faces still write depth, so edges hidden behind other parts aren't drawn.
+    Wireframe,
+    /// Shaded faces with edge lines.
+    #[default]
+    ShadedWithEdges,
+}
+
+/// How the final blit maps bright linear colors to the output range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemap {
+    /// Output colors unchanged.
+    /// Unorm formats clip colors above `1.0` like bloom and bright lights,
+    /// while float formats like [wgpu::TextureFormat::Rgba16Float] preserve them for HDR displays.
+    #[default]
+    None,
+    /// Compress highlights with the simple Reinhard operator, which also slightly darkens midtones.
+    Reinhard,
+    /// An approximation of the ACES filmic curve with more contrast than [Tonemap::Reinhard].
+    Aces,
+}
+
+impl std::str::FromStr for Tonemap {
+    type Err = UnknownOption;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Tonemap::None),
+            "reinhard" => Ok(Tonemap::Reinhard),
+            "aces" => Ok(Tonemap::Aces),
+            _ => Err(UnknownOption {
+                value: s.to_string(),
+                expected: &["none", "reinhard", "aces"],
+            }),
+        }
+    }
+}
+
+/// Bundled settings for trading rendering quality for speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualityPreset {
+    /// Fast loading for quickly scanning large libraries.
+    /// Disables studs and screen space effects and simplifies parts over 2000 triangles.
+    Draft,
+    /// The default balance of quality and speed.
+    /// Uses high contrast studs without any screen space effects.
+    #[default]
+    Preview,
+    /// Slower loading and rendering for final renders.
+    /// Uses logo studs, SSAO, contact shadows, overdraw optimization, and 2x supersampling.
+    High,
+}
+
+impl std::str::FromStr for QualityPreset {
+    type Err = UnknownOption;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "draft" => Ok(QualityPreset::Draft),
+            "preview" => Ok(QualityPreset::Preview),
+            "high" => Ok(QualityPreset::High),
+            _ => Err(UnknownOption {
+                value: s.to_string(),
+                expected: &["draft", "preview", "high"],
+            }),
+        }
+    }
+}
+
+/// The string doesn't match any of the expected values when parsing an enum like [QualityPreset].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownOption {
+    pub value: String,
+    pub expected: &'static [&'static str],
+}
+
+impl std::fmt::Display for UnknownOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown value {:?}, expected one of {}",
+            self.value,
+            self.expected.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownOption {}
+
+/// The settings selected by a [QualityPreset].
+#[derive(Debug, Clone)]
+pub struct QualitySettings {
+    pub stud_type: StudType,
+    pub scene: SceneSettings,
+    pub ssao: Option<Ssao>,
+    pub contact_shadows: Option<ContactShadows>,
+    /// The resolution scale for offscreen renders like [Renderer::set_supersample_factor].
+    pub supersample: u32,
+}
+
+impl QualityPreset {
+    pub fn settings(self) -> QualitySettings {
+        match self {
+            QualityPreset::Draft => QualitySettings {
+                stud_type: StudType::Disabled,
+                scene: SceneSettings {
+                    optimize_vertex_cache: false,
+                    optimize_overdraw: None,
+                    max_triangles_per_part: Some(2000),
+                    ..Default::default()
+                },
+                ssao: None,
+                contact_shadows: None,
+                supersample: 1,
+            },
+            QualityPreset::Preview => QualitySettings {
+                stud_type: StudType::HighContrast,
+                scene: SceneSettings::default(),
+                ssao: None,
+                contact_shadows: None,
+                supersample: 1,
+            },
+            QualityPreset::High => QualitySettings {
+                stud_type: StudType::Logo4,
+                scene: SceneSettings {
+                    optimize_overdraw: Some(1.05),
+                    ..Default::default()
+                },
+                ssao: Some(Ssao {
+                    samples: 16,
+                    ..Default::default()
+                }),
+                contact_shadows: Some(ContactShadows::default()),
+                supersample: 2,
+            },
+        }
+    }
 }
 
 struct ScanBindGroups {
@@ -68,6 +667,13 @@ pub struct Renderer {
     camera_buffer: wgpu::Buffer,
 
     output_view_msaa: wgpu::TextureView,
+    sample_count: u32,
+
+    // The output size before applying the supersampling factor.
+    width: u32,
+    height: u32,
+    supersample: f32,
+    color_view: wgpu::TextureView,
     depth_texture: wgpu::Texture,
     depth_view: wgpu::TextureView,
 
@@ -79,34 +685,112 @@ pub struct Renderer {
     // Render State
     // TODO: Organize the data better.
     bind_group0: shader::model::bind_groups::BindGroup0,
+    light_buffer: wgpu::Buffer,
+    highlight_buffer: wgpu::Buffer,
     model_pipeline: wgpu::RenderPipeline,
+    model_depth_pipeline: wgpu::RenderPipeline,
+    model_back_faces_pipeline: wgpu::RenderPipeline,
+    model_front_faces_pipeline: wgpu::RenderPipeline,
     model_edges_pipeline: wgpu::RenderPipeline,
+    render_mode: RenderMode,
+    edge_bind_group0: shader::edge::bind_groups::BindGroup0,
+    edge_camera_buffer: wgpu::Buffer,
+    edge_settings_buffer: wgpu::Buffer,
 
     visibility_pipeline: wgpu::ComputePipeline,
 
     camera_culling_buffer: wgpu::Buffer,
     culling_bind_group0: shader::culling::bind_groups::BindGroup0,
     culling_pipeline: wgpu::ComputePipeline,
+    all_visible_pipeline: wgpu::ComputePipeline,
+    culling_enabled: bool,
 
     scan_pipeline: wgpu::ComputePipeline,
     scan_add_pipeline: wgpu::ComputePipeline,
 
     supports_indirect_count: bool,
+    timer: Option<GpuTimer>,
+
+    clear_color: wgpu::Color,
+
+    output_format: wgpu::TextureFormat,
+
+    // Post processing applied when copying the color texture to the output.
+    blit_pipeline: wgpu::RenderPipeline,
+    blit_bind_group0: shader::blit::bind_groups::BindGroup0,
+    post_process: shader::blit::PostProcessSettings,
+    post_process_buffer: wgpu::Buffer,
+    post_process_camera_buffer: wgpu::Buffer,
+
+    bloom_threshold_pipeline: wgpu::ComputePipeline,
+    bloom_horizontal_pipeline: wgpu::ComputePipeline,
+    bloom_vertical_pipeline: wgpu::ComputePipeline,
+    bloom_settings_buffer: wgpu::Buffer,
+    bloom: Bloom,
+
+    grid_pipeline: wgpu::RenderPipeline,
+    grid_bind_group0: shader::grid::bind_groups::BindGroup0,
+    grid_buffer: wgpu::Buffer,
+    grid_vertex_count: u32,
+
+    // Average jittered frames while the camera is stationary.
+    camera_data: CameraData,
+    accumulate_pipeline: wgpu::RenderPipeline,
+    accumulation: Option<Accumulation>,
+    max_accumulated_samples: Option<u32>,
+    render_data_version: u64,
+
+    pick_pipeline: wgpu::RenderPipeline,
+    pick_camera_buffer: wgpu::Buffer,
+    pick_bind_group0: shader::model::bind_groups::BindGroup0,
+}
+
+/// The closest surface under a point on screen from [Renderer::pick].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickResult {
+    /// The index into [IndirectSceneData::draws] like the indices in [InstanceGroup::instances].
+    pub instance_index: usize,
+    /// The distance from the camera to `world_pos`.
+    pub distance: f32,
+    /// The world space position of the surface under the point.
+    pub world_pos: Vec3,
 }
 
 pub struct RenderData {
     scene: IndirectSceneData,
+    model_bind_group1: shader::model::bind_groups::BindGroup1,
     culling_bind_group1: shader::culling::bind_groups::BindGroup1,
     visible_bind_group: shader::visibility::bind_groups::BindGroup0,
     newly_visible_bind_group: shader::visibility::bind_groups::BindGroup0,
     scan_visible: ScanBindGroups,
     scan_newly_visible: ScanBindGroups,
+    // Changes whenever the rendered output would change to reset accumulation.
+    version: u64,
+}
+
+struct Bloom {
+    width: u32,
+    height: u32,
+    // The blurred result is written back to the first texture.
+    view: wgpu::TextureView,
+    threshold_bind_group: shader::bloom::bind_groups::BindGroup0,
+    horizontal_bind_group: shader::bloom::bind_groups::BindGroup0,
+    vertical_bind_group: shader::bloom::bind_groups::BindGroup0,
+}
+
+struct Accumulation {
+    // The running average of the jittered frames.
+    view: wgpu::TextureView,
+    bind_group: shader::accumulate::bind_groups::BindGroup0,
+    samples: u32,
+    max_samples: u32,
 }
 
 struct DepthPyramid {
     width: u32,
     height: u32,
     all_mips: wgpu::TextureView,
+    base_level: wgpu::TextureView,
     base_bind_group: shader::blit_depth::bind_groups::BindGroup0,
     mip_bind_groups: Vec<shader::depth_pyramid::bind_groups::BindGroup0>,
 }
@@ -117,14 +801,63 @@ impl RenderData {
         device: &wgpu::Device,
         ldraw_scene: &LDrawSceneInstanced,
         color_table: &HashMap<u32, LDrawColor>,
-    ) -> Self {
+        settings: &SceneSettings,
+    ) -> Result<Self, SceneError> {
+        Self::new_with_progress(device, ldraw_scene, color_table, settings, |_| ())
+    }
+
+    /// Load the scene like [RenderData::new] while reporting progress.
+    ///
+    /// The `progress` callback is called at the start of each [LoadProgress] stage
+    /// and after adding each colored part to the scene.
+    /// The callback runs on the calling thread and should return quickly.
+    pub fn new_with_progress(
+        device: &wgpu::Device,
+        ldraw_scene: &LDrawSceneInstanced,
+        color_table: &HashMap<u32, LDrawColor>,
+        settings: &SceneSettings,
+        progress: impl FnMut(LoadProgress),
+    ) -> Result<Self, SceneError> {
+        Self::from_scenes(
+            device,
+            &[(ldraw_scene, Mat4::IDENTITY)],
+            color_table,
+            settings,
+            progress,
+        )
+    }
+
+    /// Load multiple scenes into a single scene for comparisons or contact sheets.
+    /// Each scene is transformed by its layout transform like those from [grid_layout].
+    pub fn new_combined(
+        device: &wgpu::Device,
+        ldraw_scenes: &[(&LDrawSceneInstanced, Mat4)],
+        color_table: &HashMap<u32, LDrawColor>,
+        settings: &SceneSettings,
+    ) -> Result<Self, SceneError> {
+        Self::from_scenes(device, ldraw_scenes, color_table, settings, |_| ())
+    }
+
+    fn from_scenes(
+        device: &wgpu::Device,
+        ldraw_scenes: &[(&LDrawSceneInstanced, Mat4)],
+        color_table: &HashMap<u32, LDrawColor>,
+        settings: &SceneSettings,
+        progress: impl FnMut(LoadProgress),
+    ) -> Result<Self, SceneError> {
         let start = std::time::Instant::now();
-        let render_data = load_render_data(device, ldraw_scene, color_table);
+        let render_data = load_render_data(device, ldraw_scenes, color_table, settings, progress)?;
         info!(
             "Load {} parts, {} unique colored parts, and {} unique parts: {:?}",
             render_data.solid.draw_count,
-            ldraw_scene.geometry_world_transforms.len(),
-            ldraw_scene.geometry_cache.len(),
+            ldraw_scenes
+                .iter()
+                .map(|(s, _)| s.geometry_world_transforms.len())
+                .sum::<usize>(),
+            ldraw_scenes
+                .iter()
+                .map(|(s, _)| s.geometry_cache.len())
+                .sum::<usize>(),
             start.elapsed()
         );
 
@@ -137,6 +870,10 @@ impl RenderData {
                 visibility: render_data.visibility_buffer.as_entire_buffer_binding(),
                 new_visibility: render_data.new_visibility_buffer.as_entire_buffer_binding(),
                 transparent: render_data.transparent_buffer.as_entire_buffer_binding(),
+                visibility_override: render_data
+                    .visibility_override_buffer
+                    .as_entire_buffer_binding(),
+                draws: render_data.solid.indirect_buffer.as_entire_buffer_binding(),
             },
         );
 
@@ -160,6 +897,7 @@ impl RenderData {
                 compacted_draw_count: render_data
                     .compacted_count_buffer
                     .as_entire_buffer_binding(),
+                draw_chunks: render_data.draw_chunks_buffer.as_entire_buffer_binding(),
             },
         );
 
@@ -183,6 +921,7 @@ impl RenderData {
                 compacted_draw_count: render_data
                     .compacted_count_buffer
                     .as_entire_buffer_binding(),
+                draw_chunks: render_data.draw_chunks_buffer.as_entire_buffer_binding(),
             },
         );
 
@@ -200,24 +939,175 @@ impl RenderData {
             &render_data.scanned_new_visibility_buffer,
         );
 
-        Self {
+        let model_bind_group1 = shader::model::bind_groups::BindGroup1::from_bindings(
+            device,
+            shader::model::bind_groups::BindGroupLayout1 {
+                selected: render_data.selection_buffer.as_entire_buffer_binding(),
+            },
+        );
+
+        Ok(Self {
             scene: render_data,
+            model_bind_group1,
             culling_bind_group1,
             visible_bind_group,
             newly_visible_bind_group,
             scan_visible,
             scan_newly_visible,
+            version: next_render_data_version(),
+        })
+    }
+
+    /// The combined GPU buffers and draw metadata for the scene.
+    /// This allows issuing custom draws instead of using [Renderer::render].
+    pub fn scene(&self) -> &IndirectSceneData {
+        &self.scene
+    }
+
+    /// Override culling for the instances at `indices` in the scene's draws.
+    /// This can isolate culling issues or hide parts to focus on the rest of the scene.
+    /// Hidden instances may still be drawn for one frame since culling uses the previous frame.
+    pub fn force_visibility(
+        &mut self,
+        queue: &wgpu::Queue,
+        indices: &[usize],
+        visibility: VisibilityOverride,
+    ) {
+        self.scene
+            .write_visibility_overrides(queue, indices, visibility);
+        self.version = next_render_data_version();
+    }
+
+    /// Highlight the instances at `indices` in the scene's draws like the results of [Renderer::pick].
+    /// Any previously selected instances not in `indices` are deselected,
+    /// so an empty slice clears the selection.
+    /// The color is set with [Renderer::set_selection_color].
+    pub fn set_selected(&mut self, queue: &wgpu::Queue, indices: &[usize]) {
+        self.scene.write_selection(queue, indices);
+        self.version = next_render_data_version();
+    }
+
+    /// The groups of instances in the scene.
+    pub fn groups(&self) -> &[InstanceGroup] {
+        &self.scene.groups
+    }
+
+    /// Add a new group with the instances at `instances` in the scene's draws.
+    /// Returns the index of the group for [RenderData::set_group_visibility]
+    /// and [RenderData::set_group_transform].
+    ///
+    /// Returns an error without adding the group if any index isn't in the scene's draws.
+    pub fn add_group(
+        &mut self,
+        name: impl Into<String>,
+        instances: Vec<usize>,
+    ) -> Result<usize, InvalidInstance> {
+        check_instances(&instances, self.scene.draws().len())?;
+        self.scene.groups.push(InstanceGroup {
+            name: name.into(),
+            instances,
+        });
+        Ok(self.scene.groups.len() - 1)
+    }
+
+    /// Override culling for all instances in the group at index `group`
+    /// like [RenderData::force_visibility].
+    pub fn set_group_visibility(
+        &mut self,
+        queue: &wgpu::Queue,
+        group: usize,
+        visibility: VisibilityOverride,
+    ) {
+        if let Some(group) = self.scene.groups.get(group) {
+            let instances = group.instances.clone();
+            self.force_visibility(queue, &instances, visibility);
+        }
+    }
+
+    /// Move all instances in the group at index `group` by `transform`.
+    ///
+    /// The transform is relative to the instance transforms when loading,
+    /// so setting [Mat4::IDENTITY] restores the original placement.
+    /// This doesn't update [IndirectSceneData::bounds].
+    pub fn set_group_transform(&mut self, queue: &wgpu::Queue, group: usize, transform: Mat4) {
+        if let Some(group) = self.scene.groups.get(group) {
+            let instances = group.instances.clone();
+            self.scene.write_transforms(queue, &instances, transform);
+            self.version = next_render_data_version();
         }
     }
+
+    /// Read the culling results from the most recent frame.
+    /// Returns `None` unless [SceneSettings::readback_culling] was enabled when loading.
+    ///
+    /// This blocks until the GPU finishes rendering and should only be used for debugging.
+    pub fn culling_results(&self, device: &wgpu::Device) -> Option<CullingResults> {
+        let buffer = self.scene.culling_staging_buffer.as_ref()?;
+        let buffer_slice = buffer.slice(..);
+
+        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+
+        device.poll(wgpu::Maintain::Wait);
+
+        block_on(receiver.receive())?.ok()?;
+
+        let data = buffer_slice.get_mapped_range();
+        let values: &[u32] = bytemuck::cast_slice(&data);
+        let (visible, newly_visible) = values.split_at(values.len() / 2);
+        let results = CullingResults {
+            visible: visible.iter().map(|v| *v != 0).collect(),
+            newly_visible: newly_visible.iter().map(|v| *v != 0).collect(),
+        };
+
+        drop(data);
+        buffer.unmap();
+
+        Some(results)
+    }
+
+    /// Free the scene's GPU memory immediately.
+    ///
+    /// Dropping also frees the memory but only once wgpu cleans up unused resources.
+    /// Releasing explicitly avoids accumulating memory when loading many scenes in a row.
+    pub fn release(self) {
+        self.scene.destroy();
+    }
 }
 
 impl Renderer {
+    /// Create a renderer for a device shared with the host application like an editor.
+    ///
+    /// Unlike [Renderer::new], this only uses the optional features that `device` enabled
+    /// and returns an error instead of panicking later if a required feature is missing.
+    pub fn from_device(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        camera_data: &CameraData,
+        output_format: wgpu::TextureFormat,
+        sample_count: SampleCount,
+    ) -> Result<Self, UnsupportedDevice> {
+        check_device(device)?;
+        Ok(Self::new(
+            device,
+            width,
+            height,
+            camera_data,
+            output_format,
+            sample_count,
+            device.features(),
+        ))
+    }
+
+    /// Create a renderer using `sample_count` MSAA samples from [supported_sample_count].
     pub fn new(
         device: &wgpu::Device,
         width: u32,
         height: u32,
         camera_data: &CameraData,
         output_format: wgpu::TextureFormat,
+        sample_count: SampleCount,
         supported_features: wgpu::Features,
     ) -> Self {
         let required_features = required_features(supported_features);
@@ -225,11 +1115,18 @@ impl Renderer {
             required_features.contains(wgpu::Features::MULTI_DRAW_INDIRECT_COUNT);
         debug!("{:?}", required_features);
 
-        let model_pipeline = create_pipeline(device, output_format, false);
-        let model_edges_pipeline = create_pipeline(device, output_format, true);
+        let sample_count = sample_count.get();
+        let model_pipeline = create_pipeline(device, COLOR_FORMAT, sample_count);
+        let model_depth_pipeline = create_depth_pipeline(device, COLOR_FORMAT, sample_count);
+        let model_back_faces_pipeline =
+            create_transparent_pipeline(device, COLOR_FORMAT, sample_count, wgpu::Face::Front);
+        let model_front_faces_pipeline =
+            create_transparent_pipeline(device, COLOR_FORMAT, sample_count, wgpu::Face::Back);
+        let model_edges_pipeline = create_edge_pipeline(device, COLOR_FORMAT, sample_count);
 
         let visibility_pipeline = shader::visibility::compute::create_main_pipeline(device);
         let culling_pipeline = shader::culling::compute::create_main_pipeline(device);
+        let all_visible_pipeline = shader::culling::compute::create_all_visible_pipeline(device);
         let scan_pipeline = shader::scan::compute::create_main_pipeline(device);
         let scan_add_pipeline = shader::scan_add::compute::create_main_pipeline(device);
         let depth_pyramid_pipeline = shader::depth_pyramid::compute::create_main_pipeline(device);
@@ -244,61 +1141,203 @@ impl Renderer {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let bind_group0 = shader::model::bind_groups::BindGroup0::from_bindings(
-            device,
-            shader::model::bind_groups::BindGroupLayout0 {
-                camera: camera_buffer.as_entire_buffer_binding(),
-            },
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("light buffer"),
+            contents: bytemuck::cast_slice(&[model_light(&LightSettings::default())]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let highlight_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("highlight buffer"),
+            contents: bytemuck::cast_slice(&[Vec4::from(DEFAULT_SELECTION_COLOR)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group0 = shader::model::bind_groups::BindGroup0::from_bindings(
+            device,
+            shader::model::bind_groups::BindGroupLayout0 {
+                camera: camera_buffer.as_entire_buffer_binding(),
+                light: light_buffer.as_entire_buffer_binding(),
+                highlight: highlight_buffer.as_entire_buffer_binding(),
+            },
+        );
+
+        // Picking only needs the camera, but the model shader also expects lighting.
+        let pick_pipeline = create_pick_pipeline(device);
+        let pick_camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pick camera buffer"),
+            size: std::mem::size_of::<shader::model::Camera>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pick_bind_group0 = shader::model::bind_groups::BindGroup0::from_bindings(
+            device,
+            shader::model::bind_groups::BindGroupLayout0 {
+                camera: pick_camera_buffer.as_entire_buffer_binding(),
+                light: light_buffer.as_entire_buffer_binding(),
+                highlight: highlight_buffer.as_entire_buffer_binding(),
+            },
+        );
+
+        let edge_camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("edge camera buffer"),
+            contents: bytemuck::cast_slice(&[edge_camera(camera_data)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let edge_settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("edge settings buffer"),
+            contents: bytemuck::cast_slice(&[shader::edge::EdgeSettings { width: EDGE_WIDTH }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let edge_bind_group0 = shader::edge::bind_groups::BindGroup0::from_bindings(
+            device,
+            shader::edge::bind_groups::BindGroupLayout0 {
+                camera: edge_camera_buffer.as_entire_buffer_binding(),
+                settings: edge_settings_buffer.as_entire_buffer_binding(),
+            },
         );
 
         // TODO: just use encase for this to avoid manually handling padding?
         let camera_culling_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("camera culling buffer"),
-            contents: bytemuck::cast_slice(&[shader::culling::Camera {
-                z_near: Z_NEAR,
-                z_far: Z_FAR,
-                p00: camera_data.p00,
-                p11: camera_data.p11,
-                frustum: camera_data.frustum,
-                view_projection: camera_data.view_projection,
-                view: camera_data.view,
-            }]),
+            contents: bytemuck::cast_slice(&[culling_camera(camera_data)]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let (depth_texture, depth_view) = create_depth_texture(device, width, height);
+        let (depth_texture, depth_view) = create_depth_texture(device, width, height, sample_count);
 
         let depth_pyramid = create_depth_pyramid(device, width, height, &depth_view);
 
-        let depth_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            min_filter: wgpu::FilterMode::Nearest,
-            mag_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-
         let culling_bind_group0 = shader::culling::bind_groups::BindGroup0::from_bindings(
             device,
             shader::culling::bind_groups::BindGroupLayout0 {
                 camera: camera_culling_buffer.as_entire_buffer_binding(),
                 depth_pyramid: &depth_pyramid.all_mips,
-                depth_sampler: &depth_sampler,
             },
         );
 
-        let output_view_msaa = create_output_msaa_view(device, width, height, output_format);
+        let output_view_msaa =
+            create_output_msaa_view(device, width, height, COLOR_FORMAT, sample_count);
+        let color_view = create_color_view(device, width, height);
+
+        let grid_pipeline = create_grid_pipeline(device, COLOR_FORMAT, sample_count);
+        let grid_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("grid buffer"),
+            size: std::mem::size_of::<shader::grid::Grid>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let grid_bind_group0 = shader::grid::bind_groups::BindGroup0::from_bindings(
+            device,
+            shader::grid::bind_groups::BindGroupLayout0 {
+                camera: camera_buffer.as_entire_buffer_binding(),
+                grid: grid_buffer.as_entire_buffer_binding(),
+            },
+        );
+
+        let blit_pipeline = create_blit_pipeline(device, output_format);
+        let accumulate_pipeline = create_accumulate_pipeline(device, COLOR_FORMAT);
+        let bloom_threshold_pipeline = shader::bloom::compute::create_threshold_pipeline(device);
+        let bloom_horizontal_pipeline =
+            shader::bloom::compute::create_blur_horizontal_pipeline(device);
+        let bloom_vertical_pipeline = shader::bloom::compute::create_blur_vertical_pipeline(device);
+
+        let bloom_settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("bloom settings buffer"),
+            contents: bytemuck::cast_slice(&[shader::bloom::BloomSettings { threshold: 1.0 }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Post processing effects are disabled by default.
+        let post_process = shader::blit::PostProcessSettings {
+            light_direction: Vec4::ZERO,
+            bloom_intensity: 0.0,
+            contact_shadow_intensity: 0.0,
+            contact_shadow_length: 0.0,
+            contact_shadow_thickness: 0.0,
+            ssao_radius: 0.0,
+            ssao_intensity: 0.0,
+            grain_intensity: 0.0,
+            grain_size: 1.0,
+            grain_seed: 0,
+            grain_blend: 0,
+            checker_size: 0.0,
+            ssao_samples: 8,
+            checker_color0: Vec4::ZERO,
+            checker_color1: Vec4::ZERO,
+            fog_color: Vec4::ZERO,
+            fog_start: 0.0,
+            fog_density: 0.0,
+            _padding1: 0.0,
+            _padding2: 0.0,
+            ground_light_direction: Vec4::ZERO,
+            ground_color: Vec4::ZERO,
+            ground_axis: Vec4::ZERO,
+            ground_height: 0.0,
+            ground_shadow_length: 0.0,
+            _padding3: 0.0,
+            _padding4: 0.0,
+            outline_color: Vec4::ZERO,
+            outline_thickness: 0.0,
+            outline_depth_threshold: 0.0,
+            outline_normal_threshold: 0.0,
+            outline_normals: 0,
+            tonemap: 0,
+            _padding5: 0.0,
+            _padding6: 0.0,
+            _padding7: 0.0,
+        };
+        let post_process_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("post process buffer"),
+            contents: bytemuck::cast_slice(&[post_process]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let post_process_camera_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("post process camera buffer"),
+                contents: bytemuck::cast_slice(&[post_process_camera(camera_data)]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let bloom = create_bloom(device, width, height, &color_view, &bloom_settings_buffer);
+        let blit_bind_group0 = create_blit_bind_group(
+            device,
+            &color_view,
+            &bloom.view,
+            &depth_pyramid.base_level,
+            &post_process_buffer,
+            &post_process_camera_buffer,
+        );
 
         Self {
             model_pipeline,
+            model_depth_pipeline,
+            model_back_faces_pipeline,
+            model_front_faces_pipeline,
             model_edges_pipeline,
+            render_mode: RenderMode::default(),
+            edge_bind_group0,
+            edge_camera_buffer,
+            edge_settings_buffer,
             visibility_pipeline,
             culling_pipeline,
+            all_visible_pipeline,
+            culling_enabled: true,
             culling_bind_group0,
             bind_group0,
+            light_buffer,
+            highlight_buffer,
             camera_buffer,
             depth_texture,
             depth_view,
             output_view_msaa,
+            sample_count,
+            width,
+            height,
+            supersample: 1.0,
             camera_culling_buffer,
             depth_pyramid,
             depth_pyramid_pipeline,
@@ -306,10 +1345,291 @@ impl Renderer {
             scan_pipeline,
             scan_add_pipeline,
             supports_indirect_count,
+            timer: None,
+            clear_color: DEFAULT_CLEAR_COLOR,
+            output_format,
+            color_view,
+            blit_pipeline,
+            blit_bind_group0,
+            post_process,
+            post_process_buffer,
+            post_process_camera_buffer,
+            bloom_threshold_pipeline,
+            bloom_horizontal_pipeline,
+            bloom_vertical_pipeline,
+            bloom_settings_buffer,
+            bloom,
+            grid_pipeline,
+            grid_bind_group0,
+            grid_buffer,
+            grid_vertex_count: 0,
+            camera_data: *camera_data,
+            accumulate_pipeline,
+            accumulation: None,
+            max_accumulated_samples: None,
+            render_data_version: 0,
+            pick_pipeline,
+            pick_camera_buffer,
+            pick_bind_group0,
+        }
+    }
+
+    /// Set the color used to clear the output before rendering.
+    ///
+    /// Output colors use premultiplied alpha, so clearing to
+    /// [wgpu::Color::TRANSPARENT] produces edges that composite cleanly.
+    ///
+    /// This doesn't disable a [Background::Checker].
+    /// The scene is composited over the checker, so an opaque color hides it.
+    /// Use [Renderer::set_background] to switch between backgrounds.
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    /// Set the background behind the scene.
+    /// This replaces the clear color from [Renderer::set_clear_color].
+    pub fn set_background(&mut self, queue: &wgpu::Queue, background: Background) {
+        match background {
+            Background::Color(color) => {
+                self.clear_color = color;
+                self.post_process.checker_size = 0.0;
+            }
+            Background::Checker(checker) => {
+                // The checker shows through wherever the scene is transparent.
+                self.clear_color = wgpu::Color::TRANSPARENT;
+                self.post_process.checker_size = checker.size.max(1.0);
+                self.post_process.checker_color0 = checker.colors[0].into();
+                self.post_process.checker_color1 = checker.colors[1].into();
+            }
+        }
+        self.update_post_process(queue);
+    }
+
+    /// Add a glow around colors brighter than `threshold`.
+    /// An `intensity` of `0.0` disables bloom and skips the bloom passes.
+    pub fn set_bloom(&mut self, queue: &wgpu::Queue, threshold: f32, intensity: f32) {
+        queue.write_buffer(
+            &self.bloom_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[shader::bloom::BloomSettings { threshold }]),
+        );
+
+        self.post_process.bloom_intensity = intensity.max(0.0);
+        self.update_post_process(queue);
+    }
+
+    /// Enable or disable screen space contact shadows.
+    /// This only uses the depth buffer and works on all supported hardware.
+    pub fn set_contact_shadows(&mut self, queue: &wgpu::Queue, settings: Option<ContactShadows>) {
+        match settings {
+            Some(settings) => {
+                self.post_process.light_direction =
+                    settings.light_direction.normalize_or_zero().extend(0.0);
+                self.post_process.contact_shadow_intensity = settings.intensity.clamp(0.0, 1.0);
+                self.post_process.contact_shadow_length = settings.length;
+                self.post_process.contact_shadow_thickness = settings.thickness;
+            }
+            None => self.post_process.contact_shadow_intensity = 0.0,
+        }
+        self.update_post_process(queue);
+    }
+
+    /// Enable or disable screen space ambient occlusion.
+    pub fn set_ssao(&mut self, queue: &wgpu::Queue, settings: Option<Ssao>) {
+        match settings {
+            Some(settings) => {
+                self.post_process.ssao_radius = settings.radius;
+                self.post_process.ssao_intensity = settings.intensity.clamp(0.0, 1.0);
+                self.post_process.ssao_samples = settings.samples.clamp(1, 64);
+            }
+            None => self.post_process.ssao_intensity = 0.0,
+        }
+        self.update_post_process(queue);
+    }
+
+    /// Enable or disable a noise overlay applied after all other post processing.
+    pub fn set_film_grain(&mut self, queue: &wgpu::Queue, settings: Option<FilmGrain>) {
+        match settings {
+            Some(settings) => {
+                self.post_process.grain_intensity = settings.intensity.clamp(0.0, 1.0);
+                self.post_process.grain_size = settings.size;
+                self.post_process.grain_seed = settings.seed;
+                self.post_process.grain_blend = match settings.blend {
+                    GrainBlend::Overlay => 0,
+                    GrainBlend::Multiply => 1,
+                    GrainBlend::Add => 2,
+                };
+            }
+            None => self.post_process.grain_intensity = 0.0,
+        }
+        self.update_post_process(queue);
+    }
+
+    /// Set the lighting for the model shading.
+    /// Rotate the `direction` to move the key light relative to the model.
+    pub fn set_light(&self, queue: &wgpu::Queue, light: &LightSettings) {
+        queue.write_buffer(
+            &self.light_buffer,
+            0,
+            bytemuck::cast_slice(&[model_light(light)]),
+        );
+    }
+
+    /// Set the linear RGB color for instances selected with [RenderData::set_selected].
+    /// The alpha controls how much the color replaces the shaded color from `0.0` to `1.0`.
+    pub fn set_selection_color(&self, queue: &wgpu::Queue, color: [f32; 4]) {
+        queue.write_buffer(
+            &self.highlight_buffer,
+            0,
+            bytemuck::cast_slice(&[Vec4::from(color).clamp(Vec4::ZERO, Vec4::ONE)]),
+        );
+    }
+
+    /// Set the width of edge lines in pixels.
+    /// Edges are antialiased, so fractional widths like the default of `1.5` are supported.
+    pub fn set_edge_width(&self, queue: &wgpu::Queue, pixels: f32) {
+        queue.write_buffer(
+            &self.edge_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[shader::edge::EdgeSettings {
+                width: pixels.max(0.0),
+            }]),
+        );
+    }
+
+    /// Enable or disable distance fog.
+    /// Fog uses the depth of opaque geometry and doesn't affect the background.
+    pub fn set_fog(&mut self, queue: &wgpu::Queue, fog: Option<Fog>) {
+        match fog {
+            Some(fog) => {
+                self.post_process.fog_color = Vec3::from(fog.color).extend(1.0);
+                self.post_process.fog_start = fog.start.max(0.0);
+                self.post_process.fog_density = fog.density.max(0.0);
+            }
+            None => self.post_process.fog_density = 0.0,
+        }
+        self.update_post_process(queue);
+    }
+
+    /// Enable or disable a shadow catching ground plane.
+    /// Shadows are traced in screen space, so only occluders visible on screen cast shadows.
+    pub fn set_ground_plane(&mut self, queue: &wgpu::Queue, ground: Option<GroundPlane>) {
+        match ground {
+            Some(ground) => {
+                // Premultiplied alpha.
+                let [r, g, b, a] = ground.color;
+                self.post_process.ground_color = vec4(r * a, g * a, b * a, a);
+                self.post_process.ground_light_direction =
+                    ground.light_direction.normalize_or_zero().extend(0.0);
+                self.post_process.ground_axis = ground.up_axis.axis().extend(0.0);
+                self.post_process.ground_height = ground.height;
+                self.post_process.ground_shadow_length = ground.shadow_length.max(0.0);
+            }
+            None => self.post_process.ground_color = Vec4::ZERO,
+        }
+        self.update_post_process(queue);
+    }
+
+    /// Enable or disable outlines based on the depth and normals of the final frame.
+    pub fn set_outline(&mut self, queue: &wgpu::Queue, outline: Option<OutlineSettings>) {
+        match outline {
+            Some(outline) => {
+                // Premultiplied alpha.
+                let [r, g, b, a] = outline.color;
+                self.post_process.outline_color = vec4(r * a, g * a, b * a, a);
+                self.post_process.outline_thickness = outline.thickness.max(0.0);
+                self.post_process.outline_depth_threshold = outline.depth_threshold.max(0.0);
+                self.post_process.outline_normal_threshold =
+                    outline.normal_threshold.to_radians().cos();
+            }
+            None => self.post_process.outline_thickness = 0.0,
+        }
+        self.update_post_process(queue);
+    }
+
+    /// Set how colors are mapped to the output range in the final blit.
+    /// Rendering always uses a float format internally, so only the output format limits the range.
+    pub fn set_tonemap(&mut self, queue: &wgpu::Queue, tonemap: Tonemap) {
+        self.post_process.tonemap = match tonemap {
+            Tonemap::None => 0,
+            Tonemap::Reinhard => 1,
+            Tonemap::Aces => 2,
+        };
+        self.update_post_process(queue);
+    }
+
+    /// Apply the screen space effects from `settings`.
+    /// The other settings are applied when loading the scene.
+    pub fn set_quality(&mut self, queue: &wgpu::Queue, settings: &QualitySettings) {
+        self.set_ssao(queue, settings.ssao);
+        self.set_contact_shadows(queue, settings.contact_shadows);
+    }
+
+    /// Show or hide a reference grid to help judge the size of parts.
+    pub fn set_grid(&mut self, queue: &wgpu::Queue, grid: Option<Grid>) {
+        match grid {
+            Some(grid) if grid.spacing > 0.0 && grid.extent > 0.0 => {
+                // Use an odd number of lines to always include a line through the origin.
+                let half_count = (grid.extent / grid.spacing).floor() as u32;
+                let line_count = half_count * 2 + 1;
+
+                queue.write_buffer(
+                    &self.grid_buffer,
+                    0,
+                    bytemuck::cast_slice(&[shader::grid::Grid {
+                        color: grid.color.into(),
+                        height: grid.height,
+                        spacing: grid.spacing,
+                        extent: half_count as f32 * grid.spacing,
+                        line_count,
+                    }]),
+                );
+
+                // Each axis has line_count lines with two vertices each.
+                self.grid_vertex_count = line_count * 2 * 2;
+            }
+            _ => self.grid_vertex_count = 0,
+        }
+    }
+
+    fn update_post_process(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.post_process_buffer,
+            0,
+            bytemuck::cast_slice(&[self.post_process]),
+        );
+    }
+
+    fn uses_final_depth(&self) -> bool {
+        self.post_process.contact_shadow_intensity > 0.0
+            || self.post_process.ssao_intensity > 0.0
+            || self.post_process.fog_density > 0.0
+            || self.post_process.ground_color.w > 0.0
+            || self.post_process.outline_thickness > 0.0
+    }
+
+    /// Update the camera for the next frame.
+    /// Any accumulated samples are discarded if the camera changed.
+    pub fn update_camera(&mut self, queue: &wgpu::Queue, camera_data: &CameraData) {
+        if *camera_data != self.camera_data {
+            self.reset_accumulation();
         }
+        self.camera_data = *camera_data;
+
+        self.write_model_camera(queue, camera_data);
+        queue.write_buffer(
+            &self.camera_culling_buffer,
+            0,
+            bytemuck::cast_slice(&[culling_camera(camera_data)]),
+        );
+        queue.write_buffer(
+            &self.post_process_camera_buffer,
+            0,
+            bytemuck::cast_slice(&[post_process_camera(camera_data)]),
+        );
     }
 
-    pub fn update_camera(&self, queue: &wgpu::Queue, camera_data: &CameraData) {
+    fn write_model_camera(&self, queue: &wgpu::Queue, camera_data: &CameraData) {
         queue.write_buffer(
             &self.camera_buffer,
             0,
@@ -319,43 +1639,181 @@ impl Renderer {
             }]),
         );
         queue.write_buffer(
-            &self.camera_culling_buffer,
+            &self.edge_camera_buffer,
             0,
-            bytemuck::cast_slice(&[shader::culling::Camera {
-                z_near: Z_NEAR,
-                z_far: Z_FAR,
-                p00: camera_data.p00,
-                p11: camera_data.p11,
-                frustum: camera_data.frustum,
-                view_projection: camera_data.view_projection,
-                view: camera_data.view,
-            }]),
+            bytemuck::cast_slice(&[edge_camera(camera_data)]),
         );
     }
 
+    /// Average up to `max_samples` frames with subpixel jitter while the camera is stationary.
+    /// This progressively reduces aliasing for still images at the cost of an extra texture.
+    /// Use `None` to render each frame independently.
+    ///
+    /// Samples reset when [Renderer::update_camera] changes the camera, when resizing,
+    /// or when rendering a different [RenderData] or after changing its instances.
+    /// Call [Renderer::reset_accumulation] after other changes like lighting.
+    /// Once all samples are averaged, frames only reapply post-processing.
+    pub fn set_accumulation(&mut self, device: &wgpu::Device, max_samples: Option<u32>) {
+        self.max_accumulated_samples = max_samples.map(|samples| samples.max(1));
+        self.resize(device, self.width, self.height, self.output_format);
+    }
+
+    /// Discard the accumulated samples and start averaging again from the next frame.
+    pub fn reset_accumulation(&mut self) {
+        if let Some(accumulation) = &mut self.accumulation {
+            accumulation.samples = 0;
+        }
+    }
+
+    /// The number of frames averaged in the current output or `0` if accumulation is disabled.
+    pub fn accumulated_samples(&self) -> u32 {
+        self.accumulation.as_ref().map_or(0, |a| a.samples)
+    }
+
+    /// Measure the GPU time of each pass with timestamp queries for [Renderer::last_frame_timings].
+    /// This has no effect unless the device was created with the [gpu_timing_features].
+    pub fn set_gpu_timing(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, enabled: bool) {
+        let supported = device.features().contains(wgpu::Features::TIMESTAMP_QUERY);
+        self.timer = (enabled && supported).then(|| GpuTimer::new(device, queue));
+    }
+
+    /// The GPU time for each pass label in a recent frame if enabled with [Renderer::set_gpu_timing].
+    /// Passes that run more than once per frame like the visibility pass report the total time.
+    ///
+    /// Results are read back without stalling rendering, so they may lag behind by a few frames.
+    pub fn last_frame_timings(&self) -> HashMap<&'static str, Duration> {
+        self.timer
+            .as_ref()
+            .map(|timer| timer.last_frame().clone())
+            .unwrap_or_default()
+    }
+
+    fn compute_timestamps(
+        &self,
+        label: &'static str,
+    ) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        self.timer
+            .as_ref()
+            .and_then(|timer| timer.compute_pass(label))
+    }
+
+    fn render_timestamps(
+        &self,
+        label: &'static str,
+    ) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.timer
+            .as_ref()
+            .and_then(|timer| timer.render_pass(label))
+    }
+
+    /// Set whether to draw shaded faces, edge lines, or both.
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        if mode != self.render_mode {
+            self.reset_accumulation();
+        }
+        self.render_mode = mode;
+    }
+
+    /// Enable or disable frustum and occlusion culling.
+    ///
+    /// Disabling culling draws every object that isn't hidden,
+    /// which helps check if a missing part is culled incorrectly.
+    pub fn set_culling_enabled(&mut self, enabled: bool) {
+        self.culling_enabled = enabled;
+    }
+
+    /// Set the number of MSAA samples for the model, edge, and grid passes.
+    /// Use [supported_sample_count] to choose a count supported by the adapter.
+    ///
+    /// This recreates the pipelines and multisampled textures but not the scene data.
+    /// Prefer passing the sample count to [Renderer::new] to avoid creating the pipelines twice.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: SampleCount) {
+        let sample_count = sample_count.get();
+        if sample_count == self.sample_count {
+            return;
+        }
+        self.sample_count = sample_count;
+
+        self.model_pipeline = create_pipeline(device, COLOR_FORMAT, sample_count);
+        self.model_depth_pipeline = create_depth_pipeline(device, COLOR_FORMAT, sample_count);
+        self.model_back_faces_pipeline =
+            create_transparent_pipeline(device, COLOR_FORMAT, sample_count, wgpu::Face::Front);
+        self.model_front_faces_pipeline =
+            create_transparent_pipeline(device, COLOR_FORMAT, sample_count, wgpu::Face::Back);
+        self.model_edges_pipeline = create_edge_pipeline(device, COLOR_FORMAT, sample_count);
+        self.grid_pipeline = create_grid_pipeline(device, COLOR_FORMAT, sample_count);
+
+        self.resize(device, self.width, self.height, self.output_format);
+    }
+
+    /// Render at `factor` times the output resolution and downsample in the final blit.
+    /// Higher factors reduce aliasing at the cost of performance and memory.
+    ///
+    /// The factor is at least `1.0` and limited by the device's maximum texture size.
+    pub fn set_supersample_factor(&mut self, device: &wgpu::Device, factor: f32) {
+        self.supersample = factor;
+        self.resize(device, self.width, self.height, self.output_format);
+    }
+
+    /// Resize the render targets for an output with size `output_width` and `output_height`.
     pub fn resize(
         &mut self,
         device: &wgpu::Device,
-        width: u32,
-        height: u32,
+        output_width: u32,
+        output_height: u32,
         output_format: wgpu::TextureFormat,
     ) {
-        if width > 0 && height > 0 {
+        if output_width > 0 && output_height > 0 {
+            self.width = output_width;
+            self.height = output_height;
+
+            // Everything except the output uses the supersampled size.
+            let (width, height) = supersampled_size(
+                output_width,
+                output_height,
+                self.supersample,
+                device.limits().max_texture_dimension_2d,
+            );
+
             // Update each resource that depends on window size.
-            let (depth_texture, depth_view) = create_depth_texture(device, width, height);
+            let (depth_texture, depth_view) =
+                create_depth_texture(device, width, height, self.sample_count);
             self.depth_texture = depth_texture;
             self.depth_view = depth_view;
 
             self.depth_pyramid = create_depth_pyramid(device, width, height, &self.depth_view);
 
-            self.output_view_msaa = create_output_msaa_view(device, width, height, output_format);
+            self.output_view_msaa =
+                create_output_msaa_view(device, width, height, COLOR_FORMAT, self.sample_count);
+            self.color_view = create_color_view(device, width, height);
 
-            let depth_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-                min_filter: wgpu::FilterMode::Nearest,
-                mag_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
+            // Only the final blit depends on the output format.
+            if output_format != self.output_format {
+                self.blit_pipeline = create_blit_pipeline(device, output_format);
+                self.output_format = output_format;
+            }
+
+            self.bloom = create_bloom(
+                device,
+                width,
+                height,
+                &self.color_view,
+                &self.bloom_settings_buffer,
+            );
+            // Accumulated frames are no longer valid after resizing.
+            self.accumulation = self.max_accumulated_samples.map(|max_samples| {
+                create_accumulation(device, width, height, &self.color_view, max_samples)
             });
+            self.blit_bind_group0 = create_blit_bind_group(
+                device,
+                self.accumulation
+                    .as_ref()
+                    .map_or(&self.color_view, |a| &a.view),
+                &self.bloom.view,
+                &self.depth_pyramid.base_level,
+                &self.post_process_buffer,
+                &self.post_process_camera_buffer,
+            );
 
             // The textures were updated, so use views pointing to the new textures.
             self.culling_bind_group0 = shader::culling::bind_groups::BindGroup0::from_bindings(
@@ -363,7 +1821,6 @@ impl Renderer {
                 shader::culling::bind_groups::BindGroupLayout0 {
                     camera: self.camera_culling_buffer.as_entire_buffer_binding(),
                     depth_pyramid: &self.depth_pyramid.all_mips,
-                    depth_sampler: &depth_sampler,
                 },
             );
         }
@@ -376,98 +1833,513 @@ impl Renderer {
         render_data: &mut RenderData,
         output_view: &wgpu::TextureView,
     ) {
-        let encoder = self.render_scene(device, queue, render_data, output_view);
-
-        queue.submit(std::iter::once(encoder.finish()));
+        self.render_with_post_process(device, queue, render_data, output_view, |_| ());
     }
 
-    fn render_scene(
-        &mut self,
+    /// Find the closest instance under the point `ndc_x` and `ndc_y` from `-1.0` to `1.0`
+    /// for the camera from [Renderer::update_camera].
+    /// Returns `None` if only the background is under the point.
+    ///
+    /// Instances hidden with [VisibilityOverride::Hidden] can't be picked.
+    /// This renders the faces covering a single pixel and blocks until the result is read back,
+    /// so it's intended for occasional interactions like clicking rather than every frame.
+    pub fn pick(
+        &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        render_data: &mut RenderData,
-        output_view: &wgpu::TextureView,
-    ) -> wgpu::CommandEncoder {
-        // Use a two pass conservative culling scheme introduced in the following paper:
-        // "Patch-Based Occlusion Culling for Hardware Tessellation"
-        // http://www.graphics.stanford.edu/~niessner/papers/2012/2occlusion/niessner2012patch.pdf
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
+        render_data: &RenderData,
+        ndc_x: f32,
+        ndc_y: f32,
+    ) -> Option<PickResult> {
+        let draws = render_data.scene.unhidden_draws();
+        if draws.is_empty() {
+            return None;
+        }
+
+        let ndc = vec2(ndc_x, ndc_y);
+        queue.write_buffer(
+            &self.pick_camera_buffer,
+            0,
+            bytemuck::cast_slice(&[shader::model::Camera {
+                view_projection: pick_view_projection(&self.camera_data, ndc),
+                position: self.camera_data.position,
+            }]),
+        );
+
+        // Draw without culling since the last frame may have culled newly visible parts.
+        let indirect_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("pick indirect buffer"),
+            contents: bytemuck::cast_slice(&draws),
+            usage: wgpu::BufferUsages::INDIRECT,
         });
 
-        self.set_visibility_pass(&mut encoder, render_data, false);
+        let texture = create_pick_texture(device);
+        let view = texture.create_view(&Default::default());
+        let (_, depth_view) = create_depth_texture(device, 1, 1, 1);
 
-        // The synchronization and copies aren't necessary if indirect count is supported.
-        if !self.supports_indirect_count {
-            encoder.copy_buffer_to_buffer(
-                &render_data.scene.compacted_count_buffer,
-                0,
-                &render_data.scene.compacted_count_staging_buffer,
-                0,
-                render_data.scene.compacted_count_staging_buffer.size(),
-            );
-            // Submit to make sure the copy finishes.
-            queue.submit(std::iter::once(encoder.finish()));
-            self.update_compacted_draw_count(device, render_data);
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pick staging buffer"),
+            size: std::mem::size_of::<[u32; 2]>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
 
-            encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder 2"),
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Pick Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Pick Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_view,
+                    depth_ops: Some(depth_op_reversed()),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
             });
+
+            render_pass.set_pipeline(&self.pick_pipeline);
+            shader::model::set_bind_groups(
+                &mut render_pass,
+                &self.pick_bind_group0,
+                &render_data.model_bind_group1,
+            );
+            draw_indirect_uncompacted(
+                &mut render_pass,
+                &render_data.scene,
+                &render_data.scene.solid.chunks,
+                &indirect_buffer,
+            );
         }
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &staging_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        queue.submit([encoder.finish()]);
 
-        // TODO: Draw transparent twice with front faces and then back faces culled?
-        // TODO: Fix high contrast studs (manually add stud files to ldr_tools)
-        // TODO: Port right click pan from ssbh_wgpu
-        // Draw everything that was visible last frame.
-        self.model_pass(&mut encoder, output_view, render_data, true);
+        let buffer_slice = staging_buffer.slice(..);
+        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
 
-        // Apply culling to set visibility and enable newly visible objects.
-        self.depth_pyramid_pass(&mut encoder);
-        self.occlusion_culling_pass(&mut encoder, render_data);
-        self.set_visibility_pass(&mut encoder, render_data, true);
+        device.poll(wgpu::Maintain::Wait);
 
-        if !self.supports_indirect_count {
-            // Make sure the staging buffer is set up for the next compaction operation.
-            encoder.copy_buffer_to_buffer(
-                &render_data.scene.compacted_count_buffer,
-                0,
-                &render_data.scene.compacted_count_staging_buffer,
-                0,
-                render_data.scene.compacted_count_staging_buffer.size(),
-            );
-            // Submit to make sure the copy completes.
-            queue.submit(std::iter::once(encoder.finish()));
-            self.update_compacted_draw_count(device, render_data);
+        block_on(receiver.receive())?.ok()?;
 
-            encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder 3"),
-            });
-        }
+        let data = buffer_slice.get_mapped_range();
+        let [id, depth]: [u32; 2] = bytemuck::pod_read_unaligned(&data);
+        drop(data);
+        staging_buffer.unmap();
 
-        // Draw everything that is newly visible in this frame.
-        self.model_pass(&mut encoder, output_view, render_data, false);
-        encoder
+        // The shader writes the instance plus one to reserve 0 for the background.
+        let instance_index = id.checked_sub(1)? as usize;
+        let world_pos = self
+            .camera_data
+            .view_projection
+            .inverse()
+            .project_point3(ndc.extend(f32::from_bits(depth)));
+        Some(PickResult {
+            instance_index,
+            distance: world_pos.distance(self.camera_data.position.truncate()),
+            world_pos,
+        })
     }
 
-    fn model_pass(
+    /// Render the scene to an offscreen texture and read back the result.
+    /// This doesn't require a window or surface and is useful for generating thumbnails.
+    ///
+    /// The renderer is resized to `width` and `height` if needed,
+    /// so the camera aspect ratio should already match the requested size.
+    /// The output format must have 4 bytes per pixel like [wgpu::TextureFormat::Rgba8UnormSrgb].
+    /// The image uses premultiplied alpha like the output of [Renderer::render].
+    pub fn render_to_image(
         &mut self,
-        encoder: &mut wgpu::CommandEncoder,
-        output_view: &wgpu::TextureView,
-        render_data: &RenderData,
-        first_pass: bool,
-    ) {
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some(if first_pass {
-                "Visible Pass"
-            } else {
-                "Previously Visible Pass"
-            }),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.output_view_msaa,
-                resolve_target: Some(output_view),
-                ops: wgpu::Operations {
-                    load: if first_pass {
-                        wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_data: &mut RenderData,
+        width: u32,
+        height: u32,
+    ) -> image::RgbaImage {
+        if width != self.width || height != self.height {
+            self.resize(device, width, height, self.output_format);
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let output = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("image output texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.output_format,
+            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let output_view = output.create_view(&Default::default());
+
+        // Rows must be aligned, so the buffer may be larger than the image.
+        let bytes_per_row = padded_bytes_per_row(width);
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("image output buffer"),
+            size: bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        // Converge the accumulated image before the final frame.
+        self.update_render_data_version(render_data);
+        let remaining = self
+            .accumulation
+            .as_ref()
+            .map_or(0, |a| a.max_samples.saturating_sub(a.samples + 1));
+        for _ in 0..remaining {
+            self.render(device, queue, render_data, &output_view);
+        }
+
+        self.render_with_post_process(device, queue, render_data, &output_view, |encoder| {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &output,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &output_buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(bytes_per_row),
+                        rows_per_image: Some(height),
+                    },
+                },
+                size,
+            );
+        });
+
+        // Adapted from WGPU Example https://github.com/gfx-rs/wgpu/tree/master/wgpu/examples/capture
+        let buffer_slice = output_buffer.slice(..);
+        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        block_on(receiver.receive()).unwrap().unwrap();
+
+        let mut pixels = unpadded_rows(&buffer_slice.get_mapped_range(), width, height);
+        output_buffer.unmap();
+
+        if matches!(
+            self.output_format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        image::RgbaImage::from_raw(width, height, pixels).unwrap()
+    }
+
+    /// Render the scene to `output_view` and record additional commands with `post_process`
+    /// before submitting.
+    ///
+    /// The output view has the `output_format` passed to [Renderer::new]
+    /// and contains premultiplied alpha colors after the scene passes resolve.
+    /// Create the output texture with [wgpu::TextureUsages::TEXTURE_BINDING]
+    /// to sample it in a custom effect that writes to the final surface.
+    /// Commands recorded by `post_process` execute after all scene rendering.
+    pub fn render_with_post_process<F>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_data: &mut RenderData,
+        output_view: &wgpu::TextureView,
+        post_process: F,
+    ) where
+        F: FnOnce(&mut wgpu::CommandEncoder),
+    {
+        let mut encoder = self.render_scene(device, queue, render_data, output_view);
+
+        post_process(&mut encoder);
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        // Buffers can only be mapped after submitting the commands that write to them.
+        if let Some(timer) = &mut self.timer {
+            timer.map();
+        }
+    }
+
+    fn render_scene(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_data: &mut RenderData,
+        output_view: &wgpu::TextureView,
+    ) -> wgpu::CommandEncoder {
+        if let Some(timer) = &mut self.timer {
+            timer.begin_frame(device);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+
+        self.update_render_data_version(render_data);
+
+        // The converged scene doesn't change until accumulation resets.
+        let converged = self
+            .accumulation
+            .as_ref()
+            .is_some_and(|a| a.samples >= a.max_samples);
+        if !converged {
+            encoder = self.scene_passes(device, queue, render_data, encoder);
+        }
+
+        // The depth pyramid only contains previously visible objects at this point.
+        // Copy the final depth for effects that need the depth of the entire frame.
+        // This doesn't affect culling since the pyramid is regenerated each frame.
+        if self.uses_final_depth() {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Blit Depth Pass"),
+                timestamp_writes: self.compute_timestamps("Blit Depth Pass"),
+            });
+            self.blit_depth(&mut compute_pass);
+        }
+
+        // Edge lines already mark creases, so avoid outlining the same creases twice.
+        let outline_normals = (self.render_mode == RenderMode::Shaded) as u32;
+        if self.post_process.outline_normals != outline_normals {
+            self.post_process.outline_normals = outline_normals;
+            self.update_post_process(queue);
+        }
+
+        if self.post_process.bloom_intensity > 0.0 {
+            self.bloom_pass(&mut encoder);
+        }
+        self.blit_pass(&mut encoder, output_view);
+
+        if let Some(timer) = &self.timer {
+            timer.resolve(&mut encoder);
+        }
+
+        encoder
+    }
+
+    fn update_render_data_version(&mut self, render_data: &RenderData) {
+        if render_data.version != self.render_data_version {
+            // The accumulated samples are for a different scene or outdated instances.
+            self.reset_accumulation();
+            self.render_data_version = render_data.version;
+        }
+    }
+
+    fn scene_passes(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_data: &mut RenderData,
+        mut encoder: wgpu::CommandEncoder,
+    ) -> wgpu::CommandEncoder {
+        // Use a two pass conservative culling scheme introduced in the following paper:
+        // "Patch-Based Occlusion Culling for Hardware Tessellation"
+        // http://www.graphics.stanford.edu/~niessner/papers/2012/2occlusion/niessner2012patch.pdf
+        if let Some(accumulation) = &self.accumulation {
+            // Each sample covers a different part of the pixel.
+            // Culling still uses the original camera since the offset is tiny.
+            let camera_data = jittered_camera(&self.camera_data, accumulation.samples);
+            self.write_model_camera(queue, &camera_data);
+        }
+
+        // Transparent draws blend correctly only if drawn from back to front.
+        render_data
+            .scene
+            .sort_transparent_draws(queue, self.camera_data.position.truncate());
+
+        self.set_visibility_pass(&mut encoder, render_data, false);
+
+        // The synchronization and copies aren't necessary if indirect count is supported.
+        if !self.supports_indirect_count {
+            encoder.copy_buffer_to_buffer(
+                &render_data.scene.compacted_count_buffer,
+                0,
+                &render_data.scene.compacted_count_staging_buffer,
+                0,
+                render_data.scene.compacted_count_staging_buffer.size(),
+            );
+            // Submit to make sure the copy finishes.
+            queue.submit(std::iter::once(encoder.finish()));
+            self.update_compacted_draw_count(device, render_data);
+
+            encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder 2"),
+            });
+        }
+
+        // TODO: Fix high contrast studs (manually add stud files to ldr_tools)
+        // Draw everything that was visible last frame.
+        self.model_pass(&mut encoder, render_data, true);
+
+        // Apply culling to set visibility and enable newly visible objects.
+        if self.culling_enabled {
+            self.depth_pyramid_pass(&mut encoder);
+            self.occlusion_culling_pass(&mut encoder, render_data);
+        } else {
+            self.all_visible_pass(&mut encoder, render_data);
+        }
+        copy_culling_results(&mut encoder, &render_data.scene);
+        self.set_visibility_pass(&mut encoder, render_data, true);
+
+        if !self.supports_indirect_count {
+            // Make sure the staging buffer is set up for the next compaction operation.
+            encoder.copy_buffer_to_buffer(
+                &render_data.scene.compacted_count_buffer,
+                0,
+                &render_data.scene.compacted_count_staging_buffer,
+                0,
+                render_data.scene.compacted_count_staging_buffer.size(),
+            );
+            // Submit to make sure the copy completes.
+            queue.submit(std::iter::once(encoder.finish()));
+            self.update_compacted_draw_count(device, render_data);
+
+            encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder 3"),
+            });
+        }
+
+        // Draw everything that is newly visible in this frame.
+        self.model_pass(&mut encoder, render_data, false);
+
+        if self.accumulation.is_some() {
+            self.accumulate_pass(&mut encoder);
+        }
+
+        encoder
+    }
+
+    fn bloom_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Bloom Pass"),
+            timestamp_writes: self.compute_timestamps("Bloom Pass"),
+        });
+
+        // Assume the workgroups are 2D.
+        let [size_x, size_y, _] = shader::bloom::compute::THRESHOLD_WORKGROUP_SIZE;
+        let count_x = div_round_up(self.bloom.width, size_x);
+        let count_y = div_round_up(self.bloom.height, size_y);
+
+        compute_pass.set_pipeline(&self.bloom_threshold_pipeline);
+        shader::bloom::set_bind_groups(&mut compute_pass, &self.bloom.threshold_bind_group);
+        compute_pass.dispatch_workgroups(count_x, count_y, 1);
+
+        // A separable blur is much cheaper than a single 2D blur with the same radius.
+        compute_pass.set_pipeline(&self.bloom_horizontal_pipeline);
+        shader::bloom::set_bind_groups(&mut compute_pass, &self.bloom.horizontal_bind_group);
+        compute_pass.dispatch_workgroups(count_x, count_y, 1);
+
+        compute_pass.set_pipeline(&self.bloom_vertical_pipeline);
+        shader::bloom::set_bind_groups(&mut compute_pass, &self.bloom.vertical_bind_group);
+        compute_pass.dispatch_workgroups(count_x, count_y, 1);
+    }
+
+    fn accumulate_pass(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(accumulation) = &mut self.accumulation else {
+            return;
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Accumulate Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &accumulation.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // The first sample has a weight of 1.0 and replaces the previous contents.
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            // Borrow the timer separately from the mutable accumulation.
+            timestamp_writes: self
+                .timer
+                .as_ref()
+                .and_then(|timer| timer.render_pass("Accumulate Pass")),
+            occlusion_query_set: None,
+        });
+
+        let weight = 1.0 / (accumulation.samples + 1) as f64;
+        render_pass.set_pipeline(&self.accumulate_pipeline);
+        render_pass.set_blend_constant(wgpu::Color {
+            r: weight,
+            g: weight,
+            b: weight,
+            a: weight,
+        });
+        shader::accumulate::set_bind_groups(&mut render_pass, &accumulation.bind_group);
+        render_pass.draw(0..3, 0..1);
+
+        accumulation.samples += 1;
+    }
+
+    fn blit_pass(&self, encoder: &mut wgpu::CommandEncoder, output_view: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: self.render_timestamps("Blit Pass"),
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_pipeline(&self.blit_pipeline);
+        shader::blit::set_bind_groups(&mut render_pass, &self.blit_bind_group0);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn model_pass(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_data: &RenderData,
+        first_pass: bool,
+    ) {
+        let label = if first_pass {
+            "Visible Pass"
+        } else {
+            "Previously Visible Pass"
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.output_view_msaa,
+                resolve_target: Some(&self.color_view),
+                ops: wgpu::Operations {
+                    load: if first_pass {
+                        wgpu::LoadOp::Clear(self.clear_color)
                     } else {
                         wgpu::LoadOp::Load
                     },
@@ -486,27 +2358,72 @@ impl Renderer {
                 }),
                 stencil_ops: None,
             }),
-            timestamp_writes: None,
+            timestamp_writes: self.render_timestamps(label),
             occlusion_query_set: None,
         });
 
-        shader::model::set_bind_groups(&mut render_pass, &self.bind_group0);
-
-        render_pass.set_pipeline(&self.model_pipeline);
-        draw_indirect(
+        shader::model::set_bind_groups(
             &mut render_pass,
-            &render_data.scene,
-            &render_data.scene.solid,
-            self.supports_indirect_count,
+            &self.bind_group0,
+            &render_data.model_bind_group1,
         );
 
-        render_pass.set_pipeline(&self.model_edges_pipeline);
+        // Wireframe renders still need depth for occluding edges and occlusion culling.
+        render_pass.set_pipeline(match self.render_mode {
+            RenderMode::Wireframe => &self.model_depth_pipeline,
+            RenderMode::Shaded | RenderMode::ShadedWithEdges => &self.model_pipeline,
+        });
         draw_indirect(
             &mut render_pass,
             &render_data.scene,
-            &render_data.scene.edges,
+            &render_data.scene.solid,
             self.supports_indirect_count,
         );
+
+        // Transparent draws are sorted back to front and drawn after everything opaque.
+        // Each part draws its back faces and then its front faces before the next part,
+        // which avoids sorting triangles within a part without breaking the part order.
+        if !first_pass {
+            let pipelines: &[&wgpu::RenderPipeline] = match self.render_mode {
+                RenderMode::Wireframe => &[&self.model_depth_pipeline],
+                RenderMode::Shaded | RenderMode::ShadedWithEdges => &[
+                    &self.model_back_faces_pipeline,
+                    &self.model_front_faces_pipeline,
+                ],
+            };
+            draw_indirect_transparent(
+                &mut render_pass,
+                &render_data.scene,
+                &render_data.scene.solid,
+                pipelines,
+            );
+        }
+
+        if self.render_mode != RenderMode::Shaded {
+            render_pass.set_pipeline(&self.model_edges_pipeline);
+            shader::edge::set_bind_groups(&mut render_pass, &self.edge_bind_group0);
+            draw_indirect(
+                &mut render_pass,
+                &render_data.scene,
+                &render_data.scene.edges,
+                self.supports_indirect_count,
+            );
+            if !first_pass {
+                draw_indirect_transparent(
+                    &mut render_pass,
+                    &render_data.scene,
+                    &render_data.scene.edges,
+                    &[&self.model_edges_pipeline],
+                );
+            }
+        }
+
+        // The grid isn't an occluder, so only draw it once all objects are visible.
+        if !first_pass && self.grid_vertex_count > 0 {
+            render_pass.set_pipeline(&self.grid_pipeline);
+            shader::grid::set_bind_groups(&mut render_pass, &self.grid_bind_group0);
+            render_pass.draw(0..self.grid_vertex_count, 0..1);
+        }
     }
 
     fn update_compacted_draw_count(&mut self, device: &wgpu::Device, render_data: &mut RenderData) {
@@ -522,9 +2439,13 @@ impl Renderer {
             let data = buffer_slice.get_mapped_range();
             let counts: &[u32] = bytemuck::cast_slice(&data);
 
-            let draw_count = counts[0];
-            render_data.scene.solid.compacted_draw_count = draw_count;
-            render_data.scene.edges.compacted_draw_count = draw_count;
+            // Solid and edge draws use the same chunks and visibility.
+            let solid = &mut render_data.scene.solid.chunks;
+            let edges = &mut render_data.scene.edges.chunks;
+            for ((solid, edges), count) in solid.iter_mut().zip(edges).zip(counts) {
+                solid.compacted_draw_count = *count;
+                edges.compacted_draw_count = *count;
+            }
 
             drop(data);
             render_data.scene.compacted_count_staging_buffer.unmap();
@@ -534,7 +2455,7 @@ impl Renderer {
     fn occlusion_culling_pass(&self, encoder: &mut wgpu::CommandEncoder, render_data: &RenderData) {
         let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("Occlusion Culling Pass"),
-            timestamp_writes: None,
+            timestamp_writes: self.compute_timestamps("Occlusion Culling Pass"),
         });
 
         compute_pass.set_pipeline(&self.culling_pipeline);
@@ -550,6 +2471,26 @@ impl Renderer {
         compute_pass.dispatch_workgroups(count, 1, 1);
     }
 
+    fn all_visible_pass(&self, encoder: &mut wgpu::CommandEncoder, render_data: &RenderData) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("All Visible Pass"),
+            timestamp_writes: self.compute_timestamps("All Visible Pass"),
+        });
+
+        // Still apply visibility overrides and move transparent draws to the second pass.
+        compute_pass.set_pipeline(&self.all_visible_pipeline);
+        shader::culling::set_bind_groups(
+            &mut compute_pass,
+            &self.culling_bind_group0,
+            &render_data.culling_bind_group1,
+        );
+
+        // Assume the workgroup is 1D.
+        let [size_x, _, _] = shader::culling::compute::ALL_VISIBLE_WORKGROUP_SIZE;
+        let count = div_round_up(render_data.scene.solid.draw_count, size_x);
+        compute_pass.dispatch_workgroups(count, 1, 1);
+    }
+
     fn set_visibility_pass(
         &self,
         encoder: &mut wgpu::CommandEncoder,
@@ -558,26 +2499,27 @@ impl Renderer {
     ) {
         let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("Set Visibility Pass"),
-            timestamp_writes: None,
+            timestamp_writes: self.compute_timestamps("Set Visibility Pass"),
         });
 
+        let draw_count = render_data.scene.solid.draw_count;
         if newly_visible {
             self.scan_recursive(
                 &mut compute_pass,
                 &render_data.scan_newly_visible,
-                render_data,
+                draw_count,
             );
             self.set_visibility(
                 &mut compute_pass,
                 &render_data.newly_visible_bind_group,
-                render_data,
+                draw_count,
             );
         } else {
-            self.scan_recursive(&mut compute_pass, &render_data.scan_visible, render_data);
+            self.scan_recursive(&mut compute_pass, &render_data.scan_visible, draw_count);
             self.set_visibility(
                 &mut compute_pass,
                 &render_data.visible_bind_group,
-                render_data,
+                draw_count,
             );
         }
     }
@@ -586,28 +2528,28 @@ impl Renderer {
         &'a self,
         compute_pass: &mut wgpu::ComputePass<'a>,
         bind_groups: &'a ScanBindGroups,
-        render_data: &RenderData,
+        draw_count: u32,
     ) {
         // Recursively scan to support scanning arrays much larger than a single workgroup.
-        self.scan(compute_pass, &bind_groups.scan, render_data);
+        self.scan(compute_pass, &bind_groups.scan, draw_count);
         if let Some(scan_sums) = &bind_groups.scan_sums {
-            self.scan_recursive(compute_pass, scan_sums, render_data);
+            self.scan_recursive(compute_pass, scan_sums, draw_count);
         }
-        self.scan_add(compute_pass, &bind_groups.add_sums, render_data);
+        self.scan_add(compute_pass, &bind_groups.add_sums, draw_count);
     }
 
     fn set_visibility<'a>(
         &'a self,
         compute_pass: &mut wgpu::ComputePass<'a>,
         bind_group0: &'a shader::visibility::bind_groups::BindGroup0,
-        render_data: &RenderData,
+        draw_count: u32,
     ) {
         compute_pass.set_pipeline(&self.visibility_pipeline);
         shader::visibility::set_bind_groups(compute_pass, bind_group0);
 
         // Assume the workgroup is 1D.
         let [size_x, _, _] = shader::visibility::compute::MAIN_WORKGROUP_SIZE;
-        let count = div_round_up(render_data.scene.solid.draw_count, size_x);
+        let count = div_round_up(draw_count, size_x);
         compute_pass.dispatch_workgroups(count, 1, 1);
     }
 
@@ -615,14 +2557,14 @@ impl Renderer {
         &'a self,
         compute_pass: &mut wgpu::ComputePass<'a>,
         bind_group0: &'a shader::scan_add::bind_groups::BindGroup0,
-        render_data: &RenderData,
+        draw_count: u32,
     ) {
         compute_pass.set_pipeline(&self.scan_add_pipeline);
         shader::scan_add::set_bind_groups(compute_pass, bind_group0);
 
         // Assume the workgroup is 1D and processes 2 elements per thread.
         let [size_x, _, _] = shader::scan_add::compute::MAIN_WORKGROUP_SIZE;
-        let count = div_round_up(render_data.scene.solid.draw_count, size_x * 2);
+        let count = div_round_up(draw_count, size_x * 2);
         compute_pass.dispatch_workgroups(count, 1, 1);
     }
 
@@ -630,26 +2572,39 @@ impl Renderer {
         &'a self,
         compute_pass: &mut wgpu::ComputePass<'a>,
         bind_group0: &'a shader::scan::bind_groups::BindGroup0,
-        render_data: &RenderData,
+        draw_count: u32,
     ) {
         compute_pass.set_pipeline(&self.scan_pipeline);
         shader::scan::set_bind_groups(compute_pass, bind_group0);
 
         // Assume the workgroup is 1D and processes 2 elements per thread.
         let [size_x, _, _] = shader::scan::compute::MAIN_WORKGROUP_SIZE;
-        let count = div_round_up(render_data.scene.solid.draw_count, size_x * 2);
+        let count = div_round_up(draw_count, size_x * 2);
         compute_pass.dispatch_workgroups(count, 1, 1);
     }
 
     fn depth_pyramid_pass(&self, encoder: &mut wgpu::CommandEncoder) {
         let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("Depth Pyramid Pass"),
-            timestamp_writes: None,
+            timestamp_writes: self.compute_timestamps("Depth Pyramid Pass"),
         });
 
         // Copy the base level.
+        self.blit_depth(&mut compute_pass);
+
+        // Make the depth pyramid for the next frame using the current depth.
+        reduce_depth_pyramid(
+            &mut compute_pass,
+            &self.depth_pyramid_pipeline,
+            &self.depth_pyramid.mip_bind_groups,
+            self.depth_pyramid.width,
+            self.depth_pyramid.height,
+        );
+    }
+
+    fn blit_depth<'a>(&'a self, compute_pass: &mut wgpu::ComputePass<'a>) {
         compute_pass.set_pipeline(&self.blit_depth_pipeline);
-        shader::blit_depth::set_bind_groups(&mut compute_pass, &self.depth_pyramid.base_bind_group);
+        shader::blit_depth::set_bind_groups(compute_pass, &self.depth_pyramid.base_bind_group);
 
         // Assume the workgroup is 2D.
         let [size_x, size_y, _] = shader::blit_depth::compute::MAIN_WORKGROUP_SIZE;
@@ -657,34 +2612,83 @@ impl Renderer {
         let count_y = div_round_up(self.depth_pyramid.height, size_y);
 
         compute_pass.dispatch_workgroups(count_x, count_y, 1);
+    }
+}
 
-        // Make the depth pyramid for the next frame using the current depth.
-        // Each dispatch generates one mip level of the pyramid.
-        compute_pass.set_pipeline(&self.depth_pyramid_pipeline);
-        for (i, bind_group0) in self.depth_pyramid.mip_bind_groups.iter().enumerate() {
-            // The first level is copied separately from the depth texture.
-            let mip = i + 1;
-            let mip_width = (self.depth_pyramid.width >> mip).max(1);
-            let mip_height = (self.depth_pyramid.height >> mip).max(1);
+/// The size of the render targets for an output size and supersampling `factor`.
+/// The factor is limited to keep both dimensions within `max_dimension`.
+fn supersampled_size(width: u32, height: u32, factor: f32, max_dimension: u32) -> (u32, u32) {
+    let max_factor = max_dimension as f32 / width.max(height).max(1) as f32;
+    // Invalid factors use the output size.
+    let factor = factor.max(1.0).min(max_factor.max(1.0));
+    let scale = |x: u32| ((x as f32 * factor).round() as u32).clamp(1, max_dimension);
+    (scale(width), scale(height))
+}
 
-            shader::depth_pyramid::set_bind_groups(&mut compute_pass, bind_group0);
+/// An MSAA sample count supported for rendering.
+/// Create this with [supported_sample_count] to check the count against the adapter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleCount(u32);
 
-            // Assume the workgroup is 2D.
-            let [size_x, size_y, _] = shader::depth_pyramid::compute::MAIN_WORKGROUP_SIZE;
-            let count_x = div_round_up(mip_width, size_x);
-            let count_y = div_round_up(mip_height, size_y);
+impl SampleCount {
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
 
-            compute_pass.dispatch_workgroups(count_x, count_y, 1);
-        }
+impl Default for SampleCount {
+    /// 4 samples are supported on every adapter.
+    fn default() -> Self {
+        Self(MSAA_SAMPLES)
     }
 }
 
+/// The highest MSAA sample count up to `requested` that `adapter` supports for rendering.
+/// Requests below the lowest supported count use the lowest supported count.
+///
+/// At least 2 samples are used since the depth pyramid reads multisampled depth.
+/// Counts other than 4 require [wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES]
+/// from [required_features].
+pub fn supported_sample_count(adapter: &wgpu::Adapter, requested: u32) -> SampleCount {
+    SampleCount(highest_sample_count(requested, |count| {
+        [COLOR_FORMAT, DEPTH_FORMAT].into_iter().all(|format| {
+            adapter
+                .get_texture_format_features(format)
+                .flags
+                .sample_count_supported(count)
+        })
+    }))
+}
+
+fn highest_sample_count(requested: u32, is_supported: impl Fn(u32) -> bool) -> u32 {
+    let counts = [16, 8, 4, 2];
+    counts
+        .into_iter()
+        .find(|count| *count <= requested && is_supported(*count))
+        .or_else(|| counts.into_iter().rev().find(|count| is_supported(*count)))
+        // 4 samples are always supported.
+        .unwrap_or(MSAA_SAMPLES)
+}
+
+/// The features that every device passed to [Renderer::from_device] must enable.
+pub const REQUIRED_FEATURES: wgpu::Features = wgpu::Features::MULTI_DRAW_INDIRECT
+    .union(wgpu::Features::INDIRECT_FIRST_INSTANCE)
+    .union(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+    .union(wgpu::Features::FLOAT32_FILTERABLE);
+
+/// The minimum limits for every device passed to [Renderer::from_device].
+///
+/// The visibility pass binds 8 storage buffers, which is more than the downlevel defaults.
+/// Render target sizes are still limited by the device's `max_texture_dimension_2d`.
+pub const REQUIRED_LIMITS: wgpu::Limits = wgpu::Limits {
+    max_storage_buffers_per_shader_stage: 8,
+    ..wgpu::Limits::downlevel_defaults()
+};
+
+/// [REQUIRED_FEATURES] and any optional features from `supported_features` the renderer can use.
+/// Indirect count and timestamp queries are used when available.
 pub fn required_features(supported_features: wgpu::Features) -> wgpu::Features {
-    let mut required_features = wgpu::Features::MULTI_DRAW_INDIRECT
-        | wgpu::Features::INDIRECT_FIRST_INSTANCE
-        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
-        | wgpu::Features::POLYGON_MODE_LINE
-        | wgpu::Features::FLOAT32_FILTERABLE;
+    let mut required_features = REQUIRED_FEATURES;
 
     // Indirect count isn't supported on metal, so check first.
     if supported_features.contains(wgpu::Features::MULTI_DRAW_INDIRECT_COUNT) {
@@ -693,6 +2697,12 @@ pub fn required_features(supported_features: wgpu::Features) -> wgpu::Features {
     required_features
 }
 
+/// The optional features for [Renderer::set_gpu_timing] that are supported.
+/// Request these in addition to [required_features] only when profiling.
+pub fn gpu_timing_features(supported_features: wgpu::Features) -> wgpu::Features {
+    supported_features & wgpu::Features::TIMESTAMP_QUERY
+}
+
 fn create_scan_bind_groups(
     device: &wgpu::Device,
     input: &wgpu::Buffer,
@@ -751,29 +2761,209 @@ fn create_scan_bind_groups(
     }
 }
 
-fn create_depth_pyramid(
+fn create_bloom(
     device: &wgpu::Device,
     width: u32,
     height: u32,
-    base_depth_view: &wgpu::TextureView,
-) -> DepthPyramid {
-    let (pyramid, pyramid_mips) = create_depth_pyramid_texture(device, width, height);
-    let pyramid_bind_groups = depth_pyramid_bind_groups(device, &pyramid_mips);
+    color_view: &wgpu::TextureView,
+    settings_buffer: &wgpu::Buffer,
+) -> Bloom {
+    // Blurring at a lower resolution is cheaper and increases the blur radius.
+    let width = (width / 2).max(1);
+    let height = (height / 2).max(1);
 
-    let pyramid_view = pyramid.create_view(&wgpu::TextureViewDescriptor::default());
+    let view = create_bloom_view(device, width, height);
+    let blurred_view = create_bloom_view(device, width, height);
 
-    let base_bind_group = shader::blit_depth::bind_groups::BindGroup0::from_bindings(
-        device,
-        shader::blit_depth::bind_groups::BindGroupLayout0 {
-            input: base_depth_view,
-            output: &pyramid_mips[0],
-        },
-    );
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        min_filter: wgpu::FilterMode::Linear,
+        mag_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
 
-    DepthPyramid {
-        width,
+    let bind_group = |input: &wgpu::TextureView, output: &wgpu::TextureView| {
+        shader::bloom::bind_groups::BindGroup0::from_bindings(
+            device,
+            shader::bloom::bind_groups::BindGroupLayout0 {
+                settings: settings_buffer.as_entire_buffer_binding(),
+                input,
+                input_sampler: &sampler,
+                output,
+            },
+        )
+    };
+
+    Bloom {
+        width,
+        height,
+        threshold_bind_group: bind_group(color_view, &view),
+        horizontal_bind_group: bind_group(&view, &blurred_view),
+        vertical_bind_group: bind_group(&blurred_view, &view),
+        view,
+    }
+}
+
+fn create_blit_bind_group(
+    device: &wgpu::Device,
+    color_view: &wgpu::TextureView,
+    bloom_view: &wgpu::TextureView,
+    depth_view: &wgpu::TextureView,
+    settings_buffer: &wgpu::Buffer,
+    camera_buffer: &wgpu::Buffer,
+) -> shader::blit::bind_groups::BindGroup0 {
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        min_filter: wgpu::FilterMode::Linear,
+        mag_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
+    shader::blit::bind_groups::BindGroup0::from_bindings(
+        device,
+        shader::blit::bind_groups::BindGroupLayout0 {
+            color_texture: color_view,
+            bloom_texture: bloom_view,
+            color_sampler: &sampler,
+            settings: settings_buffer.as_entire_buffer_binding(),
+            camera: camera_buffer.as_entire_buffer_binding(),
+            depth_texture: depth_view,
+        },
+    )
+}
+
+fn create_accumulation(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    color_view: &wgpu::TextureView,
+    max_samples: u32,
+) -> Accumulation {
+    let bind_group = shader::accumulate::bind_groups::BindGroup0::from_bindings(
+        device,
+        shader::accumulate::bind_groups::BindGroupLayout0 {
+            color_texture: color_view,
+        },
+    );
+
+    Accumulation {
+        view: create_color_view(device, width, height),
+        bind_group,
+        samples: 0,
+        max_samples,
+    }
+}
+
+fn next_render_data_version() -> u64 {
+    // Start after the initial renderer version so the first frame always resets.
+    static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+fn jittered_camera(camera_data: &CameraData, sample: u32) -> CameraData {
+    // Keep the first sample centered to match rendering without accumulation.
+    let jitter = if sample == 0 {
+        Vec2::ZERO
+    } else {
+        vec2(halton(sample, 2), halton(sample, 3)) - 0.5
+    };
+
+    // Convert the offset in pixels to clip space before the perspective divide.
+    let viewport_size = vec2(camera_data.viewport_size.x, camera_data.viewport_size.y);
+    let offset = jitter * 2.0 / viewport_size;
+    CameraData {
+        view_projection: Mat4::from_translation(offset.extend(0.0)) * camera_data.view_projection,
+        ..*camera_data
+    }
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    // https://en.wikipedia.org/wiki/Halton_sequence
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Zoom `camera_data` so the pixel at `ndc` covers the entire viewport for a 1x1 pick target.
+fn pick_view_projection(camera_data: &CameraData, ndc: Vec2) -> Mat4 {
+    // Translate before the perspective divide like the jitter for accumulation.
+    let viewport_size = vec2(camera_data.viewport_size.x, camera_data.viewport_size.y);
+    Mat4::from_scale(viewport_size.extend(1.0))
+        * Mat4::from_translation((-ndc).extend(0.0))
+        * camera_data.view_projection
+}
+
+fn culling_camera(camera_data: &CameraData) -> shader::culling::Camera {
+    shader::culling::Camera {
+        z_near: camera_data.z_near,
+        z_far: camera_data.z_far,
+        p00: camera_data.p00,
+        p11: camera_data.p11,
+        frustum: camera_data.frustum,
+        frustum_offset: camera_data.frustum_offset,
+        view_projection: camera_data.view_projection,
+        view: camera_data.view,
+    }
+}
+
+fn edge_camera(camera_data: &CameraData) -> shader::edge::Camera {
+    shader::edge::Camera {
+        view_projection: camera_data.view_projection,
+        viewport_size: camera_data.viewport_size,
+    }
+}
+
+fn model_light(light: &LightSettings) -> shader::model::Light {
+    shader::model::Light {
+        direction: light.direction.normalize_or_zero().extend(0.0),
+        color: Vec3::from(light.color).extend(1.0),
+        ambient: light.ambient.clamp(0.0, 1.0),
+        _padding0: 0.0,
+        _padding1: 0.0,
+        _padding2: 0.0,
+    }
+}
+
+fn post_process_camera(camera_data: &CameraData) -> shader::blit::Camera {
+    shader::blit::Camera {
+        view: camera_data.view,
+        projection: camera_data.projection,
+        inverse_projection: camera_data.projection.inverse(),
+    }
+}
+
+fn create_depth_pyramid(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    base_depth_view: &wgpu::TextureView,
+) -> DepthPyramid {
+    let (pyramid, pyramid_mips) = create_depth_pyramid_texture(device, width, height);
+    let pyramid_bind_groups = depth_pyramid_bind_groups(device, &pyramid_mips);
+
+    let pyramid_view = pyramid.create_view(&wgpu::TextureViewDescriptor::default());
+    let base_level = pyramid.create_view(&wgpu::TextureViewDescriptor {
+        base_mip_level: 0,
+        mip_level_count: Some(1),
+        ..Default::default()
+    });
+
+    let base_bind_group = shader::blit_depth::bind_groups::BindGroup0::from_bindings(
+        device,
+        shader::blit_depth::bind_groups::BindGroupLayout0 {
+            input: base_depth_view,
+            output: &pyramid_mips[0],
+        },
+    );
+
+    DepthPyramid {
+        width,
         height,
         all_mips: pyramid_view,
+        base_level,
         base_bind_group,
         mip_bind_groups: pyramid_bind_groups,
     }
@@ -797,38 +2987,272 @@ fn depth_pyramid_bind_groups(
         .collect()
 }
 
+fn reduce_depth_pyramid<'a>(
+    compute_pass: &mut wgpu::ComputePass<'a>,
+    pipeline: &'a wgpu::ComputePipeline,
+    mip_bind_groups: &'a [shader::depth_pyramid::bind_groups::BindGroup0],
+    width: u32,
+    height: u32,
+) {
+    // Each dispatch generates one mip level of the pyramid.
+    compute_pass.set_pipeline(pipeline);
+    for (i, bind_group0) in mip_bind_groups.iter().enumerate() {
+        // The first level is copied separately from the depth texture.
+        // Mip sizes round down, so odd sizes reduce an extra row or column.
+        let mip = i + 1;
+        let mip_width = (width >> mip).max(1);
+        let mip_height = (height >> mip).max(1);
+
+        shader::depth_pyramid::set_bind_groups(compute_pass, bind_group0);
+
+        // Assume the workgroup is 2D.
+        let [size_x, size_y, _] = shader::depth_pyramid::compute::MAIN_WORKGROUP_SIZE;
+        let count_x = div_round_up(mip_width, size_x);
+        let count_y = div_round_up(mip_height, size_y);
+
+        compute_pass.dispatch_workgroups(count_x, count_y, 1);
+    }
+}
+
+fn copy_culling_results(encoder: &mut wgpu::CommandEncoder, scene: &IndirectSceneData) {
+    if let Some(staging_buffer) = &scene.culling_staging_buffer {
+        let size = scene.visibility_buffer.size();
+        encoder.copy_buffer_to_buffer(&scene.visibility_buffer, 0, staging_buffer, 0, size);
+        encoder.copy_buffer_to_buffer(&scene.new_visibility_buffer, 0, staging_buffer, size, size);
+    }
+}
+
 const fn div_round_up(x: u32, d: u32) -> u32 {
     (x + d - 1) / d
 }
 
+/// Convert the premultiplied alpha output of [Renderer::render_to_image] to straight alpha
+/// for saving to formats like PNG and WebP.
+/// This only affects partially transparent pixels like antialiased edges.
+pub fn unpremultiply_alpha(image: &mut image::RgbaImage) {
+    for pixel in image.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        if a > 0 && a < 255 {
+            let unpremultiply = |c: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+            pixel.0 = [unpremultiply(r), unpremultiply(g), unpremultiply(b), a];
+        }
+    }
+}
+
+/// The center of the axis-aligned `bounds` to use as the pivot for [calculate_camera_data].
+/// This is zero for invalid bounds.
+pub fn orbit_pivot(bounds: (Vec3, Vec3)) -> Vec3 {
+    let center = (bounds.0 + bounds.1) * 0.5;
+    if center.is_finite() {
+        center
+    } else {
+        Vec3::ZERO
+    }
+}
+
+/// Calculate a translation and rotation for [calculate_camera_data]
+/// that frames the axis-aligned `bounds` with a small margin.
+/// The result assumes the pivot from [orbit_pivot] for the same `bounds`.
+///
+/// Empty scenes and single points still produce a valid camera.
+pub fn fit_camera(bounds: (Vec3, Vec3), width: u32, height: u32, fov_y: f32) -> (Vec3, Vec3) {
+    let (min_xyz, max_xyz) = bounds;
+    // Avoid a zero distance for single points.
+    let radius = ((max_xyz - min_xyz).length() * 0.5).max(1.0);
+    let radius = if radius.is_finite() { radius } else { 1.0 };
+
+    // Fit the bounding sphere in the narrower of the horizontal and vertical field of view.
+    let aspect = width.max(1) as f32 / height.max(1) as f32;
+    let half_fov_y = fov_y * 0.5;
+    let half_fov_x = (half_fov_y.tan() * aspect).atan();
+    let distance = radius * 1.1 / half_fov_y.min(half_fov_x).sin();
+
+    // The pivot at the center of the bounds is already at the center of the screen.
+    (vec3(0.0, 0.0, -distance), Vec3::ZERO)
+}
+
+/// Calculate the translation for orbiting around `new_pivot` instead of `pivot`
+/// without changing the current view from [calculate_camera_data].
+///
+/// This allows changing the pivot to a selected part without the camera jumping.
+pub fn repivot_translation(
+    translation: Vec3,
+    rotation: Vec3,
+    up_axis: UpAxis,
+    pivot: Vec3,
+    new_pivot: Vec3,
+) -> Vec3 {
+    translation + camera_rotation(rotation, up_axis).transform_vector3(new_pivot - pivot)
+}
+
+fn camera_rotation(rotation: Vec3, up_axis: UpAxis) -> Mat4 {
+    // wgpu and LDraw have different coordinate systems.
+    Mat4::from_rotation_z(rotation.z)
+        * Mat4::from_rotation_x(rotation.x)
+        * Mat4::from_rotation_y(rotation.y)
+        * up_axis.correction()
+}
+
+/// Smooth pseudorandom camera motion for handheld style renders.
+///
+/// Add the offsets from [CameraShake::offset] to the translation and rotation
+/// passed to [calculate_camera_data] to leave the base camera unchanged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraShake {
+    /// The maximum translation offset in LDraw units.
+    pub translation: f32,
+    /// The maximum rotation offset in radians.
+    pub rotation: f32,
+    /// How quickly the offsets change in cycles per second.
+    pub frequency: f32,
+    /// Different seeds produce different motion with the same settings.
+    pub seed: u32,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        Self {
+            translation: 1.0,
+            rotation: 0.003,
+            frequency: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+impl CameraShake {
+    /// The translation and rotation offsets at `time` in seconds.
+    /// The same settings and `time` always produce the same offsets.
+    pub fn offset(&self, time: f32) -> (Vec3, Vec3) {
+        let t = time * self.frequency;
+        // Use a separate noise channel for each component.
+        let noise =
+            |channel: u32| value_noise(t, pcg_hash(channel.wrapping_add(pcg_hash(self.seed))));
+
+        let translation = vec3(noise(0), noise(1), noise(2)) * self.translation;
+        let rotation = vec3(noise(3), noise(4), noise(5)) * self.rotation;
+        (translation, rotation)
+    }
+}
+
+/// Smoothly interpolated noise from `-1.0` to `1.0`.
+fn value_noise(t: f32, seed: u32) -> f32 {
+    let value = |i: f32| {
+        let hash = pcg_hash((i as i32 as u32).wrapping_add(seed));
+        hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+    };
+
+    // Smoothstep easing avoids abrupt changes in direction.
+    let i = t.floor();
+    let f = t - i;
+    let f = f * f * (3.0 - 2.0 * f);
+    value(i) + (value(i + 1.0) - value(i)) * f
+}
+
+fn pcg_hash(input: u32) -> u32 {
+    // https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+    let state = input.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Calculate the camera matrices and culling data.
+///
+/// The camera rotates around the `pivot` in the model's coordinates like [orbit_pivot].
+/// The `translation` moves the camera relative to the pivot in view space.
 pub fn calculate_camera_data(
     width: u32,
     height: u32,
     translation: glam::Vec3,
     rotation: glam::Vec3,
+    pivot: glam::Vec3,
+    up_axis: UpAxis,
+    projection: Projection,
 ) -> CameraData {
-    let aspect = width as f32 / height as f32;
+    calculate_camera_data_with_depth_range(
+        width,
+        height,
+        translation,
+        rotation,
+        pivot,
+        up_axis,
+        projection,
+        DepthRange::default(),
+    )
+}
 
-    // wgpu and LDraw have different coordinate systems.
-    let axis_correction = Mat4::from_rotation_x(180.0f32.to_radians());
+/// Calculate the camera matrices and culling data like [calculate_camera_data]
+/// with custom near and far planes for very large or very small scenes.
+///
+/// An infinite far plane uses a large finite distance for [Projection::Orthographic].
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_camera_data_with_depth_range(
+    width: u32,
+    height: u32,
+    translation: glam::Vec3,
+    rotation: glam::Vec3,
+    pivot: glam::Vec3,
+    up_axis: UpAxis,
+    projection: Projection,
+    depth_range: DepthRange,
+) -> CameraData {
+    let DepthRange {
+        near: z_near,
+        far: z_far,
+    } = depth_range;
 
+    let aspect = width as f32 / height as f32;
+
+    // Roll before translating to keep panning aligned with the screen.
     let view = glam::Mat4::from_translation(translation)
-        * glam::Mat4::from_rotation_x(rotation.x)
-        * glam::Mat4::from_rotation_y(rotation.y)
-        * axis_correction;
+        * camera_rotation(rotation, up_axis)
+        * glam::Mat4::from_translation(-pivot);
 
-    let projection = glam::Mat4::perspective_infinite_reverse_rh(FOV_Y, aspect, Z_NEAR);
+    let (projection, z_far) = match projection {
+        Projection::Perspective { fov_y } if z_far.is_infinite() => (
+            glam::Mat4::perspective_infinite_reverse_rh(fov_y, aspect, z_near),
+            z_far,
+        ),
+        // Swap near and far for reversed-z.
+        Projection::Perspective { fov_y } => (
+            glam::Mat4::perspective_rh(fov_y, aspect, z_far, z_near),
+            z_far,
+        ),
+        Projection::Orthographic { height } => {
+            let half_height = height * 0.5;
+            let half_width = half_height * aspect;
+            let z_far = if z_far.is_infinite() {
+                Z_FAR_ORTHOGRAPHIC
+            } else {
+                z_far
+            };
+            (
+                glam::Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    z_far,
+                    z_near,
+                ),
+                z_far,
+            )
+        }
+    };
 
     let view_projection = projection * view;
 
     // Calculate camera frustum data for culling.
     // https://github.com/zeux/niagara/blob/3fafe000ba8fe6e309b41e915b81242b4ca3db28/src/niagara.cpp#L836-L852
+    // Normalize by the plane normal to preserve the plane offset for orthographic projections.
     let perspective_t = projection.transpose();
+    let plane = |p: Vec4| p / p.truncate().length();
     // x + w < 0
-    let frustum_x = (perspective_t.col(3) + perspective_t.col(0)).normalize();
+    let frustum_x = plane(perspective_t.col(3) + perspective_t.col(0));
     // y + w < 0
-    let frustum_y = (perspective_t.col(3) + perspective_t.col(1)).normalize();
+    let frustum_y = plane(perspective_t.col(3) + perspective_t.col(1));
     let frustum = vec4(frustum_x.x, frustum_x.z, frustum_y.y, frustum_y.z);
+    let frustum_offset = vec4(frustum_x.w, frustum_y.w, 0.0, 0.0);
 
     // Used for occlusion based culling.
     let p00 = projection.col(0).x;
@@ -838,10 +3262,1152 @@ pub fn calculate_camera_data(
 
     CameraData {
         view,
+        projection,
         view_projection,
         frustum,
         p00,
         p11,
         position,
+        viewport_size: vec4(width as f32, height as f32, 0.0, 0.0),
+        frustum_offset,
+        z_near,
+        z_far,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glam::{vec2, vec3, vec4, Vec2, Vec3};
+
+    #[test]
+    fn up_axis_y_correction() {
+        assert!(UpAxis::Y
+            .correction()
+            .transform_vector3(vec3(0.0, -1.0, 0.0))
+            .abs_diff_eq(Vec3::Y, 1e-6));
+    }
+
+    #[test]
+    fn up_axis_z_correction() {
+        assert!(UpAxis::Z
+            .correction()
+            .transform_vector3(Vec3::Z)
+            .abs_diff_eq(Vec3::Y, 1e-6));
+    }
+
+    #[test]
+    fn up_axis_x_correction() {
+        assert!(UpAxis::X
+            .correction()
+            .transform_vector3(Vec3::X)
+            .abs_diff_eq(Vec3::Y, 1e-6));
+    }
+
+    fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        // Skip GPU tests on machines without a compatible adapter.
+        let instance = wgpu::Instance::default();
+        let adapter = block_on(instance.request_adapter(&Default::default()))?;
+        let required_features = required_features(adapter.features());
+        if !adapter.features().contains(required_features) {
+            return None;
+        }
+
+        block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features,
+                required_limits: wgpu::Limits::default(),
+                memory_hints: wgpu::MemoryHints::default(),
+            },
+            None,
+        ))
+        .ok()
+    }
+
+    fn read_buffer<T: bytemuck::Pod>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+    ) -> Vec<T> {
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+        queue.submit([encoder.finish()]);
+
+        let buffer_slice = staging_buffer.slice(..);
+        buffer_slice.map_async(wgpu::MapMode::Read, |_| ());
+        device.poll(wgpu::Maintain::Wait);
+
+        let data = buffer_slice.get_mapped_range();
+        bytemuck::cast_slice(&data).to_vec()
+    }
+
+    #[test]
+    fn visibility_compaction() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+
+        let camera_data = calculate_camera_data(
+            64,
+            64,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            UpAxis::Y,
+            Projection::default(),
+        );
+        let renderer = Renderer::new(
+            &device,
+            64,
+            64,
+            &camera_data,
+            wgpu::TextureFormat::Rgba8Unorm,
+            SampleCount::default(),
+            device.features(),
+        );
+
+        // Use enough draws to require scanning the workgroup sums.
+        let draw_count = 1500u32;
+        let visibility: Vec<u32> = (0..draw_count)
+            .map(|i| (i % 3 == 0 || i % 7 == 0) as u32)
+            .collect();
+        let draws: Vec<_> = (0..draw_count)
+            .map(|i| DrawIndexedIndirect {
+                vertex_count: 3,
+                instance_count: 1,
+                base_index: i * 3,
+                vertex_offset: 0,
+                base_instance: i,
+            })
+            .collect();
+
+        // Compact two chunks separately to match scenes with multiple vertex buffers.
+        // Only the second chunk has transparent draws at the end.
+        let chunk_start = 1000u32;
+        let transparent_start = 1300u32;
+        let draw_chunks: Vec<_> = (0..draw_count)
+            .map(|i| {
+                let chunk = (i >= chunk_start) as u32;
+                shader::visibility::DrawChunk {
+                    chunk,
+                    first_draw: chunk * chunk_start,
+                    transparent_start: if chunk == 0 {
+                        chunk_start
+                    } else {
+                        transparent_start
+                    },
+                }
+            })
+            .collect();
+
+        let buffer = |contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            })
+        };
+        let draws_buffer = buffer(bytemuck::cast_slice(&draws));
+        let edge_draws_buffer = buffer(bytemuck::cast_slice(&draws));
+        let visibility_buffer = buffer(bytemuck::cast_slice(&visibility));
+        let scanned_visibility_buffer = buffer(bytemuck::cast_slice(&visibility));
+        let compacted_draws_buffer = buffer(bytemuck::cast_slice(&draws));
+        let compacted_edge_draws_buffer = buffer(bytemuck::cast_slice(&draws));
+        let compacted_count_buffer = buffer(bytemuck::cast_slice(&[0u32; 2]));
+        let draw_chunks_buffer = buffer(bytemuck::cast_slice(&draw_chunks));
+
+        let scan = create_scan_bind_groups(&device, &visibility_buffer, &scanned_visibility_buffer);
+        let bind_group = shader::visibility::bind_groups::BindGroup0::from_bindings(
+            &device,
+            shader::visibility::bind_groups::BindGroupLayout0 {
+                draws: draws_buffer.as_entire_buffer_binding(),
+                edge_draws: edge_draws_buffer.as_entire_buffer_binding(),
+                visibility: visibility_buffer.as_entire_buffer_binding(),
+                scanned_visibility: scanned_visibility_buffer.as_entire_buffer_binding(),
+                compacted_draws: compacted_draws_buffer.as_entire_buffer_binding(),
+                compacted_edge_draws: compacted_edge_draws_buffer.as_entire_buffer_binding(),
+                compacted_draw_count: compacted_count_buffer.as_entire_buffer_binding(),
+                draw_chunks: draw_chunks_buffer.as_entire_buffer_binding(),
+            },
+        );
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            renderer.scan_recursive(&mut compute_pass, &scan, draw_count);
+            renderer.set_visibility(&mut compute_pass, &bind_group, draw_count);
+        }
+        queue.submit([encoder.finish()]);
+
+        let count: Vec<u32> = read_buffer(&device, &queue, &compacted_count_buffer);
+        let compacted: Vec<DrawIndexedIndirect> =
+            read_buffer(&device, &queue, &compacted_draws_buffer);
+        let compacted_edges: Vec<DrawIndexedIndirect> =
+            read_buffer(&device, &queue, &compacted_edge_draws_buffer);
+
+        let chunk_start = chunk_start as usize;
+        let transparent_start = transparent_start as usize;
+        for (i, draws_range) in [0..chunk_start, chunk_start..transparent_start]
+            .into_iter()
+            .enumerate()
+        {
+            let expected: Vec<_> = draws[draws_range.clone()]
+                .iter()
+                .zip(&visibility[draws_range.clone()])
+                .filter(|(_, v)| **v != 0)
+                .map(|(d, _)| *d)
+                .collect();
+            let compacted_range = draws_range.start..draws_range.start + expected.len();
+
+            assert_eq!(expected.len() as u32, count[i]);
+            assert_eq!(expected, compacted[compacted_range.clone()]);
+            assert_eq!(expected, compacted_edges[compacted_range]);
+        }
+
+        // Transparent draws stay in place with culled draws having no instances.
+        let expected: Vec<_> = draws[transparent_start..]
+            .iter()
+            .zip(&visibility[transparent_start..])
+            .map(|(d, v)| DrawIndexedIndirect {
+                instance_count: *v,
+                ..*d
+            })
+            .collect();
+        assert_eq!(expected, compacted[transparent_start..]);
+        assert_eq!(expected, compacted_edges[transparent_start..]);
+    }
+
+    fn render_transparent_quads(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        far_quad_reversed: bool,
+    ) -> [u8; 4] {
+        let camera_data = calculate_camera_data(
+            1,
+            1,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            UpAxis::Y,
+            Projection::default(),
+        );
+        let renderer = Renderer::new(
+            device,
+            1,
+            1,
+            &camera_data,
+            wgpu::TextureFormat::Rgba8Unorm,
+            SampleCount::default(),
+            device.features(),
+        );
+
+        // The camera looks down +Z, so the red quad is behind the blue quad.
+        // Only one quad faces the camera to draw it with a different pipeline.
+        let quad = |z, color| {
+            [
+                vec2(-10.0, -10.0),
+                vec2(10.0, -10.0),
+                vec2(10.0, 10.0),
+                vec2(-10.0, 10.0),
+            ]
+            .map(|p| shader::model::VertexInput {
+                position: p.extend(z),
+                color,
+                normal: vec4(0.0, 0.0, -1.0, 0.0),
+            })
+        };
+        let vertices = [quad(5.0, 0x800000FF), quad(2.0, 0x80FF0000)].concat();
+        let (front, back) = ([0u32, 1, 2, 2, 3, 0], [0u32, 2, 1, 2, 0, 3]);
+        let (far_indices, near_indices) = if far_quad_reversed {
+            (back, front)
+        } else {
+            (front, back)
+        };
+        let indices = [far_indices, near_indices.map(|i| i + 4)].concat();
+
+        // Draws are already sorted back to front.
+        let draws: Vec<_> = (0..2)
+            .map(|i| DrawIndexedIndirect {
+                vertex_count: 6,
+                instance_count: 1,
+                base_index: i * 6,
+                vertex_offset: 0,
+                base_instance: i,
+            })
+            .collect();
+
+        let buffer = |contents: &[u8], usage| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage,
+            })
+        };
+        let vertex_buffer = buffer(bytemuck::cast_slice(&vertices), wgpu::BufferUsages::VERTEX);
+        let index_buffer = buffer(bytemuck::cast_slice(&indices), wgpu::BufferUsages::INDEX);
+        let transforms_buffer = buffer(
+            bytemuck::cast_slice(&[Mat4::IDENTITY; 2]),
+            wgpu::BufferUsages::VERTEX,
+        );
+        let instance_index_buffer =
+            buffer(bytemuck::cast_slice(&[0u32, 1]), wgpu::BufferUsages::VERTEX);
+        let indirect_buffer = buffer(bytemuck::cast_slice(&draws), wgpu::BufferUsages::INDIRECT);
+        let selection_buffer = buffer(
+            bytemuck::cast_slice(&[0u32; 2]),
+            wgpu::BufferUsages::STORAGE,
+        );
+        let bind_group1 = shader::model::bind_groups::BindGroup1::from_bindings(
+            device,
+            shader::model::bind_groups::BindGroupLayout1 {
+                selected: selection_buffer.as_entire_buffer_binding(),
+            },
+        );
+
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let back_faces_pipeline = create_transparent_pipeline(device, format, 1, wgpu::Face::Front);
+        let front_faces_pipeline = create_transparent_pipeline(device, format, 1, wgpu::Face::Back);
+
+        let size = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let output = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let output_view = output.create_view(&Default::default());
+        let (_, depth_view) = create_depth_texture(device, 1, 1, 1);
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: padded_bytes_per_row(1) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &output_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_view,
+                    depth_ops: Some(depth_op_reversed()),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            shader::model::set_bind_groups(&mut render_pass, &renderer.bind_group0, &bind_group1);
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, transforms_buffer.slice(..));
+            render_pass.set_vertex_buffer(2, instance_index_buffer.slice(..));
+            render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            scene::draw_each_indirect(
+                &mut render_pass,
+                &indirect_buffer,
+                0..draws.len(),
+                &[&back_faces_pipeline, &front_faces_pipeline],
+            );
+        }
+        encoder.copy_texture_to_buffer(
+            output.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &output_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row(1)),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        queue.submit([encoder.finish()]);
+
+        let data: Vec<u8> = read_buffer(device, queue, &output_buffer);
+        [data[0], data[1], data[2], data[3]]
+    }
+
+    #[test]
+    fn transparent_parts_back_to_front() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+
+        // The nearer blue quad should always blend over the farther red quad
+        // regardless of which pipeline draws each quad.
+        for far_quad_reversed in [false, true] {
+            let [r, _, b, _] = render_transparent_quads(&device, &queue, far_quad_reversed);
+            assert!(b > r && r > 0, "{far_quad_reversed}: {r} {b}");
+        }
+    }
+
+    // Only the bottom right pixel has nothing drawn, so parts behind it are visible.
+    // Odd sizes at each level shouldn't lose the pixel along the window border.
+    const ODD_SIZE: (u32, u32) = (1023, 769);
+    const EDGE_PIXEL: (u32, u32) = (ODD_SIZE.0 - 1, ODD_SIZE.1 - 1);
+
+    fn edge_pixel_depth_pyramid(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        let (width, height) = ODD_SIZE;
+        let mut depth = vec![1.0f32; (width * height) as usize];
+        depth[(EDGE_PIXEL.1 * width + EDGE_PIXEL.0) as usize] = 0.0;
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let mip_level_count = size.max_mips(wgpu::TextureDimension::D2);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&depth),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: None,
+            },
+            size,
+        );
+
+        let mips: Vec<_> = (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let pipeline = shader::depth_pyramid::compute::create_main_pipeline(device);
+        let bind_groups = depth_pyramid_bind_groups(device, &mips);
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            reduce_depth_pyramid(&mut compute_pass, &pipeline, &bind_groups, width, height);
+        }
+        queue.submit([encoder.finish()]);
+
+        texture
+    }
+
+    #[test]
+    fn depth_pyramid_odd_size_edges() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+
+        let (width, height) = ODD_SIZE;
+        let texture = edge_pixel_depth_pyramid(&device, &queue);
+        let mip_level_count = texture.mip_level_count();
+
+        // Read the texel the culling shader loads for the edge pixel at each level.
+        let row_size = padded_bytes_per_row(1) as u64;
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: row_size * mip_level_count as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        for mip in 0..mip_level_count {
+            let mip_width = (width >> mip).max(1);
+            let mip_height = (height >> mip).max(1);
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: mip,
+                    origin: wgpu::Origin3d {
+                        x: (EDGE_PIXEL.0 >> mip).min(mip_width - 1),
+                        y: (EDGE_PIXEL.1 >> mip).min(mip_height - 1),
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &output_buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: row_size * mip as u64,
+                        bytes_per_row: Some(row_size as u32),
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        queue.submit([encoder.finish()]);
+
+        let output: Vec<f32> = read_buffer(&device, &queue, &output_buffer);
+        for mip in 0..mip_level_count as usize {
+            assert_eq!(0.0, output[mip * row_size as usize / 4], "mip {mip}");
+        }
+    }
+
+    #[test]
+    fn occlusion_culling_odd_size_edges() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+
+        let (width, height) = ODD_SIZE;
+        let camera_data = calculate_camera_data(
+            width,
+            height,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            UpAxis::Y,
+            Projection::default(),
+        );
+
+        // Find the world position for pixel coordinates at a fixed depth.
+        let inverse_view_projection = camera_data.view_projection.inverse();
+        let world_position = |x: f32, y: f32| {
+            let ndc = vec2(x / width as f32 * 2.0 - 1.0, 1.0 - y / height as f32 * 2.0);
+            inverse_view_projection.project_point3(ndc.extend(0.01))
+        };
+        let bounds = |min_pixel: Vec2, max_pixel: Vec2| {
+            let a = world_position(min_pixel.x, min_pixel.y);
+            let b = world_position(max_pixel.x, max_pixel.y);
+            let min_xyz = a.min(b) - 0.0001;
+            let max_xyz = a.max(b) + 0.0001;
+            let center = (min_xyz + max_xyz) * 0.5;
+            shader::culling::InstanceBounds {
+                sphere: center.extend(center.distance(max_xyz)),
+                min_xyz: min_xyz.extend(0.0),
+                max_xyz: max_xyz.extend(0.0),
+            }
+        };
+
+        // Parts ending in the edge pixel are visible at each mip level.
+        // The part in the center is behind the occluder.
+        let edge = vec2(EDGE_PIXEL.0 as f32, EDGE_PIXEL.1 as f32) + 0.5;
+        let center = vec2(width as f32, height as f32) * 0.5;
+        let instance_bounds: Vec<_> = [0.0, 3.0, 15.0, 63.0, 255.0]
+            .into_iter()
+            .map(|size| bounds(edge - size, edge))
+            .chain(std::iter::once(bounds(center, center + 2.0)))
+            .collect();
+        let count = instance_bounds.len();
+
+        let draws: Vec<_> = (0..count as u32)
+            .map(|i| DrawIndexedIndirect {
+                vertex_count: 3,
+                instance_count: 1,
+                base_index: 0,
+                vertex_offset: 0,
+                base_instance: i,
+            })
+            .collect();
+
+        let buffer = |contents: &[u8], usage| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage,
+            })
+        };
+        let storage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC;
+        let zeros = vec![0u32; count];
+        let camera_buffer = buffer(
+            bytemuck::cast_slice(&[culling_camera(&camera_data)]),
+            wgpu::BufferUsages::UNIFORM,
+        );
+        let instance_bounds_buffer = buffer(bytemuck::cast_slice(&instance_bounds), storage);
+        let visibility_buffer = buffer(bytemuck::cast_slice(&zeros), storage);
+        let new_visibility_buffer = buffer(bytemuck::cast_slice(&zeros), storage);
+        let transparent_buffer = buffer(bytemuck::cast_slice(&zeros), storage);
+        let visibility_override_buffer = buffer(bytemuck::cast_slice(&zeros), storage);
+        let draws_buffer = buffer(bytemuck::cast_slice(&draws), storage);
+
+        let texture = edge_pixel_depth_pyramid(&device, &queue);
+        let depth_pyramid = texture.create_view(&Default::default());
+
+        let bind_group0 = shader::culling::bind_groups::BindGroup0::from_bindings(
+            &device,
+            shader::culling::bind_groups::BindGroupLayout0 {
+                camera: camera_buffer.as_entire_buffer_binding(),
+                depth_pyramid: &depth_pyramid,
+            },
+        );
+        let bind_group1 = shader::culling::bind_groups::BindGroup1::from_bindings(
+            &device,
+            shader::culling::bind_groups::BindGroupLayout1 {
+                instance_bounds: instance_bounds_buffer.as_entire_buffer_binding(),
+                visibility: visibility_buffer.as_entire_buffer_binding(),
+                new_visibility: new_visibility_buffer.as_entire_buffer_binding(),
+                transparent: transparent_buffer.as_entire_buffer_binding(),
+                visibility_override: visibility_override_buffer.as_entire_buffer_binding(),
+                draws: draws_buffer.as_entire_buffer_binding(),
+            },
+        );
+        let pipeline = shader::culling::compute::create_main_pipeline(&device);
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_pipeline(&pipeline);
+            shader::culling::set_bind_groups(&mut compute_pass, &bind_group0, &bind_group1);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        queue.submit([encoder.finish()]);
+
+        let visibility: Vec<u32> = read_buffer(&device, &queue, &visibility_buffer);
+        assert_eq!(vec![1, 1, 1, 1, 1, 0], visibility);
+    }
+
+    #[test]
+    fn check_features_limits_required() {
+        assert_eq!(
+            Ok(()),
+            check_features_limits(REQUIRED_FEATURES, &wgpu::Limits::default())
+        );
+        assert_eq!(
+            Err(UnsupportedDevice::MissingFeatures(
+                wgpu::Features::MULTI_DRAW_INDIRECT
+            )),
+            check_features_limits(
+                REQUIRED_FEATURES - wgpu::Features::MULTI_DRAW_INDIRECT,
+                &wgpu::Limits::default()
+            )
+        );
+        assert_eq!(
+            Err(UnsupportedDevice::InsufficientLimits),
+            check_features_limits(REQUIRED_FEATURES, &wgpu::Limits::downlevel_defaults())
+        );
+    }
+
+    #[test]
+    fn highest_sample_count_requested() {
+        assert_eq!(8, highest_sample_count(8, |_| true));
+        assert_eq!(4, highest_sample_count(6, |_| true));
+        assert_eq!(4, highest_sample_count(16, |c| c == 4 || c == 2));
+    }
+
+    #[test]
+    fn highest_sample_count_below_supported() {
+        // The depth pyramid requires multisampled depth.
+        assert_eq!(2, highest_sample_count(1, |_| true));
+        assert_eq!(4, highest_sample_count(1, |c| c == 4 || c == 8));
+        assert_eq!(4, highest_sample_count(0, |_| false));
+    }
+
+    #[test]
+    fn fit_camera_empty() {
+        let (translation, rotation) = fit_camera((Vec3::ZERO, Vec3::ZERO), 512, 512, FOV_Y);
+        assert!(translation.is_finite());
+        assert!(translation.z < 0.0);
+        assert_eq!(Vec3::ZERO, rotation);
+    }
+
+    #[test]
+    fn fit_camera_point() {
+        let point = vec3(1.0, 2.0, 3.0);
+        let (translation, _) = fit_camera((point, point), 0, 0, FOV_Y);
+        assert!(translation.is_finite());
+    }
+
+    #[test]
+    fn fit_camera_contains_bounds() {
+        let bounds = (vec3(10.0, -50.0, 0.0), vec3(250.0, 0.0, 40.0));
+        let (translation, rotation) = fit_camera(bounds, 1024, 512, FOV_Y);
+        let camera = calculate_camera_data(
+            1024,
+            512,
+            translation,
+            rotation,
+            orbit_pivot(bounds),
+            UpAxis::Y,
+            Projection::Perspective { fov_y: FOV_Y },
+        );
+
+        for i in 0..8 {
+            let corner = vec3(
+                if i & 1 == 0 { bounds.0.x } else { bounds.1.x },
+                if i & 2 == 0 { bounds.0.y } else { bounds.1.y },
+                if i & 4 == 0 { bounds.0.z } else { bounds.1.z },
+            );
+            let ndc = camera.view_projection.project_point3(corner);
+            assert!(ndc.x.abs() < 1.0 && ndc.y.abs() < 1.0, "{ndc}");
+        }
+    }
+
+    // Matches is_within_view_frustum in culling.wgsl.
+    fn is_within_view_frustum(camera: &CameraData, center: Vec3, radius: f32) -> bool {
+        let frustum = camera.frustum;
+        let offset = camera.frustum_offset;
+        center.z * frustum.y - center.x.abs() * frustum.x + offset.x >= -radius
+            && center.z * frustum.w - center.y.abs() * frustum.z + offset.y >= -radius
+            && center.z - radius <= -camera.z_near
+            && center.z + radius >= -camera.z_far
+    }
+
+    fn view_space_camera(width: u32, height: u32, projection: Projection) -> CameraData {
+        calculate_camera_data(
+            width,
+            height,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            UpAxis::Y,
+            projection,
+        )
+    }
+
+    #[test]
+    fn frustum_planes_perspective() {
+        let camera = view_space_camera(
+            512,
+            512,
+            Projection::Perspective {
+                fov_y: 90f32.to_radians(),
+            },
+        );
+
+        // The side planes are at 45 degrees for a 90 degree field of view.
+        let c = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(camera.frustum.abs_diff_eq(vec4(c, -c, c, -c), 1e-5));
+        assert_eq!(Vec4::ZERO, camera.frustum_offset);
+    }
+
+    #[test]
+    fn frustum_planes_orthographic() {
+        let camera = view_space_camera(512, 256, Projection::Orthographic { height: 4.0 });
+
+        // The planes are parallel to the view direction and offset by the half extents.
+        assert!(camera.frustum.abs_diff_eq(vec4(1.0, 0.0, 1.0, 0.0), 1e-5));
+        assert!(camera
+            .frustum_offset
+            .abs_diff_eq(vec4(4.0, 2.0, 0.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn frustum_culling_sides() {
+        let camera = view_space_camera(512, 512, Projection::default());
+        assert!(is_within_view_frustum(&camera, vec3(0.0, 0.0, -100.0), 1.0));
+        assert!(!is_within_view_frustum(
+            &camera,
+            vec3(1000.0, 0.0, -100.0),
+            1.0
+        ));
+        assert!(!is_within_view_frustum(
+            &camera,
+            vec3(0.0, -1000.0, -100.0),
+            1.0
+        ));
+        // Spheres partially inside the frustum are visible.
+        assert!(is_within_view_frustum(
+            &camera,
+            vec3(1000.0, 0.0, -100.0),
+            1000.0
+        ));
+    }
+
+    #[test]
+    fn frustum_culling_near_plane() {
+        let camera = view_space_camera(512, 512, Projection::default());
+        // The camera looks down -Z in view space.
+        assert!(!is_within_view_frustum(&camera, vec3(0.0, 0.0, 100.0), 1.0));
+        assert!(!is_within_view_frustum(
+            &camera,
+            vec3(0.0, 0.0, 0.0),
+            Z_NEAR * 0.5
+        ));
+        // Spheres crossing the near plane are still visible.
+        assert!(is_within_view_frustum(&camera, vec3(0.0, 0.0, 1.0), 2.0));
+    }
+
+    #[test]
+    fn frustum_culling_far_plane_orthographic() {
+        let camera = view_space_camera(512, 512, Projection::Orthographic { height: 100.0 });
+        assert!(is_within_view_frustum(&camera, vec3(0.0, 0.0, -100.0), 1.0));
+        assert!(!is_within_view_frustum(
+            &camera,
+            vec3(0.0, 0.0, -Z_FAR_ORTHOGRAPHIC - 10.0),
+            1.0
+        ));
+    }
+
+    #[test]
+    fn depth_range_validation() {
+        assert!(DepthRange::new(1.0, f32::INFINITY).is_ok());
+        assert!(DepthRange::new(1.0, 1000.0).is_ok());
+        assert_eq!(
+            Err(InvalidDepthRange {
+                near: 10.0,
+                far: 1.0
+            }),
+            DepthRange::new(10.0, 1.0)
+        );
+        assert!(DepthRange::new(0.0, 1.0).is_err());
+        assert!(DepthRange::new(-1.0, 1.0).is_err());
+        assert!(DepthRange::new(1.0, 1.0).is_err());
+        assert!(DepthRange::new(f32::NAN, 1.0).is_err());
+        assert!(DepthRange::new(1.0, f32::NAN).is_err());
+    }
+
+    fn depth_range_camera(projection: Projection, depth_range: DepthRange) -> CameraData {
+        calculate_camera_data_with_depth_range(
+            512,
+            512,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            UpAxis::Y,
+            projection,
+            depth_range,
+        )
+    }
+
+    #[test]
+    fn depth_range_perspective_reversed() {
+        let depth_range = DepthRange::new(10.0, 1000.0).unwrap();
+        let camera = depth_range_camera(Projection::default(), depth_range);
+        let depth = |z: f32| camera.view_projection.project_point3(vec3(0.0, 0.0, z)).z;
+
+        assert!((depth(-10.0) - 1.0).abs() < 1e-5);
+        assert!(depth(-1000.0).abs() < 1e-5);
+        assert!(depth(-100.0) > depth(-500.0));
+        assert_eq!(10.0, camera.z_near);
+        assert_eq!(1000.0, camera.z_far);
+    }
+
+    #[test]
+    fn depth_range_infinite_far() {
+        let depth_range = DepthRange::new(10.0, f32::INFINITY).unwrap();
+        let camera = depth_range_camera(Projection::default(), depth_range);
+        let depth = |z: f32| camera.view_projection.project_point3(vec3(0.0, 0.0, z)).z;
+        assert!((depth(-10.0) - 1.0).abs() < 1e-5);
+        assert!(depth(-1e7) > 0.0);
+
+        let camera = depth_range_camera(Projection::Orthographic { height: 4.0 }, depth_range);
+        assert_eq!(Z_FAR_ORTHOGRAPHIC, camera.z_far);
+    }
+
+    #[test]
+    fn depth_range_default() {
+        assert_eq!(
+            view_space_camera(512, 512, Projection::default()),
+            depth_range_camera(Projection::default(), DepthRange::default())
+        );
+    }
+
+    #[test]
+    fn supersampled_size_factor() {
+        assert_eq!((3840, 2160), supersampled_size(1920, 1080, 2.0, 8192));
+        assert_eq!((300, 150), supersampled_size(200, 100, 1.5, 8192));
+    }
+
+    #[test]
+    fn supersampled_size_limits() {
+        // The factor is reduced to fit the larger dimension.
+        assert_eq!((8192, 2048), supersampled_size(4096, 1024, 4.0, 8192));
+        assert_eq!((8192, 8192), supersampled_size(8192, 8192, 2.0, 8192));
+        // Supersampling never reduces the resolution.
+        assert_eq!((100, 50), supersampled_size(100, 50, 0.5, 8192));
+        assert_eq!((100, 50), supersampled_size(100, 50, f32::NAN, 8192));
+    }
+
+    #[test]
+    fn halton_bases() {
+        assert_eq!([0.5, 0.25, 0.75], [1, 2, 3].map(|i| halton(i, 2)));
+        for (i, expected) in [(1, 1.0 / 3.0), (2, 2.0 / 3.0), (3, 1.0 / 9.0)] {
+            assert!((halton(i, 3) - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn jittered_camera_subpixel() {
+        let camera = view_space_camera(
+            512,
+            256,
+            Projection::Perspective {
+                fov_y: 90f32.to_radians(),
+            },
+        );
+        assert_eq!(camera, jittered_camera(&camera, 0));
+
+        // Points shift by the same fraction of a pixel regardless of depth.
+        for sample in 1..16 {
+            let jittered = jittered_camera(&camera, sample);
+            for z in [-1.0, -10.0] {
+                let ndc = jittered.view_projection.project_point3(vec3(0.0, 0.0, z));
+                let pixels = ndc.truncate() * Vec2::new(512.0, 256.0) * 0.5;
+                assert!(pixels.abs().max_element() < 0.5);
+                assert!(pixels.abs_diff_eq(vec2(halton(sample, 2), halton(sample, 3)) - 0.5, 1e-3));
+            }
+        }
+    }
+
+    #[test]
+    fn orbit_pivot_center() {
+        assert_eq!(
+            vec3(1.0, 2.0, 3.0),
+            orbit_pivot((vec3(0.0, 0.0, 0.0), vec3(2.0, 4.0, 6.0)))
+        );
+        assert_eq!(Vec3::ZERO, orbit_pivot((Vec3::MAX, Vec3::INFINITY)));
+    }
+
+    #[test]
+    fn pivot_projects_to_center() {
+        let pivot = vec3(100.0, -50.0, 20.0);
+        let camera = calculate_camera_data(
+            512,
+            512,
+            vec3(0.0, 0.0, -200.0),
+            vec3(0.5, 1.0, 0.25),
+            pivot,
+            UpAxis::Z,
+            Projection::default(),
+        );
+        let ndc = camera.view_projection.project_point3(pivot);
+        assert!(ndc.truncate().abs_diff_eq(Vec2::ZERO, 1e-5), "{ndc}");
+    }
+
+    #[test]
+    fn repivot_translation_preserves_view() {
+        let camera = |translation, pivot| {
+            calculate_camera_data(
+                512,
+                256,
+                translation,
+                vec3(0.3, -1.2, 0.1),
+                pivot,
+                UpAxis::Y,
+                Projection::default(),
+            )
+            .view_projection
+        };
+
+        let translation = vec3(5.0, -3.0, -150.0);
+        let pivot = vec3(10.0, 20.0, 30.0);
+        let new_pivot = vec3(-40.0, 0.0, 15.0);
+        let new_translation = repivot_translation(
+            translation,
+            vec3(0.3, -1.2, 0.1),
+            UpAxis::Y,
+            pivot,
+            new_pivot,
+        );
+
+        assert!(camera(translation, pivot).abs_diff_eq(camera(new_translation, new_pivot), 1e-4));
+    }
+
+    #[test]
+    fn camera_shake_deterministic() {
+        let shake = CameraShake::default();
+        assert_eq!(shake.offset(1.25), shake.offset(1.25));
+        assert_ne!(
+            shake.offset(1.25),
+            CameraShake { seed: 1, ..shake }.offset(1.25)
+        );
+    }
+
+    #[test]
+    fn camera_shake_amplitude() {
+        let shake = CameraShake {
+            translation: 2.0,
+            rotation: 0.5,
+            frequency: 3.0,
+            seed: 7,
+        };
+        for i in 0..1000 {
+            let (translation, rotation) = shake.offset(i as f32 * 0.01);
+            assert!(translation.abs().max_element() <= 2.0);
+            assert!(rotation.abs().max_element() <= 0.5);
+        }
+    }
+
+    #[test]
+    fn camera_shake_smooth() {
+        let shake = CameraShake::default();
+        for i in 0..1000 {
+            let t = i as f32 * 0.01;
+            let (a, _) = shake.offset(t);
+            let (b, _) = shake.offset(t + 0.001);
+            assert!(a.distance(b) < 0.05);
+        }
+    }
+
+    #[test]
+    fn camera_shake_disabled() {
+        let shake = CameraShake {
+            translation: 0.0,
+            rotation: 0.0,
+            ..Default::default()
+        };
+        assert_eq!((Vec3::ZERO, Vec3::ZERO), shake.offset(3.5));
+    }
+
+    #[test]
+    fn model_light_normalized_clamped() {
+        let light = model_light(&LightSettings {
+            direction: vec3(0.0, 2.0, 0.0),
+            color: [1.0, 0.5, 0.25],
+            ambient: 1.5,
+        });
+        assert_eq!(vec4(0.0, 1.0, 0.0, 0.0), light.direction);
+        assert_eq!(vec4(1.0, 0.5, 0.25, 1.0), light.color);
+        assert_eq!(1.0, light.ambient);
+    }
+
+    #[test]
+    fn orthographic_height_matches_perspective() {
+        let distance = 100.0;
+        let height = orthographic_height(distance, FOV_Y);
+        let camera = |projection| {
+            calculate_camera_data(
+                512,
+                512,
+                vec3(0.0, 0.0, -distance),
+                Vec3::ZERO,
+                Vec3::ZERO,
+                UpAxis::Y,
+                projection,
+            )
+        };
+        let perspective = camera(Projection::Perspective { fov_y: FOV_Y });
+        let orthographic = camera(Projection::Orthographic { height });
+
+        // Points at the pivot distance should project to the same screen position.
+        let point = vec3(0.0, -height * 0.5, 0.0);
+        let ndc_perspective = perspective.view_projection.project_point3(point);
+        let ndc_orthographic = orthographic.view_projection.project_point3(point);
+        assert!((ndc_perspective.y - 1.0).abs() < 1e-4);
+        assert!((ndc_orthographic.y - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn quality_preset_from_str() {
+        assert_eq!(Ok(QualityPreset::Draft), "draft".parse());
+        assert_eq!(Ok(QualityPreset::Preview), "preview".parse());
+        assert_eq!(Ok(QualityPreset::High), "high".parse());
+        assert_eq!(
+            "unknown value \"ultra\", expected one of draft, preview, high",
+            "ultra".parse::<QualityPreset>().unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn tonemap_from_str() {
+        assert_eq!(Ok(Tonemap::None), "none".parse());
+        assert_eq!(Ok(Tonemap::Reinhard), "reinhard".parse());
+        assert_eq!(Ok(Tonemap::Aces), "aces".parse());
+        assert!("filmic".parse::<Tonemap>().is_err());
+    }
+
+    fn screen_extents(distance: f32) -> (Vec3, Vec3) {
+        let camera = calculate_camera_data(
+            512,
+            256,
+            vec3(0.0, 0.0, -distance),
+            Vec3::ZERO,
+            Vec3::ZERO,
+            UpAxis::Y,
+            Projection::Orthographic { height: 4.0 },
+        );
+
+        // Project the corners of a unit cube at the origin.
+        let corners = (0..8).map(|i| {
+            let corner = vec3((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32);
+            camera
+                .view_projection
+                .project_point3(corner - Vec3::splat(0.5))
+        });
+        corners.fold((Vec3::MAX, Vec3::MIN), |(min, max), p| {
+            (min.min(p), max.max(p))
+        })
+    }
+
+    #[test]
+    fn orthographic_extents_independent_of_distance() {
+        let (near_min, near_max) = screen_extents(10.0);
+        let (far_min, far_max) = screen_extents(1000.0);
+
+        assert!(near_min.truncate().abs_diff_eq(far_min.truncate(), 1e-5));
+        assert!(near_max.truncate().abs_diff_eq(far_max.truncate(), 1e-5));
+        // The cube is 1/4 of the height and 1/8 of the width.
+        assert!((near_max.y - near_min.y - 0.5).abs() < 1e-5);
+        assert!((near_max.x - near_min.x - 0.25).abs() < 1e-5);
+
+        // Closer objects should have larger depth values for reversed-z.
+        assert!(near_min.z > far_max.z);
+        assert!(near_max.z <= 1.0 && far_min.z >= 0.0);
+    }
+
+    #[test]
+    fn pick_view_projection_centers_pixel() {
+        let camera = view_space_camera(
+            512,
+            256,
+            Projection::Perspective {
+                fov_y: 90f32.to_radians(),
+            },
+        );
+
+        let point = vec3(1.0, 0.5, -4.0);
+        let ndc = camera.view_projection.project_point3(point).truncate();
+        let pick = pick_view_projection(&camera, ndc);
+        assert!(pick
+            .project_point3(point)
+            .truncate()
+            .abs_diff_eq(Vec2::ZERO, 1e-4));
+
+        // The edge of the picked pixel is at the edge of the 1x1 target.
+        let edge = vec3(ndc.x + 1.0 / 512.0, ndc.y, 0.5);
+        let edge = camera.view_projection.inverse().project_point3(edge);
+        assert!((pick.project_point3(edge).x - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn unpremultiply_alpha_partial() {
+        let mut image =
+            image::RgbaImage::from_raw(3, 1, vec![64, 32, 0, 128, 10, 20, 30, 255, 0, 0, 0, 0])
+                .unwrap();
+        unpremultiply_alpha(&mut image);
+        assert_eq!(
+            vec![128, 64, 0, 128, 10, 20, 30, 255, 0, 0, 0, 0],
+            image.into_raw()
+        );
     }
 }
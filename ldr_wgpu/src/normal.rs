@@ -1,15 +1,19 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 
 use glam::Vec3;
 
-// TODO: Add an option to index this separately instead of returning the set?
-// i.e. normals + normals indices
+/// Calculate smooth normals for each face vertex from the adjacent faces.
+///
+/// Adjacent faces are only smoothed together if the angle between their normals
+/// is less than `smoothing_angle` in radians.
+/// Smaller angles produce sharper creases.
 pub fn triangle_face_vertex_normals(
     vertices: &[Vec3],
     vertex_indices: &[u32],
+    smoothing_angle: f32,
 ) -> (Vec<BTreeSet<usize>>, Vec<Vec3>) {
     // TODO: move this to ldr_tools.
-    // TODO: Smooth normals based on hard edges and face angle threshold.
+    // TODO: Smooth normals based on hard edges.
     let face_normals: Vec<_> = vertex_indices
         .chunks_exact(3)
         .map(|face| {
@@ -35,7 +39,6 @@ pub fn triangle_face_vertex_normals(
     }
 
     // Use a BTreeSet for a consistent hash value.
-    // Use a large angle threshold to only add creases on extreme angle changes.
     let filtered_adjacent_faces: Vec<BTreeSet<_>> = vertex_indices
         .iter()
         .enumerate()
@@ -45,7 +48,7 @@ pub fn triangle_face_vertex_normals(
             vertex_adjacent_faces[*vertex_index as usize]
                 .iter()
                 .copied()
-                .filter(|f| face_normals[*f].angle_between(face_normal).abs() < 90f32.to_radians())
+                .filter(|f| face_normals[*f].angle_between(face_normal).abs() < smoothing_angle)
                 .collect()
         })
         .collect();
@@ -66,6 +69,27 @@ pub fn triangle_face_vertex_normals(
     (filtered_adjacent_faces, face_vertex_normals)
 }
 
+/// Deduplicate `normals` into unique normals and an index for each input normal.
+pub fn index_normals(normals: &[Vec3]) -> (Vec<Vec3>, Vec<u32>) {
+    let mut unique_normals = Vec::new();
+    let mut index_by_normal = HashMap::new();
+
+    // Compare the bits since floats don't implement Hash or Eq.
+    let indices = normals
+        .iter()
+        .map(|n| {
+            *index_by_normal
+                .entry(n.to_array().map(f32::to_bits))
+                .or_insert_with(|| {
+                    unique_normals.push(*n);
+                    unique_normals.len() as u32 - 1
+                })
+        })
+        .collect();
+
+    (unique_normals, indices)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,6 +109,7 @@ mod tests {
                 vec3(0f32, 0f32, 1f32),
             ],
             &[0, 1, 2],
+            90f32.to_radians(),
         );
 
         assert_eq!(vec![set([0]); 3], adjacent);
@@ -102,6 +127,7 @@ mod tests {
                 vec3(0.000000, 0.707000, 0.000000),
             ],
             &[0, 3, 1, 0, 1, 2, 1, 3, 2, 2, 3, 0],
+            90f32.to_radians(),
         );
         // The angle threshold should split all faces.
         assert_eq!(
@@ -132,4 +158,62 @@ mod tests {
     }
 
     // TODO: Test a simple 2D mesh with and without hard edges
+
+    fn box_normals(smoothing_angle: f32) -> Vec<Vec3> {
+        let vertices: Vec<_> = (0..8)
+            .map(|i| vec3((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32))
+            .collect();
+        // Two triangles for each side with outward facing normals.
+        let vertex_indices = [
+            0, 2, 3, 0, 3, 1, // -Z
+            4, 5, 7, 4, 7, 6, // +Z
+            0, 4, 6, 0, 6, 2, // -X
+            1, 3, 7, 1, 7, 5, // +X
+            0, 1, 5, 0, 5, 4, // -Y
+            2, 6, 7, 2, 7, 3, // +Y
+        ];
+        triangle_face_vertex_normals(&vertices, &vertex_indices, smoothing_angle).1
+    }
+
+    #[test]
+    fn normals_box_sharp_edges() {
+        // The 90 degree edges exceed the threshold, so each side stays flat.
+        let normals = box_normals(45f32.to_radians());
+        assert_eq!(36, normals.len());
+        for (side, side_normals) in normals.chunks_exact(6).enumerate() {
+            let expected = [
+                vec3(0.0, 0.0, -1.0),
+                vec3(0.0, 0.0, 1.0),
+                vec3(-1.0, 0.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, -1.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+            ][side];
+            assert!(side_normals.iter().all(|n| n.abs_diff_eq(expected, 1e-6)));
+        }
+    }
+
+    #[test]
+    fn normals_box_smooth_edges() {
+        // Every corner averages the normals of all three sides.
+        let normals = box_normals(120f32.to_radians());
+        assert_eq!(36, normals.len());
+        for n in normals {
+            assert!(n.x.abs() > 0.1 && n.y.abs() > 0.1 && n.z.abs() > 0.1, "{n}");
+            assert!((n.length() - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn index_normals_empty() {
+        assert_eq!((Vec::new(), Vec::new()), index_normals(&[]));
+    }
+
+    #[test]
+    fn index_normals_duplicates() {
+        assert_eq!(
+            (vec![Vec3::X, Vec3::Y], vec![0, 1, 0, 0]),
+            index_normals(&[Vec3::X, Vec3::Y, Vec3::X, Vec3::X])
+        );
+    }
 }
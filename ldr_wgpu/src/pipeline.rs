@@ -1,30 +1,119 @@
-use crate::{depth_stencil_reversed, shader, MSAA_SAMPLES};
+use crate::{depth_stencil_reversed, shader, PICK_FORMAT};
 
 pub fn create_pipeline(
     device: &wgpu::Device,
     surface_format: wgpu::TextureFormat,
-    edges: bool,
+    sample_count: u32,
 ) -> wgpu::RenderPipeline {
+    model_pipeline(
+        device,
+        surface_format,
+        sample_count,
+        wgpu::ColorWrites::all(),
+        None,
+        true,
+        "Render Pipeline",
+    )
+}
+
+/// A model pipeline for transparent parts that culls faces with `cull_mode`.
+/// Drawing a part with front faces culled and then back faces culled
+/// blends the inside of the part behind the outside.
+///
+/// Depth is tested but not written, so overlapping transparent parts all blend.
+pub fn create_transparent_pipeline(
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+    sample_count: u32,
+    cull_mode: wgpu::Face,
+) -> wgpu::RenderPipeline {
+    model_pipeline(
+        device,
+        surface_format,
+        sample_count,
+        wgpu::ColorWrites::all(),
+        Some(cull_mode),
+        false,
+        "Transparent Pipeline",
+    )
+}
+
+/// A model pipeline that only writes depth for occluding edges in wireframe renders.
+pub fn create_depth_pipeline(
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    model_pipeline(
+        device,
+        surface_format,
+        sample_count,
+        wgpu::ColorWrites::empty(),
+        None,
+        true,
+        "Depth Pipeline",
+    )
+}
+
+/// A model pipeline that writes the instance and depth of the closest face for picking.
+pub fn create_pick_pipeline(device: &wgpu::Device) -> wgpu::RenderPipeline {
     let module = shader::model::create_shader_module(device);
     let render_pipeline_layout = shader::model::create_pipeline_layout(device);
 
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render Pipeline"),
+        label: Some("Pick Pipeline"),
         layout: Some(&render_pipeline_layout),
         vertex: shader::model::vertex_state(
             &module,
             &shader::model::vs_main_entry(
                 wgpu::VertexStepMode::Vertex,
                 wgpu::VertexStepMode::Instance,
+                wgpu::VertexStepMode::Instance,
             ),
         ),
         fragment: Some(wgpu::FragmentState {
             module: &module,
-            entry_point: if edges {
-                shader::model::ENTRY_FS_EDGE_MAIN
-            } else {
-                shader::model::ENTRY_FS_MAIN
-            },
+            entry_point: shader::model::ENTRY_FS_PICK,
+            // Integer formats don't support blending.
+            targets: &[Some(PICK_FORMAT.into())],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(depth_stencil_reversed()),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn model_pipeline(
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+    sample_count: u32,
+    write_mask: wgpu::ColorWrites,
+    cull_mode: Option<wgpu::Face>,
+    depth_write_enabled: bool,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    let module = shader::model::create_shader_module(device);
+    let render_pipeline_layout = shader::model::create_pipeline_layout(device);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&render_pipeline_layout),
+        vertex: shader::model::vertex_state(
+            &module,
+            &shader::model::vs_main_entry(
+                wgpu::VertexStepMode::Vertex,
+                wgpu::VertexStepMode::Instance,
+                wgpu::VertexStepMode::Instance,
+            ),
+        ),
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: shader::model::ENTRY_FS_MAIN,
             targets: &[Some(wgpu::ColorTargetState {
                 format: surface_format,
                 // Premultiplied alpha.
@@ -34,33 +123,185 @@ pub fn create_pipeline(
                         dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
                         operation: wgpu::BlendOperation::Add,
                     },
+                    // Blend alpha the same way as color.
+                    // This keeps edges correct when clearing to a transparent background.
                     alpha: wgpu::BlendComponent {
                         src_factor: wgpu::BlendFactor::One,
-                        dst_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
                         operation: wgpu::BlendOperation::Add,
                     },
                 }),
-                write_mask: wgpu::ColorWrites::all(),
+                write_mask,
             })],
             compilation_options: wgpu::PipelineCompilationOptions::default(),
         }),
         // TODO: Culling doesn't always work?
-        primitive: if edges {
-            wgpu::PrimitiveState {
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Line,
-                topology: wgpu::PrimitiveTopology::LineList,
-                ..Default::default()
-            }
-        } else {
-            wgpu::PrimitiveState {
-                cull_mode: None,
-                ..Default::default()
-            }
+        primitive: wgpu::PrimitiveState {
+            cull_mode,
+            ..Default::default()
         },
-        depth_stencil: Some(depth_stencil_reversed()),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            depth_write_enabled,
+            ..depth_stencil_reversed()
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+pub fn create_edge_pipeline(
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let module = shader::edge::create_shader_module(device);
+    let render_pipeline_layout = shader::edge::create_pipeline_layout(device);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Edge Pipeline"),
+        layout: Some(&render_pipeline_layout),
+        vertex: shader::edge::vertex_state(
+            &module,
+            &shader::edge::vs_main_entry(
+                wgpu::VertexStepMode::Vertex,
+                wgpu::VertexStepMode::Instance,
+            ),
+        ),
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: shader::edge::ENTRY_FS_MAIN,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: surface_format,
+                blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::all(),
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        // Edges are expanded to quads facing the camera.
+        primitive: wgpu::PrimitiveState {
+            cull_mode: None,
+            ..Default::default()
+        },
+        // The antialiased borders shouldn't occlude anything.
+        // Pull edges towards the camera to avoid z-fighting with faces.
+        // The bias is positive for reversed-z and works for any projection.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            bias: wgpu::DepthBiasState {
+                constant: 16,
+                slope_scale: 1.0,
+                clamp: 0.0,
+            },
+            ..depth_stencil_reversed()
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+pub fn create_blit_pipeline(
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let module = shader::blit::create_shader_module(device);
+    let render_pipeline_layout = shader::blit::create_pipeline_layout(device);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Blit Pipeline"),
+        layout: Some(&render_pipeline_layout),
+        vertex: shader::blit::vertex_state(&module, &shader::blit::vs_main_entry()),
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: shader::blit::ENTRY_FS_MAIN,
+            // The colors are already blended, so just overwrite the output.
+            targets: &[Some(surface_format.into())],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+pub fn create_accumulate_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let module = shader::accumulate::create_shader_module(device);
+    let render_pipeline_layout = shader::accumulate::create_pipeline_layout(device);
+
+    // Blend with a constant weight of 1/n to update the running average
+    // without a separate pass to divide the sum by the sample count.
+    let blend = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Constant,
+        dst_factor: wgpu::BlendFactor::OneMinusConstant,
+        operation: wgpu::BlendOperation::Add,
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Accumulate Pipeline"),
+        layout: Some(&render_pipeline_layout),
+        vertex: shader::accumulate::vertex_state(&module, &shader::accumulate::vs_main_entry()),
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: shader::accumulate::ENTRY_FS_MAIN,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: blend,
+                    alpha: blend,
+                }),
+                write_mask: wgpu::ColorWrites::all(),
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+pub fn create_grid_pipeline(
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let module = shader::grid::create_shader_module(device);
+    let render_pipeline_layout = shader::grid::create_pipeline_layout(device);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Grid Pipeline"),
+        layout: Some(&render_pipeline_layout),
+        vertex: shader::grid::vertex_state(&module, &shader::grid::vs_main_entry()),
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: shader::grid::ENTRY_FS_MAIN,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: surface_format,
+                blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::all(),
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        // Test against the model depth without occluding the model.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            ..depth_stencil_reversed()
+        }),
         multisample: wgpu::MultisampleState {
-            count: MSAA_SAMPLES,
+            count: sample_count,
             ..Default::default()
         },
         multiview: None,
@@ -1,128 +1,921 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use glam::{Mat4, Vec4Swizzles};
-use ldr_tools::{LDrawColor, LDrawSceneInstanced};
-use log::info;
-use meshopt::optimize_vertex_cache;
+use glam::{Mat4, Vec3, Vec4Swizzles};
+use ldr_tools::{GeometrySettings, LDrawColor, LDrawSceneInstanced};
+use log::{info, warn};
+use meshopt::{
+    optimize_overdraw_in_place_decoder, optimize_vertex_cache, optimize_vertex_fetch_in_place,
+    simplify_decoder, SimplifyOptions,
+};
 use rayon::prelude::*;
 use wgpu::util::DeviceExt;
 
-use crate::geometry::IndexedVertexData;
+use crate::{
+    color::{resolved_color, ResolvedColor},
+    geometry::IndexedVertexData,
+};
 
 /// Combined data for every part in the scene.
-/// Renderable with a single multidraw indirect call.
+/// Renderable with a single multidraw indirect call for each [BufferChunk].
 pub struct IndirectSceneData {
     pub instance_transforms_buffer: wgpu::Buffer,
+    /// The index of each instance in [IndirectSceneData::instance_transforms_buffer].
+    pub instance_index_buffer: wgpu::Buffer,
     pub instance_bounds_buffer: wgpu::Buffer,
     pub visibility_buffer: wgpu::Buffer,
     pub new_visibility_buffer: wgpu::Buffer,
     pub scanned_new_visibility_buffer: wgpu::Buffer,
     pub scanned_visibility_buffer: wgpu::Buffer,
     pub transparent_buffer: wgpu::Buffer,
+    /// Per instance overrides for culling using [VisibilityOverride] values.
+    pub visibility_override_buffer: wgpu::Buffer,
+    /// Per instance flags for highlighting selected instances.
+    pub selection_buffer: wgpu::Buffer,
+    /// The compacted draw count for each chunk in [IndirectData::chunks].
     pub compacted_count_buffer: wgpu::Buffer,
     pub compacted_count_staging_buffer: wgpu::Buffer,
-    pub vertex_buffer: wgpu::Buffer,
+    /// The chunk containing each draw for compacting draws within each chunk.
+    pub draw_chunks_buffer: wgpu::Buffer,
+    /// The visibility followed by the new visibility for each instance
+    /// if enabled with [SceneSettings::readback_culling].
+    pub culling_staging_buffer: Option<wgpu::Buffer>,
     pub solid: IndirectData,
     pub edges: IndirectData,
+    /// Contiguous ranges of [IndirectSceneData::draws] sharing the same material.
+    pub material_ranges: Vec<MaterialDrawRange>,
+    /// Contiguous ranges of [IndirectSceneData::draws] for each unique part and color.
+    pub part_ranges: Vec<PartDrawRange>,
+    /// Named sets of instances that can be hidden or transformed together.
+    pub groups: Vec<InstanceGroup>,
+    bounds: (Vec3, Vec3),
+    // The initial values on the CPU for applying group transforms.
+    instance_transforms: Vec<Mat4>,
+    instance_bounds: Vec<crate::shader::culling::InstanceBounds>,
+    // The current bounds including group transforms.
+    instance_world_bounds: Vec<crate::shader::culling::InstanceBounds>,
+    // The untransformed bounds for each unique part name.
+    part_bounds: HashMap<String, crate::shader::culling::InstanceBounds>,
+    // A CPU copy of the visibility override buffer.
+    visibility_overrides: Vec<VisibilityOverride>,
+    // The camera position used for the current order of transparent draws on the GPU.
+    transparent_sort_position: Option<Vec3>,
+}
+
+/// A set of instances for applying operations like hiding or transforming together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceGroup {
+    pub name: String,
+    /// Indices into [IndirectSceneData::draws] for each instance in the group.
+    pub instances: Vec<usize>,
+}
+
+/// An instance index passed to [crate::RenderData::add_group] isn't in the scene's draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidInstance {
+    pub index: usize,
+    pub instance_count: usize,
+}
+
+impl std::fmt::Display for InvalidInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "instance {} is out of range for a scene with {} instances",
+            self.index, self.instance_count
+        )
+    }
+}
+
+impl std::error::Error for InvalidInstance {}
+
+/// Check that each of `instances` is less than `instance_count`.
+pub(crate) fn check_instances(
+    instances: &[usize],
+    instance_count: usize,
+) -> Result<(), InvalidInstance> {
+    match instances.iter().find(|i| **i >= instance_count) {
+        Some(index) => Err(InvalidInstance {
+            index: *index,
+            instance_count,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// How culling applies to an instance.
+/// The values match the visibility override buffer used for culling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VisibilityOverride {
+    /// Use frustum and occlusion culling.
+    #[default]
+    Culled = 0,
+    /// Always draw the instance even if it would be culled.
+    Visible = 1,
+    /// Never draw the instance.
+    Hidden = 2,
+}
+
+/// Errors while loading a scene.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SceneError {
+    /// The LDraw file or library folder doesn't exist.
+    FileNotFound(PathBuf),
+    /// The scene references a part with no loaded geometry.
+    MissingGeometry(String),
+    /// A single part or the combined instance data is too large for a buffer on this device.
+    BufferSizeExceeded {
+        label: &'static str,
+        size: u64,
+        max_size: u64,
+    },
+    /// The multi-part file has no model with the requested name.
+    SubmodelNotFound {
+        name: String,
+        available: Vec<String>,
+    },
+    /// Reading or writing a file failed.
+    Io { path: PathBuf, message: String },
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::FileNotFound(path) => write!(f, "file not found: {path:?}"),
+            SceneError::MissingGeometry(name) => write!(f, "no geometry for part {name:?}"),
+            SceneError::BufferSizeExceeded {
+                label,
+                size,
+                max_size,
+            } => write!(
+                f,
+                "{label} size of {size} bytes exceeds the device limit of {max_size} bytes"
+            ),
+            SceneError::SubmodelNotFound { name, available } => write!(
+                f,
+                "submodel {name:?} not found, available submodels: {}",
+                available.join(", ")
+            ),
+            SceneError::Io { path, message } => write!(f, "{path:?}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// The current stage when loading a scene with [load_scene_with_progress]
+/// or [crate::RenderData::new_with_progress].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadProgress {
+    /// Reading the LDraw file and its referenced parts from the library.
+    Parsing,
+    /// Calculating normals and optimizing the geometry for `total` unique parts in parallel.
+    ProcessingParts { processed: usize, total: usize },
+    /// Adding colored parts and their instances to the combined scene data.
+    CombiningParts { processed: usize, total: usize },
+    /// Creating the GPU buffers for the combined scene data.
+    Uploading,
+}
+
+impl std::fmt::Display for LoadProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadProgress::Parsing => write!(f, "Parsing"),
+            LoadProgress::ProcessingParts { processed, total } => {
+                write!(f, "Processing {processed}/{total} parts")
+            }
+            LoadProgress::CombiningParts { processed, total } => {
+                write!(f, "Combining {processed}/{total} parts")
+            }
+            LoadProgress::Uploading => write!(f, "Uploading"),
+        }
+    }
+}
+
+/// Load the LDraw file at `path` using the parts in `ldraw_path`.
+pub fn load_scene(
+    path: &str,
+    ldraw_path: &str,
+    settings: &GeometrySettings,
+) -> Result<LDrawSceneInstanced, SceneError> {
+    load_scene_with_progress(path, ldraw_path, settings, |_| ())
+}
+
+/// Load the scene like [load_scene] while reporting progress.
+///
+/// The `progress` callback is called with [LoadProgress::Parsing] before parsing.
+/// ldr_tools parses the file and its parts in a single call, so there are no later updates.
+pub fn load_scene_with_progress(
+    path: &str,
+    ldraw_path: &str,
+    settings: &GeometrySettings,
+    mut progress: impl FnMut(LoadProgress),
+) -> Result<LDrawSceneInstanced, SceneError> {
+    for path in [path, ldraw_path] {
+        if !Path::new(path).exists() {
+            return Err(SceneError::FileNotFound(path.into()));
+        }
+    }
+    progress(LoadProgress::Parsing);
+    Ok(ldr_tools::load_file_instanced(
+        path,
+        ldraw_path,
+        &[],
+        settings,
+    ))
+}
+
+/// Load the model `name` from the multi-part `.mpd` file at `path` instead of the main model.
+/// Names are case insensitive like other LDraw file names.
+///
+/// Returns [SceneError::SubmodelNotFound] with the names from [submodel_names] if no model matches.
+pub fn load_submodel(
+    path: &str,
+    ldraw_path: &str,
+    name: &str,
+    settings: &GeometrySettings,
+) -> Result<LDrawSceneInstanced, SceneError> {
+    for path in [path, ldraw_path] {
+        if !Path::new(path).exists() {
+            return Err(SceneError::FileNotFound(path.into()));
+        }
+    }
+    let mpd = std::fs::read_to_string(path).map_err(|e| SceneError::Io {
+        path: path.into(),
+        message: e.to_string(),
+    })?;
+
+    let reordered = main_model_first(&mpd, name).ok_or_else(|| SceneError::SubmodelNotFound {
+        name: name.to_string(),
+        available: submodel_names(&mpd),
+    })?;
+
+    // ldr_tools loads the first model in the file, so load a copy starting with the submodel.
+    // Separate files referenced by the model are still found in the original folder.
+    let temp_file = TempFile::new(reordered.as_bytes())?;
+
+    let folder = Path::new(path)
+        .parent()
+        .and_then(|p| p.to_str())
+        .unwrap_or(".");
+    Ok(ldr_tools::load_file_instanced(
+        temp_file.0.to_str().unwrap_or_default(),
+        ldraw_path,
+        &[folder],
+        settings,
+    ))
+}
+
+// A uniquely named file in the temp directory that is removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(contents: &[u8]) -> Result<Self, SceneError> {
+        // Concurrent loads need different names even within the same process.
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("ldr_wgpu_{}_{id}.mpd", std::process::id()));
+
+        let io_error = |e: std::io::Error| SceneError::Io {
+            path: path.clone(),
+            message: e.to_string(),
+        };
+        // Fail instead of writing through an existing file or symlink with the same name.
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(io_error)?;
+        let temp_file = Self(path.clone());
+        file.write_all(contents).map_err(io_error)?;
+        Ok(temp_file)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Failed to remove {:?}: {e}", self.0);
+        }
+    }
+}
+
+/// The names of the models in the text of a multi-part `.mpd` file in file order.
+/// The first name is the main model.
+/// Files without any `0 FILE` lines return an empty list.
+pub fn submodel_names(mpd: &str) -> Vec<String> {
+    mpd.lines()
+        .filter_map(file_name)
+        .map(ToString::to_string)
+        .collect()
+}
+
+// https://www.ldraw.org/article/47.html
+fn file_name(line: &str) -> Option<&str> {
+    let line = line.trim_start().strip_prefix('0')?.trim_start();
+    let name = line.strip_prefix("FILE")?;
+    // The name may contain spaces but must be separated from the meta command.
+    name.starts_with(char::is_whitespace)
+        .then(|| name.trim())
+        .filter(|name| !name.is_empty())
+}
+
+/// Move the `0 FILE` block for `name` to the start of the file.
+fn main_model_first(mpd: &str, name: &str) -> Option<String> {
+    // Split at each FILE line while keeping any header text with the first block.
+    let mut blocks: Vec<(Option<&str>, String)> = vec![(None, String::new())];
+    for line in mpd.lines() {
+        if let Some(file) = file_name(line) {
+            blocks.push((Some(file), String::new()));
+        }
+        if let Some((_, text)) = blocks.last_mut() {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+
+    let index = blocks
+        .iter()
+        .position(|(file, _)| file.is_some_and(|f| f.eq_ignore_ascii_case(name)))?;
+    let block = blocks.remove(index);
+    blocks.insert(0, block);
+
+    Some(blocks.into_iter().map(|(_, text)| text).collect())
+}
+
+/// Per instance culling results read back from the GPU.
+///
+/// Transparent draws are sorted by distance on the GPU,
+/// so their `newly_visible` values may not match the order of [IndirectSceneData::draws].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CullingResults {
+    /// Instances that passed frustum and occlusion culling.
+    /// Transparent instances are always `false` since they are only drawn in the second pass.
+    pub visible: Vec<bool>,
+    /// Instances that passed culling this frame but not the previous frame.
+    pub newly_visible: Vec<bool>,
+}
+
+/// The shading category for a draw.
+/// Draws with the same material can share the same pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Material {
+    Opaque,
+    Transparent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaterialDrawRange {
+    pub material: Material,
+    pub draws: Range<usize>,
+}
+
+/// The instances of a part in a single color like for listing the parts in a model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartDrawRange {
+    pub name: String,
+    /// The color code for the part after replacing code 16 with the color of the parent.
+    pub color: u32,
+    pub draws: Range<usize>,
+}
+
+/// A bounding sphere and axis-aligned bounding box in the same space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub sphere_center: Vec3,
+    pub sphere_radius: f32,
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl From<crate::shader::culling::InstanceBounds> for Bounds {
+    fn from(bounds: crate::shader::culling::InstanceBounds) -> Self {
+        Self {
+            sphere_center: bounds.sphere.xyz(),
+            sphere_radius: bounds.sphere.w,
+            min: bounds.min_xyz.xyz(),
+            max: bounds.max_xyz.xyz(),
+        }
+    }
+}
+
+/// The GPU memory in bytes used by the buffers for a loaded scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryReport {
+    /// Solid and edge vertex buffers.
+    pub vertices: u64,
+    /// Solid and edge index buffers.
+    pub indices: u64,
+    /// Per instance transforms, indices, and culling bounds.
+    pub instances: u64,
+    /// Indirect draw, visibility, and compaction buffers.
+    pub draws: u64,
+}
+
+impl MemoryReport {
+    pub fn total(&self) -> u64 {
+        self.vertices + self.indices + self.instances + self.draws
+    }
+}
+
+/// Settings for processing part geometry when loading a scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneSettings {
+    /// Reorder triangles to improve vertex reuse on the GPU.
+    /// Disabling this reduces load times but may reduce rendering performance.
+    pub optimize_vertex_cache: bool,
+    /// Reorder triangles to reduce overdraw after any vertex cache optimization.
+    /// The threshold like `1.05` limits how much worse the vertex cache efficiency can get.
+    /// This increases load times and is disabled by default.
+    pub optimize_overdraw: Option<f32>,
+    /// Simplify parts with more triangles than this limit when loading.
+    /// This reduces memory usage and render times for previews and thumbnails
+    /// where small details aren't visible.
+    pub max_triangles_per_part: Option<usize>,
+    /// Copy culling results each frame for reading with [crate::RenderData::culling_results].
+    /// This is intended for debugging and tests and is disabled by default.
+    pub readback_culling: bool,
+    /// Edge line colors by color code like those returned by [crate::edge_colors].
+    /// Parts with colors not in this table use black edges.
+    pub edge_colors: HashMap<u32, [f32; 4]>,
+    /// A folder for caching processed part geometry between runs.
+    /// This speeds up loading large scenes but is disabled by default.
+    pub cache_dir: Option<PathBuf>,
+    /// Skip instances with the same part, color, and transform as another instance.
+    /// Flattened submodels sometimes place identical copies that only cause z-fighting.
+    /// This reduces draws and culling work but is disabled by default.
+    pub deduplicate_instances: bool,
+    /// The maximum angle in radians between adjacent faces to smooth their normals.
+    /// Smaller angles add creases on more edges like the sides of studs.
+    /// The default of 90 degrees keeps right angles sharp.
+    pub smoothing_angle: f32,
+}
+
+impl Default for SceneSettings {
+    fn default() -> Self {
+        Self {
+            optimize_vertex_cache: true,
+            optimize_overdraw: None,
+            max_triangles_per_part: None,
+            readback_culling: false,
+            edge_colors: HashMap::new(),
+            cache_dir: None,
+            deduplicate_instances: false,
+            smoothing_angle: 90f32.to_radians(),
+        }
+    }
 }
 
 pub struct IndirectData {
-    pub index_buffer: wgpu::Buffer,
+    /// Consecutive ranges of draws with separate vertex and index buffers.
+    pub chunks: Vec<BufferChunk>,
     pub indirect_buffer: wgpu::Buffer,
     pub compacted_indirect_buffer: wgpu::Buffer,
     pub draw_count: u32,
+    /// A CPU copy of the uncompacted draws in `indirect_buffer`.
+    /// Transparent draws on the GPU may be reordered from back to front for the camera.
+    pub draws: Vec<DrawIndexedIndirect>,
+}
+
+/// The vertex and index buffers for a range of draws.
+///
+/// Large scenes like those with logo studs are split into
+/// multiple chunks to keep each buffer within the device's `max_buffer_size`.
+pub struct BufferChunk {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    /// The draws in [IndirectData::draws] with offsets relative to these buffers.
+    pub draws: Range<usize>,
+    /// The transparent draws at the end of `draws`.
+    /// These keep the same position in the compacted indirect buffer,
+    /// and culled draws have an instance count of zero.
+    pub transparent_draws: Range<usize>,
+    /// The compacted opaque draws start at `draws.start` in the compacted indirect buffer.
     pub compacted_draw_count: u32,
 }
 
 // wgpu already provides this type.
 // Make our own so we can derive bytemuck.
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct DrawIndexedIndirect {
-    vertex_count: u32,
-    instance_count: u32,
-    base_index: u32,
-    vertex_offset: i32,
-    base_instance: u32,
+    /// The number of indices to draw.
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    /// The offset into the index buffer.
+    pub base_index: u32,
+    /// The offset added to each index into the vertex buffer.
+    pub vertex_offset: i32,
+    /// The offset into the instance transforms buffer.
+    pub base_instance: u32,
+}
+
+impl IndirectSceneData {
+    /// The uncompacted draws for the solid geometry with one draw per instance.
+    ///
+    /// Each draw can be issued separately using [wgpu::RenderPass::draw_indexed]
+    /// with the solid index buffer and vertex buffer from the [BufferChunk] containing the draw
+    /// and the instance transforms buffer.
+    pub fn draws(&self) -> &[DrawIndexedIndirect] {
+        &self.solid.draws
+    }
+
+    /// The uncompacted draws for the edge geometry in the same order as [Self::draws].
+    pub fn edge_draws(&self) -> &[DrawIndexedIndirect] {
+        &self.edges.draws
+    }
+
+    /// The combined world space axis-aligned bounding box `(min, max)` of all instances.
+    /// This is zero for empty scenes.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.bounds
+    }
+
+    /// The part local bounds for the part `name` shared by all colors and instances.
+    /// Returns `None` if the part isn't in the scene.
+    pub fn part_bounds(&self, name: &str) -> Option<Bounds> {
+        self.part_bounds.get(name).copied().map(Bounds::from)
+    }
+
+    /// The world space bounds for each of the [IndirectSceneData::draws]
+    /// including any transforms applied with [crate::RenderData::set_group_transform].
+    /// These are the same bounds used for culling.
+    pub fn instance_bounds(&self) -> Vec<Bounds> {
+        self.instance_world_bounds
+            .iter()
+            .copied()
+            .map(Bounds::from)
+            .collect()
+    }
+
+    /// The color code and RGBA color for each of the [IndirectSceneData::draws]
+    /// using the same `color_table` used for loading.
+    pub fn instance_colors(&self, color_table: &HashMap<u32, LDrawColor>) -> Vec<ResolvedColor> {
+        part_instance_colors(&self.part_ranges, color_table)
+    }
+
+    /// Set the culling behavior for `instances` on the GPU and in the CPU copy.
+    /// Indices outside the scene's draws are ignored.
+    pub(crate) fn write_visibility_overrides(
+        &mut self,
+        queue: &wgpu::Queue,
+        instances: &[usize],
+        visibility: VisibilityOverride,
+    ) {
+        for i in instances {
+            if let Some(value) = self.visibility_overrides.get_mut(*i) {
+                *value = visibility;
+            }
+        }
+
+        // Write all values at once to avoid a separate copy for each instance.
+        let values: Vec<_> = self
+            .visibility_overrides
+            .iter()
+            .map(|v| *v as u32)
+            .collect();
+        queue.write_buffer(
+            &self.visibility_override_buffer,
+            0,
+            bytemuck::cast_slice(&values),
+        );
+    }
+
+    /// Select only the `instances` for highlighting and deselect all other instances.
+    pub(crate) fn write_selection(&self, queue: &wgpu::Queue, instances: &[usize]) {
+        queue.write_buffer(
+            &self.selection_buffer,
+            0,
+            bytemuck::cast_slice(&selection_flags(self.solid.draws.len(), instances)),
+        );
+    }
+
+    /// The uncompacted solid draws with no instances for draws hidden with [VisibilityOverride::Hidden].
+    /// This skips hidden instances without culling or compacting the draws.
+    pub(crate) fn unhidden_draws(&self) -> Vec<DrawIndexedIndirect> {
+        self.solid
+            .draws
+            .iter()
+            .zip(&self.visibility_overrides)
+            .map(|(draw, visibility)| DrawIndexedIndirect {
+                instance_count: (*visibility != VisibilityOverride::Hidden) as u32,
+                ..*draw
+            })
+            .collect()
+    }
+
+    /// Replace the transforms for `instances` with `transform` applied to their initial transforms.
+    /// Indices outside the scene's draws are ignored.
+    pub(crate) fn write_transforms(
+        &mut self,
+        queue: &wgpu::Queue,
+        instances: &[usize],
+        transform: Mat4,
+    ) {
+        // Moving instances can change the order of transparent draws.
+        self.transparent_sort_position = None;
+
+        let instance_count = self.instance_transforms.len();
+        for i in instances.iter().filter(|i| **i < instance_count) {
+            let instance_transform = transform * self.instance_transforms[*i];
+            queue.write_buffer(
+                &self.instance_transforms_buffer,
+                (i * std::mem::size_of::<Mat4>()) as u64,
+                bytemuck::cast_slice(&[instance_transform]),
+            );
+
+            // Update the bounds to keep culling correct.
+            let bounds = transform_bounds(self.instance_bounds[*i], transform);
+            self.instance_world_bounds[*i] = bounds;
+            queue.write_buffer(
+                &self.instance_bounds_buffer,
+                (i * std::mem::size_of_val(&bounds)) as u64,
+                bytemuck::cast_slice(&[bounds]),
+            );
+        }
+    }
+
+    /// Reorder the transparent draws in each chunk on the GPU from back to front
+    /// relative to `camera_position` for correct blending.
+    /// This does nothing if the draws are already sorted for `camera_position`.
+    pub(crate) fn sort_transparent_draws(&mut self, queue: &wgpu::Queue, camera_position: Vec3) {
+        if self.transparent_sort_position == Some(camera_position) {
+            return;
+        }
+        self.transparent_sort_position = Some(camera_position);
+
+        // Draws can only move within a chunk since offsets are relative to the chunk's buffers.
+        for chunk in &self.solid.chunks {
+            let range = chunk.transparent_draws.clone();
+            if range.is_empty() {
+                continue;
+            }
+
+            let order = back_to_front(
+                &self.solid.draws[range.clone()],
+                &self.instance_world_bounds,
+                camera_position,
+            );
+            let offset = (range.start * std::mem::size_of::<DrawIndexedIndirect>()) as u64;
+            for data in [&self.solid, &self.edges] {
+                let draws: Vec<_> = order.iter().map(|i| data.draws[range.start + i]).collect();
+                queue.write_buffer(&data.indirect_buffer, offset, bytemuck::cast_slice(&draws));
+            }
+        }
+    }
+
+    /// The sizes of all the scene's buffers.
+    pub fn memory_usage(&self) -> MemoryReport {
+        let size = |buffers: &[&wgpu::Buffer]| -> u64 { buffers.iter().map(|b| b.size()).sum() };
+        MemoryReport {
+            vertices: [&self.solid, &self.edges]
+                .iter()
+                .flat_map(|d| &d.chunks)
+                .map(|c| c.vertex_buffer.size())
+                .sum(),
+            indices: [&self.solid, &self.edges]
+                .iter()
+                .flat_map(|d| &d.chunks)
+                .map(|c| c.index_buffer.size())
+                .sum(),
+            instances: size(&[
+                &self.instance_transforms_buffer,
+                &self.instance_index_buffer,
+                &self.instance_bounds_buffer,
+            ]),
+            draws: size(&[
+                &self.solid.indirect_buffer,
+                &self.solid.compacted_indirect_buffer,
+                &self.edges.indirect_buffer,
+                &self.edges.compacted_indirect_buffer,
+                &self.visibility_buffer,
+                &self.new_visibility_buffer,
+                &self.scanned_visibility_buffer,
+                &self.scanned_new_visibility_buffer,
+                &self.transparent_buffer,
+                &self.visibility_override_buffer,
+                &self.selection_buffer,
+                &self.compacted_count_buffer,
+                &self.compacted_count_staging_buffer,
+                &self.draw_chunks_buffer,
+            ]) + self.culling_staging_buffer.as_ref().map_or(0, |b| b.size()),
+        }
+    }
+
+    /// Free the GPU memory for all of the scene's buffers without waiting for them to be dropped.
+    ///
+    /// Any draws using the scene after calling this will fail validation.
+    pub fn destroy(&self) {
+        for buffer in [
+            &self.instance_transforms_buffer,
+            &self.instance_index_buffer,
+            &self.instance_bounds_buffer,
+            &self.visibility_buffer,
+            &self.new_visibility_buffer,
+            &self.scanned_new_visibility_buffer,
+            &self.scanned_visibility_buffer,
+            &self.transparent_buffer,
+            &self.visibility_override_buffer,
+            &self.selection_buffer,
+            &self.compacted_count_buffer,
+            &self.compacted_count_staging_buffer,
+            &self.draw_chunks_buffer,
+        ] {
+            buffer.destroy();
+        }
+        if let Some(buffer) = &self.culling_staging_buffer {
+            buffer.destroy();
+        }
+        self.solid.destroy();
+        self.edges.destroy();
+    }
+}
+
+impl IndirectData {
+    fn destroy(&self) {
+        for chunk in &self.chunks {
+            chunk.vertex_buffer.destroy();
+            chunk.index_buffer.destroy();
+        }
+        self.indirect_buffer.destroy();
+        self.compacted_indirect_buffer.destroy();
+    }
 }
 
+/// Combine `scenes` into a single scene with each scene transformed by its layout transform.
+///
+/// Each scene's instances are added to a group named by the index of the scene like `"0"`.
 pub fn load_render_data(
     device: &wgpu::Device,
-    scene: &LDrawSceneInstanced,
+    scenes: &[(&LDrawSceneInstanced, Mat4)],
     color_table: &HashMap<u32, LDrawColor>,
-) -> IndirectSceneData {
+    settings: &SceneSettings,
+    mut progress: impl FnMut(LoadProgress),
+) -> Result<IndirectSceneData, SceneError> {
     // Combine all data into a single multidraw indirect call.
-    let mut combined_vertices = Vec::new();
-    let mut combined_indices = Vec::new();
     let mut combined_transforms = Vec::new();
     let mut indirect_draws = Vec::new();
     let mut instance_bounds = Vec::new();
     let mut is_part_transparent = Vec::new();
 
-    let mut combined_edge_indices = Vec::new();
     let mut edge_indirect_draws = Vec::new();
 
+    // Parts shared between scenes only need to be processed once.
+    let geometry_cache: HashMap<_, _> = scenes
+        .iter()
+        .flat_map(|(scene, _)| &scene.geometry_cache)
+        .collect();
+    // Track the source scene of each transform for grouping instances.
+    let mut geometry_world_transforms: HashMap<_, Vec<(usize, Mat4)>> = HashMap::new();
+    for (i, (scene, layout)) in scenes.iter().enumerate() {
+        for (key, transforms) in &scene.geometry_world_transforms {
+            geometry_world_transforms
+                .entry(key)
+                .or_default()
+                .extend(transforms.iter().map(|t| (i, *layout * *t)));
+        }
+    }
+    let mut groups: Vec<_> = (0..scenes.len())
+        .map(|i| InstanceGroup {
+            name: i.to_string(),
+            instances: Vec::new(),
+        })
+        .collect();
+
     // Sort so that transparent draws happen last for proper blending.
     // Opaque objects evaluate to false and appear first when sorted.
     // This is simpler than drawing separate opaque and transparent passes.
-    let mut alpha_sorted: Vec<_> = scene.geometry_world_transforms.iter().collect();
+    let mut alpha_sorted: Vec<_> = geometry_world_transforms.iter().collect();
     alpha_sorted.sort_by_key(|((_, color), _)| is_transparent(color_table, color));
 
     // Geometry for parts appearing in multiple colors should be calculated only once.
     // Use multiple threads to improve performance since parts are independent.
-    let part_vertex_data: HashMap<_, _> = scene
-        .geometry_cache
-        .par_iter()
-        .map(|(name, geometry)| (name.clone(), IndexedVertexData::from_geometry(geometry)))
+    let part_vertex_data = process_parts_with_progress(&geometry_cache, settings, &mut progress);
+
+    let start = std::time::Instant::now();
+
+    // Converting colors doesn't change the vertex or index counts.
+    // Scan the sizes to calculate each part's offsets in the combined buffers.
+    // This allows processing parts in parallel while keeping a deterministic order.
+    let sizes = alpha_sorted
+        .iter()
+        .map(|((name, _), _)| {
+            part_vertex_data
+                .get(name)
+                .map(BufferSizes::new)
+                .ok_or_else(|| SceneError::MissingGeometry(name.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let offsets: Vec<_> = sizes
+        .iter()
+        .scan(BufferSizes::default(), |offset, size| {
+            let current = *offset;
+            *offset = current + *size;
+            Some(current)
+        })
         .collect();
+    let total = sizes.iter().fold(BufferSizes::default(), |a, b| a + *b);
 
-    // TODO: perform these conversions in parallel?
-    // TODO: Parallelizing this will require scanning the sizes to calculate buffer offsets.
-    for ((name, color), transforms) in alpha_sorted {
-        let base_index = combined_indices.len() as u32;
-        let base_edge_index = combined_edge_indices.len() as u32;
-        let vertex_offset = combined_vertices.len() as i32;
-
-        // Create separate vertex data if a part has multiple colors.
-        // This is necessary since we store face colors per vertex.
-        // Copy the vertex data so that we can replace the color.
-        let mut vertex_data = part_vertex_data[name].clone();
-        vertex_data.replace_colors(*color, color_table);
-
-        // Modern GPUs reuse indices in small batches.
-        // This also helps slightly on Apple M1.
-        // https://arbook.icg.tugraz.at/schmalstieg/Schmalstieg_351.pdf
-        let vertex_indices =
-            optimize_vertex_cache(&vertex_data.vertex_indices, vertex_data.vertices.len());
+    // Parts are assigned to chunks to avoid creating buffers past the device limit.
+    let limits = device.limits();
+    let part_chunks = buffer_chunks(&sizes, limits.max_buffer_size);
+    let chunk_starts: Vec<_> = part_chunks
+        .iter()
+        .flat_map(|c| c.clone().map(|_| c.start))
+        .collect();
 
-        combined_vertices.extend_from_slice(&vertex_data.vertices);
-        combined_indices.extend_from_slice(&vertex_indices);
-        combined_edge_indices.extend_from_slice(&vertex_data.edge_indices);
+    let mut combined_vertices: Vec<crate::shader::model::VertexInput> =
+        vec![bytemuck::Zeroable::zeroed(); total.vertices];
+    let mut combined_indices = vec![0u32; total.indices];
+    let mut combined_edge_vertices: Vec<crate::shader::edge::VertexInput> =
+        vec![bytemuck::Zeroable::zeroed(); total.edge_vertices];
+    let mut combined_edge_indices = vec![0u32; total.edge_indices];
 
-        let is_transparent = color_table
-            .get(color)
-            .map(|c| c.rgba_linear[3] < 1.0)
-            .unwrap_or_default();
+    alpha_sorted
+        .par_iter()
+        .zip(split_sizes(
+            &mut combined_vertices,
+            sizes.iter().map(|s| s.vertices),
+        ))
+        .zip(split_sizes(
+            &mut combined_indices,
+            sizes.iter().map(|s| s.indices),
+        ))
+        .zip(split_sizes(
+            &mut combined_edge_vertices,
+            sizes.iter().map(|s| s.edge_vertices),
+        ))
+        .zip(split_sizes(
+            &mut combined_edge_indices,
+            sizes.iter().map(|s| s.edge_indices),
+        ))
+        .for_each(
+            |((((((name, color), _), vertices), indices), edge_vertices), edge_indices)| {
+                let vertex_data =
+                    colored_vertex_data(&part_vertex_data[name], *color, color_table, settings);
+                vertices.copy_from_slice(&vertex_data.vertices);
+                indices.copy_from_slice(&vertex_data.vertex_indices);
+                edge_vertices.copy_from_slice(&vertex_data.edge_vertices);
+                edge_indices.copy_from_slice(&vertex_data.edge_indices);
+            },
+        );
+
+    let part_count = alpha_sorted.len();
+    let mut duplicate_count = 0;
+    let mut part_draw_starts = Vec::new();
+    let mut part_ranges = Vec::new();
+    for (i, (((name, color), transforms), (offset, size))) in alpha_sorted
+        .iter()
+        .zip(offsets.iter().zip(&sizes))
+        .enumerate()
+    {
+        let is_transparent = is_transparent(color_table, color);
+
+        // Each chunk has its own buffers, so offsets start from the chunk's first part.
+        let offset = *offset - offsets[chunk_starts[i]];
+        part_draw_starts.push(indirect_draws.len());
+
+        // The bounds don't depend on color, so use the uncolored part data.
+        // Getting the sizes already checked that each part has geometry.
+        let part_bounds = part_vertex_data[name].bounds;
 
         // Each draw specifies the part mesh using an offset and count.
         // The base instance steps through the transforms buffer.
         // Each draw uses a single instance to allow culling individual draws.
-        for transform in transforms {
+        let mut placed = HashSet::new();
+        for (scene_index, transform) in transforms.iter() {
+            // Keep duplicates from different scenes to preserve the groups for each scene.
+            if settings.deduplicate_instances
+                && !placed.insert((*scene_index, transform_key(transform)))
+            {
+                duplicate_count += 1;
+                continue;
+            }
+
+            groups[*scene_index]
+                .instances
+                .push(combined_transforms.len());
+
             // TODO: Is this the best way to share culling information with edges?
             let edge_indirect_draw = DrawIndexedIndirect {
-                vertex_count: combined_edge_indices.len() as u32 - base_edge_index,
+                vertex_count: size.edge_indices as u32,
                 instance_count: 1,
-                base_index: base_edge_index,
-                vertex_offset,
+                base_index: offset.edge_indices as u32,
+                vertex_offset: offset.edge_vertices as i32,
                 base_instance: combined_transforms.len() as u32,
             };
             edge_indirect_draws.push(edge_indirect_draw);
 
             let draw = DrawIndexedIndirect {
-                vertex_count: combined_indices.len() as u32 - base_index,
+                vertex_count: size.indices as u32,
                 instance_count: 1,
-                base_index,
-                vertex_offset,
+                base_index: offset.indices as u32,
+                vertex_offset: offset.vertices as i32,
                 base_instance: combined_transforms.len() as u32,
             };
             indirect_draws.push(draw);
@@ -130,40 +923,176 @@ pub fn load_render_data(
             // Transform the bounds from the cached geometry.
             // This avoids looping over the points again and improves performance.
             // TODO: Find an efficient way to potentially update this each frame.
-            let bounds = transform_bounds(vertex_data.bounds, *transform);
+            let bounds = transform_bounds(part_bounds, *transform);
             instance_bounds.push(bounds);
 
             combined_transforms.push(*transform);
 
             is_part_transparent.push(is_transparent as u32);
         }
+
+        part_ranges.push(PartDrawRange {
+            name: name.clone(),
+            color: *color,
+            draws: part_draw_starts[i]..indirect_draws.len(),
+        });
+
+        progress(LoadProgress::CombiningParts {
+            processed: i + 1,
+            total: part_count,
+        });
+    }
+
+    if settings.deduplicate_instances {
+        info!(
+            "removed {duplicate_count} duplicate instances, {} instances remaining",
+            combined_transforms.len()
+        );
     }
 
+    let chunk_draws: Vec<_> = part_chunks
+        .iter()
+        .map(|c| {
+            let end = part_draw_starts
+                .get(c.end)
+                .copied()
+                .unwrap_or(indirect_draws.len());
+            part_draw_starts[c.start]..end
+        })
+        .collect();
+    // Transparent draws are sorted last, so each chunk's transparent draws are also last.
+    let transparent_start = is_part_transparent
+        .iter()
+        .position(|t| *t != 0)
+        .unwrap_or(is_part_transparent.len());
+    let chunk_transparent_draws: Vec<_> = chunk_draws
+        .iter()
+        .map(|d| transparent_start.clamp(d.start, d.end)..d.end)
+        .collect();
+    let draw_chunks: Vec<_> = chunk_draws
+        .iter()
+        .zip(&chunk_transparent_draws)
+        .enumerate()
+        .flat_map(|(i, (draws, transparent_draws))| {
+            draws
+                .clone()
+                .map(move |_| crate::shader::visibility::DrawChunk {
+                    chunk: i as u32,
+                    first_draw: draws.start as u32,
+                    transparent_start: transparent_draws.start as u32,
+                })
+        })
+        .collect();
+
+    let material_ranges = material_draw_ranges(&is_part_transparent);
+    let bounds = scene_bounds(&instance_bounds);
+
     info!(
-        "vertices: {}, indices: {}",
+        "vertices: {}, indices: {}, edge vertices: {}, edge indices: {}, combine parts: {:?}",
         combined_vertices.len(),
-        combined_indices.len()
+        combined_indices.len(),
+        combined_edge_vertices.len(),
+        combined_edge_indices.len(),
+        start.elapsed()
     );
 
-    // TODO: Create buffer creation helper functions
-    // vertex_buffer, index_buffer, indirect_buffer, etc
-    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("vertex buffer"),
-        contents: bytemuck::cast_slice(&combined_vertices),
-        usage: wgpu::BufferUsages::VERTEX,
-    });
+    // Each chunk's buffers start and end on part boundaries in the combined data.
+    let chunk_offsets: Vec<_> = part_chunks
+        .iter()
+        .map(|c| {
+            (
+                offsets[c.start],
+                offsets.get(c.end).copied().unwrap_or(total),
+            )
+        })
+        .collect();
 
-    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("index buffer"),
-        contents: bytemuck::cast_slice(&combined_indices),
-        usage: wgpu::BufferUsages::INDEX,
+    // Check sizes first since creating buffers past the limits is a validation error.
+    // Chunks only exceed the limit if a single part is too large.
+    let max_storage_size = limits.max_storage_buffer_binding_size as u64;
+    let chunk_sizes = chunk_offsets.iter().flat_map(|(start, end)| {
+        let [vertices, indices, edge_vertices, edge_indices] = (*end - *start).bytes();
+        [
+            ("vertex buffer", vertices, limits.max_buffer_size),
+            ("index buffer", indices, limits.max_buffer_size),
+            ("edge vertex buffer", edge_vertices, limits.max_buffer_size),
+            ("edge index buffer", edge_indices, limits.max_buffer_size),
+        ]
     });
+    for (label, size, max_size) in chunk_sizes.chain([
+        (
+            "instance transforms buffer",
+            std::mem::size_of_val(combined_transforms.as_slice()) as u64,
+            limits.max_buffer_size,
+        ),
+        (
+            "instance bounds buffer",
+            std::mem::size_of_val(instance_bounds.as_slice()) as u64,
+            max_storage_size,
+        ),
+        (
+            "indirect buffer",
+            std::mem::size_of_val(indirect_draws.as_slice()) as u64,
+            max_storage_size,
+        ),
+    ]) {
+        if size > max_size {
+            return Err(SceneError::BufferSizeExceeded {
+                label,
+                size,
+                max_size,
+            });
+        }
+    }
 
-    let edge_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("edge index buffer"),
-        contents: bytemuck::cast_slice(&combined_edge_indices),
-        usage: wgpu::BufferUsages::INDEX,
-    });
+    progress(LoadProgress::Uploading);
+
+    // TODO: Create buffer creation helper functions
+    // vertex_buffer, index_buffer, indirect_buffer, etc
+    let mut chunks = Vec::new();
+    let mut edge_chunks = Vec::new();
+    for (((start, end), draws), transparent_draws) in chunk_offsets
+        .iter()
+        .zip(&chunk_draws)
+        .zip(&chunk_transparent_draws)
+    {
+        let compacted_draw_count = (transparent_draws.start - draws.start) as u32;
+        chunks.push(BufferChunk {
+            vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("vertex buffer"),
+                contents: bytemuck::cast_slice(&combined_vertices[start.vertices..end.vertices]),
+                usage: wgpu::BufferUsages::VERTEX,
+            }),
+            index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("index buffer"),
+                contents: bytemuck::cast_slice(&combined_indices[start.indices..end.indices]),
+                usage: wgpu::BufferUsages::INDEX,
+            }),
+            draws: draws.clone(),
+            transparent_draws: transparent_draws.clone(),
+            compacted_draw_count,
+        });
+
+        edge_chunks.push(BufferChunk {
+            vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("edge vertex buffer"),
+                contents: bytemuck::cast_slice(
+                    &combined_edge_vertices[start.edge_vertices..end.edge_vertices],
+                ),
+                usage: wgpu::BufferUsages::VERTEX,
+            }),
+            index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("edge index buffer"),
+                contents: bytemuck::cast_slice(
+                    &combined_edge_indices[start.edge_indices..end.edge_indices],
+                ),
+                usage: wgpu::BufferUsages::INDEX,
+            }),
+            draws: draws.clone(),
+            transparent_draws: transparent_draws.clone(),
+            compacted_draw_count,
+        });
+    }
 
     // TODO: the non compacted buffer could just be storage?
     let indirect_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -192,13 +1121,22 @@ pub fn load_render_data(
     let instance_transforms_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("instance transforms buffer"),
         contents: bytemuck::cast_slice(&combined_transforms),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    });
+
+    // Shaders read the instance index from a vertex buffer like the transforms,
+    // which always includes the draw's base instance.
+    let instance_indices: Vec<u32> = (0..combined_transforms.len() as u32).collect();
+    let instance_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("instance index buffer"),
+        contents: bytemuck::cast_slice(&instance_indices),
         usage: wgpu::BufferUsages::VERTEX,
     });
 
     let instance_bounds_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("instance bounds buffer"),
         contents: bytemuck::cast_slice(&instance_bounds),
-        usage: wgpu::BufferUsages::STORAGE,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
     });
 
     // Start with all objects visible.
@@ -206,12 +1144,21 @@ pub fn load_render_data(
     let visibility_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("visibility buffer"),
         contents: bytemuck::cast_slice(&vec![1u32; indirect_draws.len()]),
-        usage: wgpu::BufferUsages::STORAGE,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
     });
     let new_visibility_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("new visibility buffer"),
         contents: bytemuck::cast_slice(&vec![0u32; indirect_draws.len()]),
-        usage: wgpu::BufferUsages::STORAGE,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    });
+
+    let culling_staging_buffer = settings.readback_culling.then(|| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("culling staging buffer"),
+            size: visibility_buffer.size() + new_visibility_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        })
     });
 
     // Used to prevent transparent objects occluding other objects.
@@ -221,9 +1168,25 @@ pub fn load_render_data(
         usage: wgpu::BufferUsages::STORAGE,
     });
 
+    let visibility_override_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("visibility override buffer"),
+        contents: bytemuck::cast_slice(&vec![
+            VisibilityOverride::Culled as u32;
+            indirect_draws.len()
+        ]),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let selection_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("selection buffer"),
+        contents: bytemuck::cast_slice(&selection_flags(indirect_draws.len(), &[])),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    // Bindings can't be empty, so always include at least one count.
     let compacted_count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("compacted draw count buffer"),
-        contents: bytemuck::cast_slice(&[0u32]),
+        contents: bytemuck::cast_slice(&vec![0u32; chunks.len().max(1)]),
         usage: wgpu::BufferUsages::STORAGE
             | wgpu::BufferUsages::COPY_SRC
             | wgpu::BufferUsages::INDIRECT,
@@ -236,6 +1199,12 @@ pub fn load_render_data(
         mapped_at_creation: false,
     });
 
+    let draw_chunks_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("draw chunks buffer"),
+        contents: bytemuck::cast_slice(&draw_chunks),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
     let scanned_visibility_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("scanned visibility buffer"),
         size: visibility_buffer.size(),
@@ -250,34 +1219,313 @@ pub fn load_render_data(
         mapped_at_creation: false,
     });
 
-    IndirectSceneData {
-        vertex_buffer,
+    Ok(IndirectSceneData {
         visibility_buffer,
         new_visibility_buffer,
         instance_transforms_buffer,
+        instance_index_buffer,
         instance_bounds_buffer,
         compacted_count_buffer,
         compacted_count_staging_buffer,
+        draw_chunks_buffer,
+        culling_staging_buffer,
         scanned_visibility_buffer,
         scanned_new_visibility_buffer,
         transparent_buffer,
+        visibility_override_buffer,
+        selection_buffer,
+        material_ranges,
+        part_ranges,
+        groups,
+        bounds,
+        transparent_sort_position: None,
+        instance_transforms: combined_transforms,
+        instance_world_bounds: instance_bounds.clone(),
+        instance_bounds,
+        part_bounds: part_vertex_data
+            .iter()
+            .map(|(name, vertex_data)| (name.clone(), vertex_data.bounds))
+            .collect(),
+        visibility_overrides: vec![VisibilityOverride::Culled; indirect_draws.len()],
         solid: IndirectData {
-            index_buffer,
+            chunks,
             indirect_buffer,
             draw_count: indirect_draws.len() as u32,
-            compacted_draw_count: indirect_draws.len() as u32,
             compacted_indirect_buffer,
+            draws: indirect_draws,
         },
         edges: IndirectData {
-            index_buffer: edge_index_buffer,
+            chunks: edge_chunks,
             indirect_buffer: edge_indirect_buffer,
             draw_count: edge_indirect_draws.len() as u32,
-            compacted_draw_count: edge_indirect_draws.len() as u32,
             compacted_indirect_buffer: compacted_edge_indirect_buffer,
+            draws: edge_indirect_draws,
         },
+    })
+}
+
+/// Process the geometry for the part `name` in `scene` exactly as it would be rendered.
+///
+/// The vertices are welded, split along sharp edges, assigned normals and colors,
+/// and reordered for the vertex cache if enabled in `settings`.
+/// Returns `None` if the part isn't in the scene's geometry cache.
+pub fn part_vertex_data(
+    scene: &LDrawSceneInstanced,
+    name: &str,
+    color: u32,
+    color_table: &HashMap<u32, LDrawColor>,
+    settings: &SceneSettings,
+) -> Option<IndexedVertexData> {
+    let geometry = scene.geometry_cache.get(name)?;
+    let vertex_data = processed_vertex_data(geometry, settings);
+    Some(colored_vertex_data(
+        &vertex_data,
+        color,
+        color_table,
+        settings,
+    ))
+}
+
+fn process_parts_with_progress(
+    geometry_cache: &HashMap<&String, &ldr_tools::LDrawGeometry>,
+    settings: &SceneSettings,
+    progress: &mut impl FnMut(LoadProgress),
+) -> HashMap<String, IndexedVertexData> {
+    let total = geometry_cache.len();
+    progress(LoadProgress::ProcessingParts {
+        processed: 0,
+        total,
+    });
+
+    // The progress callback isn't thread safe, so only call it from this thread.
+    // Workers send a message for each finished part to report the new count.
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::scope(|s| {
+        let worker = s.spawn(move || {
+            geometry_cache
+                .par_iter()
+                .map_with(sender, |sender, (name, geometry)| {
+                    let vertex_data = processed_vertex_data(geometry, settings);
+                    // The receiver outlives the workers, so sending can't fail.
+                    sender.send(()).ok();
+                    ((*name).clone(), vertex_data)
+                })
+                .collect::<HashMap<_, _>>()
+        });
+
+        // The loop ends once the worker finishes and drops all the senders.
+        for (i, _) in receiver.iter().enumerate() {
+            progress(LoadProgress::ProcessingParts {
+                processed: i + 1,
+                total,
+            });
+        }
+
+        worker
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    })
+}
+
+fn processed_vertex_data(
+    geometry: &ldr_tools::LDrawGeometry,
+    settings: &SceneSettings,
+) -> IndexedVertexData {
+    match &settings.cache_dir {
+        Some(dir) => {
+            let key = crate::cache::cache_key(geometry, settings);
+            crate::cache::load(dir, key).unwrap_or_else(|| {
+                let vertex_data = process_vertex_data(geometry, settings);
+                if let Err(e) = crate::cache::save(dir, key, &vertex_data) {
+                    warn!("Failed to cache geometry in {dir:?}: {e}");
+                }
+                vertex_data
+            })
+        }
+        None => process_vertex_data(geometry, settings),
     }
 }
 
+fn process_vertex_data(
+    geometry: &ldr_tools::LDrawGeometry,
+    settings: &SceneSettings,
+) -> IndexedVertexData {
+    let mut vertex_data = IndexedVertexData::from_geometry(geometry, settings.smoothing_angle);
+
+    // Simplify first so that later optimizations apply to the final triangles.
+    if let Some(max_triangles) = settings.max_triangles_per_part {
+        let positions: Vec<_> = vertex_data
+            .vertices
+            .iter()
+            .map(|v| v.position.to_array())
+            .collect();
+        vertex_data.vertex_indices =
+            simplify_indices(&vertex_data.vertex_indices, &positions, max_triangles);
+        remove_unused_vertices(&mut vertex_data.vertex_indices, &mut vertex_data.vertices);
+    }
+
+    // Modern GPUs reuse indices in small batches.
+    // This also helps slightly on Apple M1.
+    // https://arbook.icg.tugraz.at/schmalstieg/Schmalstieg_351.pdf
+    // The index order doesn't depend on color, so optimize once per unique part.
+    if settings.optimize_vertex_cache {
+        vertex_data.vertex_indices =
+            optimize_vertex_cache(&vertex_data.vertex_indices, vertex_data.vertices.len());
+    }
+
+    // Drawing front faces first improves performance for fill bound renders.
+    // https://github.com/zeux/meshoptimizer#overdraw-optimization
+    if let Some(threshold) = settings.optimize_overdraw {
+        let positions: Vec<_> = vertex_data
+            .vertices
+            .iter()
+            .map(|v| v.position.to_array())
+            .collect();
+        optimize_overdraw_in_place_decoder(&mut vertex_data.vertex_indices, &positions, threshold);
+    }
+
+    vertex_data
+}
+
+fn simplify_indices(indices: &[u32], positions: &[[f32; 3]], max_triangles: usize) -> Vec<u32> {
+    if indices.len() <= max_triangles * 3 {
+        return indices.to_vec();
+    }
+
+    // Allow any amount of error so that only the triangle budget determines the result.
+    // Locking borders avoids cracks along open edges like the bottoms of bricks.
+    // The triangle count may stay above the limit if the locked borders prevent simplification.
+    simplify_decoder(
+        indices,
+        positions,
+        max_triangles * 3,
+        1.0,
+        SimplifyOptions::LockBorder,
+        None,
+    )
+}
+
+// Simplifying only removes triangles, so the removed vertices would still take up memory.
+fn remove_unused_vertices<T: bytemuck::Pod>(indices: &mut [u32], vertices: &mut Vec<T>) {
+    // This also reorders the vertices in the order they are first used by the indices.
+    let vertex_count = optimize_vertex_fetch_in_place(indices, vertices);
+    vertices.truncate(vertex_count);
+}
+
+fn colored_vertex_data(
+    vertex_data: &IndexedVertexData,
+    color: u32,
+    color_table: &HashMap<u32, LDrawColor>,
+    settings: &SceneSettings,
+) -> IndexedVertexData {
+    // Create separate vertex data if a part has multiple colors.
+    // This is necessary since we store face colors per vertex.
+    // Copy the vertex data so that we can replace the color.
+    // Only vertex colors change, so the optimized indices remain valid.
+    let mut vertex_data = vertex_data.clone();
+    vertex_data.replace_colors(color, color_table, &settings.edge_colors);
+    vertex_data
+}
+
+/// The number of elements in each of the per part buffers.
+#[derive(Debug, Clone, Copy, Default)]
+struct BufferSizes {
+    vertices: usize,
+    indices: usize,
+    edge_vertices: usize,
+    edge_indices: usize,
+}
+
+impl BufferSizes {
+    fn new(vertex_data: &IndexedVertexData) -> Self {
+        Self {
+            vertices: vertex_data.vertices.len(),
+            indices: vertex_data.vertex_indices.len(),
+            edge_vertices: vertex_data.edge_vertices.len(),
+            edge_indices: vertex_data.edge_indices.len(),
+        }
+    }
+
+    /// The size in bytes of the vertex, index, edge vertex, and edge index buffers.
+    fn bytes(&self) -> [u64; 4] {
+        [
+            self.vertices * std::mem::size_of::<crate::shader::model::VertexInput>(),
+            self.indices * std::mem::size_of::<u32>(),
+            self.edge_vertices * std::mem::size_of::<crate::shader::edge::VertexInput>(),
+            self.edge_indices * std::mem::size_of::<u32>(),
+        ]
+        .map(|size| size as u64)
+    }
+}
+
+impl std::ops::Add for BufferSizes {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            vertices: self.vertices + rhs.vertices,
+            indices: self.indices + rhs.indices,
+            edge_vertices: self.edge_vertices + rhs.edge_vertices,
+            edge_indices: self.edge_indices + rhs.edge_indices,
+        }
+    }
+}
+
+impl std::ops::Sub for BufferSizes {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            vertices: self.vertices - rhs.vertices,
+            indices: self.indices - rhs.indices,
+            edge_vertices: self.edge_vertices - rhs.edge_vertices,
+            edge_indices: self.edge_indices - rhs.edge_indices,
+        }
+    }
+}
+
+/// Group consecutive parts into chunks with each buffer at most `max_size` bytes.
+///
+/// Parts aren't split between chunks, so a chunk with a single large part may still exceed `max_size`.
+fn buffer_chunks(sizes: &[BufferSizes], max_size: u64) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut chunk_size = BufferSizes::default();
+    for (i, size) in sizes.iter().enumerate() {
+        let new_size = chunk_size + *size;
+        if i > start && new_size.bytes().iter().any(|b| *b > max_size) {
+            chunks.push(start..i);
+            start = i;
+            chunk_size = *size;
+        } else {
+            chunk_size = new_size;
+        }
+    }
+    if start < sizes.len() {
+        chunks.push(start..sizes.len());
+    }
+    chunks
+}
+
+/// Split `slice` into consecutive non overlapping slices with the given `sizes`.
+fn split_sizes<T>(mut slice: &mut [T], sizes: impl Iterator<Item = usize>) -> Vec<&mut [T]> {
+    sizes
+        .map(|size| {
+            let (chunk, remaining) = std::mem::take(&mut slice).split_at_mut(size);
+            slice = remaining;
+            chunk
+        })
+        .collect()
+}
+
+/// A hashable key for transforms that are equal up to floating point error.
+fn transform_key(transform: &Mat4) -> [i32; 16] {
+    // LDraw units are small enough that this precision is well below a pixel.
+    transform
+        .to_cols_array()
+        .map(|x| (x * 1024.0).round() as i32)
+}
+
 fn transform_bounds(
     bounds: crate::shader::culling::InstanceBounds,
     transform: Mat4,
@@ -305,6 +1553,89 @@ fn transform_bounds(
     }
 }
 
+/// Layout transforms for [crate::RenderData::new_combined] that arrange `count` scenes
+/// in a square grid on the ground plane with `spacing` between the origins of each scene.
+pub fn grid_layout(count: usize, spacing: f32) -> Vec<Mat4> {
+    let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+    (0..count)
+        .map(|i| {
+            let x = (i % columns) as f32 * spacing;
+            let z = (i / columns) as f32 * spacing;
+            Mat4::from_translation(Vec3::new(x, 0.0, z))
+        })
+        .collect()
+}
+
+fn scene_bounds(instance_bounds: &[crate::shader::culling::InstanceBounds]) -> (Vec3, Vec3) {
+    if instance_bounds.is_empty() {
+        return (Vec3::ZERO, Vec3::ZERO);
+    }
+
+    instance_bounds
+        .iter()
+        .fold((Vec3::MAX, Vec3::MIN), |(min_xyz, max_xyz), b| {
+            (min_xyz.min(b.min_xyz.xyz()), max_xyz.max(b.max_xyz.xyz()))
+        })
+}
+
+fn material_draw_ranges(is_part_transparent: &[u32]) -> Vec<MaterialDrawRange> {
+    // Draws are already sorted by material, so group adjacent draws into ranges.
+    let mut ranges: Vec<MaterialDrawRange> = Vec::new();
+    for (i, is_transparent) in is_part_transparent.iter().enumerate() {
+        let material = if *is_transparent != 0 {
+            Material::Transparent
+        } else {
+            Material::Opaque
+        };
+
+        match ranges.last_mut() {
+            Some(range) if range.material == material => range.draws.end = i + 1,
+            _ => ranges.push(MaterialDrawRange {
+                material,
+                draws: i..i + 1,
+            }),
+        }
+    }
+    ranges
+}
+
+fn part_instance_colors(
+    part_ranges: &[PartDrawRange],
+    color_table: &HashMap<u32, LDrawColor>,
+) -> Vec<ResolvedColor> {
+    part_ranges
+        .iter()
+        .flat_map(|range| {
+            range
+                .draws
+                .clone()
+                .map(|_| resolved_color(range.color, color_table))
+        })
+        .collect()
+}
+
+/// The indices of `draws` ordered from farthest to closest to `camera_position`.
+fn back_to_front(
+    draws: &[DrawIndexedIndirect],
+    instance_bounds: &[crate::shader::culling::InstanceBounds],
+    camera_position: Vec3,
+) -> Vec<usize> {
+    let distances: Vec<_> = draws
+        .iter()
+        .map(|d| {
+            instance_bounds[d.base_instance as usize]
+                .sphere
+                .xyz()
+                .distance_squared(camera_position)
+        })
+        .collect();
+
+    // Use a stable sort to avoid flickering between draws at the same distance.
+    let mut order: Vec<_> = (0..draws.len()).collect();
+    order.sort_by(|a, b| distances[*b].total_cmp(&distances[*a]));
+    order
+}
+
 fn is_transparent(color_table: &HashMap<u32, LDrawColor>, color: &u32) -> bool {
     color_table
         .get(color)
@@ -320,36 +1651,378 @@ pub fn draw_indirect<'a>(
 ) {
     // Draw the instances of each unique part and color.
     // This allows reusing most of the rendering state for better performance.
-    render_pass.set_index_buffer(data.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-    render_pass.set_vertex_buffer(0, scene.vertex_buffer.slice(..));
     render_pass.set_vertex_buffer(1, scene.instance_transforms_buffer.slice(..));
+    render_pass.set_vertex_buffer(2, scene.instance_index_buffer.slice(..));
 
-    // Draw each instance with a different transform.
-    if supports_indirect_count {
-        render_pass.multi_draw_indexed_indirect_count(
-            &data.compacted_indirect_buffer,
-            0,
-            &scene.compacted_count_buffer,
-            0,
-            data.draw_count,
-        );
-    } else {
-        render_pass.multi_draw_indexed_indirect(
+    // Most scenes fit in a single chunk and only need one draw call.
+    for (i, chunk) in data.chunks.iter().enumerate() {
+        render_pass.set_index_buffer(chunk.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+
+        // Draw each instance with a different transform.
+        let offset = (chunk.draws.start * std::mem::size_of::<DrawIndexedIndirect>()) as u64;
+        let max_count = (chunk.transparent_draws.start - chunk.draws.start) as u32;
+        if supports_indirect_count {
+            render_pass.multi_draw_indexed_indirect_count(
+                &data.compacted_indirect_buffer,
+                offset,
+                &scene.compacted_count_buffer,
+                (i * std::mem::size_of::<u32>()) as u64,
+                max_count,
+            );
+        } else {
+            render_pass.multi_draw_indexed_indirect(
+                &data.compacted_indirect_buffer,
+                offset,
+                chunk.compacted_draw_count,
+            );
+        }
+    }
+}
+
+fn selection_flags(instance_count: usize, selected: &[usize]) -> Vec<u32> {
+    // Bindings can't be empty, so always include at least one flag.
+    let mut flags = vec![0u32; instance_count.max(1)];
+    for i in selected {
+        if let Some(flag) = flags.get_mut(*i) {
+            *flag = 1;
+        }
+    }
+    flags
+}
+
+/// Draw the transparent draws in each chunk that weren't culled by the last visibility pass.
+/// Unlike [draw_indirect], this only draws [BufferChunk::transparent_draws].
+///
+/// Each draw is drawn with every pipeline in `pipelines` before the next draw
+/// to preserve the back to front order of the draws.
+pub fn draw_indirect_transparent<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    scene: &'a IndirectSceneData,
+    data: &'a IndirectData,
+    pipelines: &[&'a wgpu::RenderPipeline],
+) {
+    render_pass.set_vertex_buffer(1, scene.instance_transforms_buffer.slice(..));
+    render_pass.set_vertex_buffer(2, scene.instance_index_buffer.slice(..));
+    for chunk in data
+        .chunks
+        .iter()
+        .filter(|c| !c.transparent_draws.is_empty())
+    {
+        render_pass.set_index_buffer(chunk.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+
+        // Culled draws are still in the buffer with no instances.
+        draw_each_indirect(
+            render_pass,
             &data.compacted_indirect_buffer,
-            0,
-            data.compacted_draw_count,
+            chunk.transparent_draws.clone(),
+            pipelines,
         );
     }
 }
 
+/// Draw `draws` from `indirect_buffer` one at a time in order
+/// with each of the `pipelines` before moving to the next draw.
+pub(crate) fn draw_each_indirect<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    indirect_buffer: &'a wgpu::Buffer,
+    draws: Range<usize>,
+    pipelines: &[&'a wgpu::RenderPipeline],
+) {
+    for draw in draws {
+        let offset = (draw * std::mem::size_of::<DrawIndexedIndirect>()) as u64;
+        for pipeline in pipelines {
+            render_pass.set_pipeline(pipeline);
+            render_pass.draw_indexed_indirect(indirect_buffer, offset);
+        }
+    }
+}
+
+/// Draw every draw in `indirect_buffer` using the buffers for each of the `chunks`.
+/// Unlike [draw_indirect], this doesn't depend on the results of culling.
+pub fn draw_indirect_uncompacted<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    scene: &'a IndirectSceneData,
+    chunks: &'a [BufferChunk],
+    indirect_buffer: &'a wgpu::Buffer,
+) {
+    render_pass.set_vertex_buffer(1, scene.instance_transforms_buffer.slice(..));
+    render_pass.set_vertex_buffer(2, scene.instance_index_buffer.slice(..));
+    for chunk in chunks {
+        render_pass.set_index_buffer(chunk.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+
+        let offset = (chunk.draws.start * std::mem::size_of::<DrawIndexedIndirect>()) as u64;
+        render_pass.multi_draw_indexed_indirect(indirect_buffer, offset, chunk.draws.len() as u32);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use glam::{vec3, vec4};
+    use std::collections::BTreeSet;
+
+    use glam::{vec3, vec4, Vec4};
 
     use crate::shader::culling::InstanceBounds;
 
     use super::*;
 
+    fn grid(size: u32) -> (Vec<u32>, Vec<[f32; 3]>) {
+        let positions = (0..=size)
+            .flat_map(|y| (0..=size).map(move |x| [x as f32, y as f32, 0.0]))
+            .collect();
+        let indices = (0..size)
+            .flat_map(|y| {
+                (0..size).flat_map(move |x| {
+                    let i = y * (size + 1) + x;
+                    let j = i + size + 1;
+                    [i, j, i + 1, i + 1, j, j + 1]
+                })
+            })
+            .collect();
+        (indices, positions)
+    }
+
+    #[test]
+    fn simplify_indices_under_limit() {
+        let (indices, positions) = grid(4);
+        assert_eq!(indices, simplify_indices(&indices, &positions, 32));
+    }
+
+    #[test]
+    fn simplify_indices_over_limit() {
+        let (indices, positions) = grid(8);
+        let simplified = simplify_indices(&indices, &positions, 16);
+        assert!(simplified.len() < indices.len());
+        assert!(!simplified.is_empty());
+        assert_eq!(0, simplified.len() % 3);
+    }
+
+    #[test]
+    fn remove_unused_vertices_after_simplify() {
+        let (indices, mut positions) = grid(8);
+        let mut simplified = simplify_indices(&indices, &positions, 16);
+        let used_count = simplified.iter().collect::<BTreeSet<_>>().len();
+
+        remove_unused_vertices(&mut simplified, &mut positions);
+        assert!(used_count < 81);
+        assert_eq!(used_count, positions.len());
+        assert!(simplified.iter().all(|i| (*i as usize) < positions.len()));
+    }
+
+    #[test]
+    fn grid_layout_empty() {
+        assert!(grid_layout(0, 100.0).is_empty());
+    }
+
+    #[test]
+    fn grid_layout_rows() {
+        let translations: Vec<_> = grid_layout(5, 100.0)
+            .iter()
+            .map(|t| t.col(3).xyz())
+            .collect();
+        assert_eq!(
+            vec![
+                vec3(0.0, 0.0, 0.0),
+                vec3(100.0, 0.0, 0.0),
+                vec3(200.0, 0.0, 0.0),
+                vec3(0.0, 0.0, 100.0),
+                vec3(100.0, 0.0, 100.0),
+            ],
+            translations
+        );
+    }
+
+    #[test]
+    fn process_parts_with_progress_empty() {
+        let mut stages = Vec::new();
+        let parts = process_parts_with_progress(
+            &HashMap::new(),
+            &SceneSettings::default(),
+            &mut |progress| stages.push(progress),
+        );
+        assert!(parts.is_empty());
+        assert_eq!(
+            vec![LoadProgress::ProcessingParts {
+                processed: 0,
+                total: 0
+            }],
+            stages
+        );
+    }
+
+    #[test]
+    fn check_instances_in_range() {
+        assert_eq!(Ok(()), check_instances(&[0, 2, 1], 3));
+    }
+
+    #[test]
+    fn check_instances_out_of_range() {
+        assert_eq!(
+            Err(InvalidInstance {
+                index: 3,
+                instance_count: 3
+            }),
+            check_instances(&[0, 3, 4], 3)
+        );
+    }
+
+    #[test]
+    fn scene_bounds_empty() {
+        assert_eq!((Vec3::ZERO, Vec3::ZERO), scene_bounds(&[]));
+    }
+
+    #[test]
+    fn scene_bounds_instances() {
+        assert_eq!(
+            (vec3(-1.0, -2.0, 0.0), vec3(3.0, 1.0, 4.0)),
+            scene_bounds(&[
+                InstanceBounds {
+                    sphere: Vec4::ZERO,
+                    min_xyz: vec4(-1.0, 0.0, 0.0, 0.0),
+                    max_xyz: vec4(1.0, 1.0, 4.0, 0.0),
+                },
+                InstanceBounds {
+                    sphere: Vec4::ZERO,
+                    min_xyz: vec4(0.0, -2.0, 1.0, 0.0),
+                    max_xyz: vec4(3.0, 0.0, 2.0, 0.0),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn material_draw_ranges_empty() {
+        assert!(material_draw_ranges(&[]).is_empty());
+    }
+
+    #[test]
+    fn material_draw_ranges_opaque_transparent() {
+        assert_eq!(
+            vec![
+                MaterialDrawRange {
+                    material: Material::Opaque,
+                    draws: 0..2
+                },
+                MaterialDrawRange {
+                    material: Material::Transparent,
+                    draws: 2..5
+                }
+            ],
+            material_draw_ranges(&[0, 0, 1, 1, 1])
+        );
+    }
+
+    #[test]
+    fn part_instance_colors_expand_ranges() {
+        let part = |color, draws| PartDrawRange {
+            name: "3001.dat".to_string(),
+            color,
+            draws,
+        };
+        // Colors missing from the table are white like the vertex colors.
+        let colors = part_instance_colors(
+            &[part(4, 0..2), part(1, 2..2), part(15, 2..3)],
+            &HashMap::new(),
+        );
+        assert_eq!(
+            vec![4, 4, 15],
+            colors.iter().map(|c| c.code).collect::<Vec<_>>()
+        );
+        assert!(colors.iter().all(|c| c.rgba_linear == [1.0; 4]));
+    }
+
+    fn sphere_bounds(center: Vec3) -> InstanceBounds {
+        InstanceBounds {
+            sphere: center.extend(1.0),
+            min_xyz: (center - 1.0).extend(0.0),
+            max_xyz: (center + 1.0).extend(0.0),
+        }
+    }
+
+    #[test]
+    fn back_to_front_distance() {
+        let draw = |base_instance| DrawIndexedIndirect {
+            vertex_count: 3,
+            instance_count: 1,
+            base_index: 0,
+            vertex_offset: 0,
+            base_instance,
+        };
+        let bounds = [
+            vec3(0.0, 0.0, -1.0),
+            vec3(0.0, 0.0, -10.0),
+            vec3(0.0, 0.0, 5.0),
+            vec3(0.0, 0.0, -5.0),
+        ]
+        .map(sphere_bounds);
+
+        // The draws for a chunk can start after the first instance.
+        let draws = [draw(1), draw(2), draw(3)];
+        assert_eq!(vec![0, 1, 2], back_to_front(&draws, &bounds, Vec3::ZERO));
+        assert_eq!(
+            vec![1, 2, 0],
+            back_to_front(&draws, &bounds, vec3(0.0, 0.0, -20.0))
+        );
+    }
+
+    #[test]
+    fn back_to_front_stable() {
+        let draw = |base_instance| DrawIndexedIndirect {
+            vertex_count: 3,
+            instance_count: 1,
+            base_index: 0,
+            vertex_offset: 0,
+            base_instance,
+        };
+        let bounds = [Vec3::X, -Vec3::X, Vec3::Y].map(sphere_bounds);
+        let draws = [draw(0), draw(1), draw(2)];
+        assert_eq!(vec![0, 1, 2], back_to_front(&draws, &bounds, Vec3::ZERO));
+    }
+
+    #[test]
+    fn split_sizes_consecutive() {
+        let mut values = [0, 1, 2, 3, 4, 5];
+        let chunks = split_sizes(&mut values, [1, 0, 3, 2].into_iter());
+        assert_eq!(
+            vec![&[0][..], &[], &[1, 2, 3], &[4, 5]],
+            chunks.into_iter().map(|c| &*c).collect::<Vec<_>>()
+        );
+    }
+
+    fn index_sizes(indices: usize) -> BufferSizes {
+        BufferSizes {
+            indices,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn buffer_chunks_empty() {
+        assert!(buffer_chunks(&[], 16).is_empty());
+    }
+
+    #[test]
+    fn buffer_chunks_under_limit() {
+        let sizes = [index_sizes(3), index_sizes(6)];
+        assert_eq!(vec![0..2], buffer_chunks(&sizes, u64::MAX));
+    }
+
+    #[test]
+    fn buffer_chunks_tiny_max_size() {
+        // 24 bytes fits at most 6 indices in each index buffer.
+        let sizes = [
+            index_sizes(3),
+            index_sizes(3),
+            index_sizes(6),
+            index_sizes(9),
+            index_sizes(3),
+        ];
+        // The part with 9 indices is too large but still gets its own chunk.
+        assert_eq!(vec![0..2, 2..3, 3..4, 4..5], buffer_chunks(&sizes, 24));
+    }
+
     #[test]
     fn transform_bounds_identity() {
         assert_eq!(
@@ -388,6 +2061,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn transform_key_rounding() {
+        let transform = Mat4::from_translation(vec3(20.0, -24.0, 0.0));
+        assert_eq!(
+            transform_key(&transform),
+            transform_key(&(Mat4::from_rotation_y(1e-6) * transform))
+        );
+        assert_ne!(
+            transform_key(&transform),
+            transform_key(&Mat4::from_translation(vec3(20.0, -24.0, 1.0)))
+        );
+        assert_eq!(
+            transform_key(&Mat4::ZERO),
+            transform_key(&Mat4::from_cols_array(&[-0.0; 16]))
+        );
+    }
+
     #[test]
     fn transform_bounds_translation_rotation() {
         assert_eq!(
@@ -413,4 +2103,59 @@ mod tests {
             )
         );
     }
+
+    const MPD: &str = "0 FILE main.ldr\n\
+        1 16 0 0 0 1 0 0 0 1 0 0 0 1 wheel assembly.ldr\n\
+        0 NOFILE\n\
+        0  FILE  Wheel Assembly.ldr \n\
+        1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n";
+
+    #[test]
+    fn submodel_names_file_order() {
+        assert_eq!(
+            vec!["main.ldr".to_string(), "Wheel Assembly.ldr".to_string()],
+            submodel_names(MPD)
+        );
+        assert!(submodel_names("0 FILENAME main.ldr\n0 FILE").is_empty());
+    }
+
+    #[test]
+    fn main_model_first_case_insensitive() {
+        assert_eq!(
+            Some(
+                "0  FILE  Wheel Assembly.ldr \n\
+                1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n\
+                0 FILE main.ldr\n\
+                1 16 0 0 0 1 0 0 0 1 0 0 0 1 wheel assembly.ldr\n\
+                0 NOFILE\n"
+                    .to_string()
+            ),
+            main_model_first(MPD, "wheel assembly.ldr")
+        );
+        assert_eq!(None, main_model_first(MPD, "missing.ldr"));
+    }
+
+    #[test]
+    fn temp_file_unique_and_removed() {
+        let a = TempFile::new(b"a").unwrap();
+        let b = TempFile::new(b"b").unwrap();
+        assert_ne!(a.0, b.0);
+        assert_eq!("a", std::fs::read_to_string(&a.0).unwrap());
+
+        let path = a.0.clone();
+        drop(a);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn selection_flags_deselect_others() {
+        assert_eq!(vec![0, 1, 0, 1], selection_flags(4, &[1, 3]));
+        assert_eq!(vec![0, 0, 0, 0], selection_flags(4, &[]));
+    }
+
+    #[test]
+    fn selection_flags_out_of_range() {
+        assert_eq!(vec![1, 0], selection_flags(2, &[0, 2]));
+        assert_eq!(vec![0], selection_flags(0, &[0]));
+    }
 }
@@ -1,4 +1,16 @@
 #[allow(dead_code)]
+pub mod accumulate {
+    include!(concat!(env!("OUT_DIR"), "/accumulate.rs"));
+}
+#[allow(dead_code)]
+pub mod blit {
+    include!(concat!(env!("OUT_DIR"), "/blit.rs"));
+}
+#[allow(dead_code)]
+pub mod bloom {
+    include!(concat!(env!("OUT_DIR"), "/bloom.rs"));
+}
+#[allow(dead_code)]
 pub mod blit_depth {
     include!(concat!(env!("OUT_DIR"), "/blit_depth.rs"));
 }
@@ -11,6 +23,14 @@ pub mod depth_pyramid {
     include!(concat!(env!("OUT_DIR"), "/depth_pyramid.rs"));
 }
 #[allow(dead_code)]
+pub mod edge {
+    include!(concat!(env!("OUT_DIR"), "/edge.rs"));
+}
+#[allow(dead_code)]
+pub mod grid {
+    include!(concat!(env!("OUT_DIR"), "/grid.rs"));
+}
+#[allow(dead_code)]
 pub mod model {
     include!(concat!(env!("OUT_DIR"), "/model.rs"));
 }
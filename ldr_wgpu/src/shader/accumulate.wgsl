@@ -0,0 +1,24 @@
+// The resolved color of the current frame.
+@group(0) @binding(0)
+var color_texture: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // Draw a single triangle covering the screen without any vertex buffers.
+    var out: VertexOutput;
+    let x = f32((vertex_index << 1u) & 2u);
+    let y = f32(vertex_index & 2u);
+    out.clip_position = vec4(x * 2.0 - 1.0, y * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The accumulation texture has the same size, so load without filtering.
+    // The blend constant weights this frame to update the running average.
+    return textureLoad(color_texture, vec2<i32>(in.clip_position.xy), 0);
+}
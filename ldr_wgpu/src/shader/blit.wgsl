@@ -0,0 +1,440 @@
+struct PostProcessSettings {
+    // The world space direction the light travels.
+    light_direction: vec4<f32>,
+    bloom_intensity: f32,
+    contact_shadow_intensity: f32,
+    contact_shadow_length: f32,
+    contact_shadow_thickness: f32,
+    ssao_radius: f32,
+    ssao_intensity: f32,
+    grain_intensity: f32,
+    // The size of each grain in pixels.
+    grain_size: f32,
+    grain_seed: u32,
+    // 0 for overlay, 1 for multiply, and 2 for add.
+    grain_blend: u32,
+    // The size of each checker square in pixels or 0.0 to disable the checker.
+    checker_size: f32,
+    // The number of SSAO samples per pixel.
+    ssao_samples: u32,
+    checker_color0: vec4<f32>,
+    checker_color1: vec4<f32>,
+    // Linear RGB with the alpha unused.
+    fog_color: vec4<f32>,
+    // The view space distance where the fog begins.
+    fog_start: f32,
+    // The exponential density or 0.0 to disable fog.
+    fog_density: f32,
+    _padding1: f32,
+    _padding2: f32,
+    // The world space direction the light travels for ground shadows.
+    ground_light_direction: vec4<f32>,
+    // Premultiplied RGBA shadow color or 0.0 alpha to disable the ground plane.
+    ground_color: vec4<f32>,
+    // The world space axis perpendicular to the ground plane.
+    ground_axis: vec4<f32>,
+    // The coordinate of the ground plane along the axis in world space.
+    ground_height: f32,
+    // The maximum distance from the ground to search for occluders.
+    ground_shadow_length: f32,
+    // Manually pad to a multiple of 16 bytes.
+    _padding3: f32,
+    _padding4: f32,
+    // Premultiplied RGBA outline color.
+    outline_color: vec4<f32>,
+    // The outline width in output pixels or 0.0 to disable outlines.
+    outline_thickness: f32,
+    // The minimum change in depth relative to the view depth.
+    outline_depth_threshold: f32,
+    // The cosine of the minimum angle between surface normals.
+    outline_normal_threshold: f32,
+    // 1 to outline changes in surface normals in addition to silhouettes.
+    outline_normals: u32,
+    // 0 for none, 1 for Reinhard, and 2 for ACES.
+    tonemap: u32,
+    _padding5: f32,
+    _padding6: f32,
+    _padding7: f32,
+}
+
+struct Camera {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var color_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var bloom_texture: texture_2d<f32>;
+
+@group(0) @binding(2)
+var color_sampler: sampler;
+
+@group(0) @binding(3)
+var<uniform> settings: PostProcessSettings;
+
+@group(0) @binding(4)
+var<uniform> camera: Camera;
+
+// The reversed-z depth for the current frame.
+@group(0) @binding(5)
+var depth_texture: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // Draw a single triangle covering the screen without any vertex buffers.
+    // https://www.saschawillems.de/blog/2016/08/13/vulkan-tutorial-on-rendering-a-fullscreen-quad-without-buffers/
+    var out: VertexOutput;
+    let x = f32((vertex_index << 1u) & 2u);
+    let y = f32(vertex_index & 2u);
+    out.clip_position = vec4(x * 2.0 - 1.0, y * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2(x, 1.0 - y);
+    return out;
+}
+
+fn load_depth(uv: vec2<f32>) -> f32 {
+    let dimensions = vec2<i32>(textureDimensions(depth_texture));
+    let coords = clamp(vec2<i32>(uv * vec2<f32>(dimensions)), vec2(0), dimensions - vec2(1));
+    return textureLoad(depth_texture, coords, 0).x;
+}
+
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = camera.inverse_projection * ndc;
+    return position.xyz / position.w;
+}
+
+fn contact_shadow(uv: vec2<f32>, depth: f32) -> f32 {
+    // March a short distance from the surface toward the light in view space.
+    // Any sample hidden behind the depth buffer is likely occluded by nearby geometry.
+    // https://panoskarabelas.com/posts/screen_space_shadows/
+    let position = view_position(uv, depth);
+    let to_light = normalize((camera.view * vec4(-settings.light_direction.xyz, 0.0)).xyz);
+
+    let step_count = 16;
+    let step_length = settings.contact_shadow_length / f32(step_count);
+    for (var i = 1; i <= step_count; i++) {
+        let sample_position = position + to_light * step_length * f32(i);
+
+        let clip_position = camera.projection * vec4(sample_position, 1.0);
+        let ndc = clip_position.xy / clip_position.w;
+        let sample_uv = ndc * vec2(0.5, -0.5) + vec2(0.5);
+        if (any(sample_uv < vec2(0.0)) || any(sample_uv > vec2(1.0))) {
+            break;
+        }
+
+        // A depth of 0.0 is the background for reversed-z.
+        let scene_depth = load_depth(sample_uv);
+        if (scene_depth > 0.0) {
+            // The camera looks down -Z, so closer surfaces have larger Z values.
+            // Limit the thickness to avoid shadows from objects far in front of the sample.
+            let difference = view_position(sample_uv, scene_depth).z - sample_position.z;
+            if (difference > 0.0 && difference < settings.contact_shadow_thickness) {
+                return 1.0 - settings.contact_shadow_intensity;
+            }
+        }
+    }
+
+    return 1.0;
+}
+
+fn view_normal(uv: vec2<f32>, position: vec3<f32>) -> vec3<f32> {
+    // Screen space derivatives of the position give the face normal.
+    // Texture coordinates increase down the screen, so the order gives normals facing the camera.
+    let texel_size = 1.0 / vec2<f32>(textureDimensions(depth_texture));
+    let uv_x = uv + vec2(texel_size.x, 0.0);
+    let uv_y = uv + vec2(0.0, texel_size.y);
+    let position_x = view_position(uv_x, load_depth(uv_x));
+    let position_y = view_position(uv_y, load_depth(uv_y));
+    return normalize(cross(position_y - position, position_x - position));
+}
+
+fn ambient_occlusion(uv: vec2<f32>, depth: f32, coords: vec2<f32>) -> f32 {
+    // Depth only SSAO with hemisphere sampling around normals reconstructed from depth.
+    // https://learnopengl.com/Advanced-Lighting/SSAO
+    let position = view_position(uv, depth);
+    let normal = view_normal(uv, position);
+
+    // Randomly reflect the kernel per pixel to trade banding for noise.
+    // http://www.iryoku.com/next-generation-post-processing-in-call-of-duty-advanced-warfare
+    let noise = fract(52.9829189 * fract(dot(coords, vec2(0.06711056, 0.00583715))));
+    let random = normalize(vec3(cos(noise * 6.2831853), sin(noise * 6.2831853), noise - 0.5));
+
+    let sample_count = max(settings.ssao_samples, 1u);
+    var occlusion = 0.0;
+    for (var i = 0u; i < sample_count; i++) {
+        // Flip samples into the hemisphere around the normal.
+        var offset = reflect(ssao_kernel(i, sample_count), random);
+        if (dot(offset, normal) < 0.0) {
+            offset = -offset;
+        }
+        let sample_position = position + offset * settings.ssao_radius;
+
+        let clip_position = camera.projection * vec4(sample_position, 1.0);
+        let sample_uv = clip_position.xy / clip_position.w * vec2(0.5, -0.5) + vec2(0.5);
+        let scene_depth = load_depth(sample_uv);
+        if (scene_depth > 0.0) {
+            let scene_z = view_position(sample_uv, scene_depth).z;
+            // Fade out occluders outside the sampling radius to avoid dark halos.
+            let range = smoothstep(0.0, 1.0, settings.ssao_radius / abs(position.z - scene_z));
+            if (scene_z > sample_position.z + 0.01 * settings.ssao_radius) {
+                occlusion += range;
+            }
+        }
+    }
+
+    return 1.0 - occlusion / f32(sample_count) * settings.ssao_intensity;
+}
+
+fn ssao_kernel(i: u32, count: u32) -> vec3<f32> {
+    // Spiral points over the hemisphere using the golden angle for an even distribution.
+    // Scale the lengths to place more samples close to the surface.
+    let t = (f32(i) + 0.5) / f32(count);
+    let z = 1.0 - t;
+    let radius = sqrt(1.0 - z * z);
+    let angle = f32(i) * 2.3999632;
+    let direction = vec3(radius * cos(angle), radius * sin(angle), z);
+    return direction * mix(0.1, 1.0, t * t);
+}
+
+fn pcg_hash(input: u32) -> u32 {
+    // https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn film_grain(color: vec4<f32>, coords: vec2<f32>) -> vec4<f32> {
+    // The noise only depends on the pixel and seed for reproducible renders.
+    let cell = vec2<u32>(coords / max(settings.grain_size, 1.0));
+    let hash = pcg_hash(cell.x + pcg_hash(cell.y + pcg_hash(settings.grain_seed)));
+    let noise = (f32(hash) / 4294967295.0 - 0.5) * settings.grain_intensity;
+
+    // Blend with straight alpha to avoid adding grain to transparent backgrounds.
+    if (color.a <= 0.0) {
+        return color;
+    }
+    let base = color.rgb / color.a;
+
+    var blended = base;
+    switch settings.grain_blend {
+        case 1u: {
+            blended = base * (1.0 + noise);
+        }
+        case 2u: {
+            blended = base + noise;
+        }
+        default: {
+            // https://en.wikipedia.org/wiki/Blend_modes#Overlay
+            let grain = vec3(0.5 + noise);
+            blended = select(
+                1.0 - 2.0 * (1.0 - base) * (1.0 - grain),
+                2.0 * base * grain,
+                base < vec3(0.5)
+            );
+        }
+    }
+
+    return vec4(max(blended, vec3(0.0)) * color.a, color.a);
+}
+
+fn tonemap(color: vec4<f32>) -> vec4<f32> {
+    // Map the straight color to avoid changing the alpha of partially transparent pixels.
+    if (color.a <= 0.0) {
+        return color;
+    }
+    let rgb = color.rgb / color.a;
+
+    var mapped = rgb;
+    switch settings.tonemap {
+        case 1u: {
+            // https://en.wikipedia.org/wiki/Tone_mapping
+            mapped = rgb / (1.0 + rgb);
+        }
+        case 2u: {
+            // https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+            let curve = (rgb * (2.51 * rgb + 0.03)) / (rgb * (2.43 * rgb + 0.59) + 0.14);
+            mapped = clamp(curve, vec3(0.0), vec3(1.0));
+        }
+        default: {}
+    }
+
+    return vec4(mapped * color.a, color.a);
+}
+
+fn fog(color: vec4<f32>, uv: vec2<f32>, depth: f32) -> vec4<f32> {
+    // Exponential fog based on the distance from the camera.
+    // https://learn.microsoft.com/en-us/windows/win32/direct3d9/fog-formulas
+    let distance = max(length(view_position(uv, depth)) - settings.fog_start, 0.0);
+    let factor = 1.0 - exp(-settings.fog_density * distance);
+
+    // Premultiplied alpha.
+    return vec4(mix(color.rgb, settings.fog_color.rgb * color.a, factor), color.a);
+}
+
+fn ground_shadow(uv: vec2<f32>) -> f32 {
+    // Intersect the view ray with the ground plane in view space.
+    // Using two depths along the ray works for both perspective and orthographic projections.
+    let origin = view_position(uv, 1.0);
+    let direction = view_position(uv, 0.5) - origin;
+    let axis = settings.ground_axis.xyz;
+    let plane_normal = (camera.view * vec4(axis, 0.0)).xyz;
+    let plane_point = (camera.view * vec4(axis * settings.ground_height, 1.0)).xyz;
+
+    let denominator = dot(direction, plane_normal);
+    if (abs(denominator) < 0.000001) {
+        return 0.0;
+    }
+    let t = dot(plane_point - origin, plane_normal) / denominator;
+    if (t <= 0.0) {
+        return 0.0;
+    }
+    let position = origin + direction * t;
+
+    // March toward the light like contact shadows but over a much longer distance.
+    // Only occluders visible on screen cast shadows, which works well for framed models.
+    let to_light = normalize((camera.view * vec4(-settings.ground_light_direction.xyz, 0.0)).xyz);
+    let step_count = 32;
+    let step_length = settings.ground_shadow_length / f32(step_count);
+    for (var i = 1; i <= step_count; i++) {
+        let sample_position = position + to_light * step_length * f32(i);
+
+        let clip_position = camera.projection * vec4(sample_position, 1.0);
+        let sample_uv = clip_position.xy / clip_position.w * vec2(0.5, -0.5) + vec2(0.5);
+        if (any(sample_uv < vec2(0.0)) || any(sample_uv > vec2(1.0))) {
+            break;
+        }
+
+        let scene_depth = load_depth(sample_uv);
+        if (scene_depth > 0.0) {
+            let difference = view_position(sample_uv, scene_depth).z - sample_position.z;
+            if (difference > 0.0 && difference < settings.ground_shadow_length) {
+                // Soften shadows further from their occluders.
+                return 1.0 - f32(i - 1) / f32(step_count);
+            }
+        }
+    }
+
+    return 0.0;
+}
+
+fn is_behind(view_depth: f32, uv: vec2<f32>) -> bool {
+    // A depth of 0.0 is the background for reversed-z.
+    let depth = load_depth(uv);
+    if (depth <= 0.0) {
+        return true;
+    }
+    // Scale by the view depth to outline near and far parts consistently.
+    let difference = -view_position(uv, depth).z - view_depth;
+    return difference > settings.outline_depth_threshold * view_depth;
+}
+
+fn is_crease(normal: vec3<f32>, uv: vec2<f32>) -> bool {
+    let depth = load_depth(uv);
+    if (depth <= 0.0) {
+        return false;
+    }
+    let neighbor_normal = view_normal(uv, view_position(uv, depth));
+    return dot(normal, neighbor_normal) < settings.outline_normal_threshold;
+}
+
+fn outline(uv: vec2<f32>, depth: f32, output_texel: vec2<f32>) -> bool {
+    // Compare neighbors on each side to find silhouettes and sharp creases.
+    // Only the closer side of a silhouette is outlined to keep a consistent thickness.
+    // https://roystan.net/articles/outline-shader/
+    let offset = output_texel * settings.outline_thickness;
+    let position = view_position(uv, depth);
+    let view_depth = -position.z;
+    if (is_behind(view_depth, uv + vec2(offset.x, 0.0))
+        || is_behind(view_depth, uv - vec2(offset.x, 0.0))
+        || is_behind(view_depth, uv + vec2(0.0, offset.y))
+        || is_behind(view_depth, uv - vec2(0.0, offset.y))) {
+        return true;
+    }
+
+    // Creases affect both sides, so only check one neighbor on each axis.
+    if (settings.outline_normals != 0u) {
+        let normal = view_normal(uv, position);
+        return is_crease(normal, uv + vec2(offset.x, 0.0))
+            || is_crease(normal, uv + vec2(0.0, offset.y));
+    }
+
+    return false;
+}
+
+fn downsample_color(uv: vec2<f32>, output_texel: vec2<f32>) -> vec4<f32> {
+    // Average all the supersampled pixels covered by this output pixel.
+    // Each bilinear sample already averages 2x2 pixels.
+    let supersample = output_texel * vec2<f32>(textureDimensions(color_texture));
+    let taps = max(vec2<i32>(ceil(supersample * 0.5 - 0.01)), vec2(1));
+
+    var color = vec4(0.0);
+    for (var y = 0; y < taps.y; y++) {
+        for (var x = 0; x < taps.x; x++) {
+            let offset = (vec2(f32(x), f32(y)) + 0.5) / vec2<f32>(taps) - 0.5;
+            color += textureSampleLevel(color_texture, color_sampler, uv + offset * output_texel, 0.0);
+        }
+    }
+    return color / f32(taps.x * taps.y);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The size of an output pixel in UV coordinates.
+    let output_texel = abs(vec2(dpdx(in.uv.x), dpdy(in.uv.y)));
+    var color = downsample_color(in.uv, output_texel);
+
+    // A depth of 0.0 is the background for reversed-z.
+    let depth = load_depth(in.uv);
+    if (depth > 0.0) {
+        if (settings.contact_shadow_intensity > 0.0) {
+            color = vec4(color.rgb * contact_shadow(in.uv, depth), color.a);
+        }
+        if (settings.ssao_intensity > 0.0) {
+            color = vec4(color.rgb * ambient_occlusion(in.uv, depth, in.clip_position.xy), color.a);
+        }
+        if (settings.fog_density > 0.0) {
+            color = fog(color, in.uv, depth);
+        }
+        // Outline after fog to keep the lines visible for distant parts.
+        if (settings.outline_thickness > 0.0 && outline(in.uv, depth, output_texel)) {
+            color = settings.outline_color + color * (1.0 - settings.outline_color.a);
+        }
+    } else if (settings.ground_color.a > 0.0) {
+        // Composite the premultiplied shadow over the background.
+        // Unshadowed areas stay transparent for compositing over other backgrounds.
+        let shadow = settings.ground_color * ground_shadow(in.uv);
+        color = shadow + color * (1.0 - shadow.a);
+    }
+
+    // Bloom only adds light, so leave the alpha unchanged.
+    // This is still valid for premultiplied alpha and composites additively.
+    let bloom = textureSample(bloom_texture, color_sampler, in.uv).rgb * settings.bloom_intensity;
+
+    color = vec4(color.rgb + bloom, color.a);
+
+    // Grain and the checker are designed for the final display range.
+    if (settings.tonemap != 0u) {
+        color = tonemap(color);
+    }
+
+    if (settings.grain_intensity > 0.0) {
+        color = film_grain(color, in.clip_position.xy);
+    }
+
+    if (settings.checker_size > 0.0) {
+        // Composite the premultiplied scene over the checker.
+        let cell = vec2<i32>(floor(in.clip_position.xy / settings.checker_size));
+        let checker = select(settings.checker_color0, settings.checker_color1, ((cell.x + cell.y) & 1) == 1);
+        color = color + checker * (1.0 - color.a);
+    }
+
+    return color;
+}
@@ -0,0 +1,76 @@
+struct BloomSettings {
+    threshold: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: BloomSettings;
+
+@group(0) @binding(1)
+var input: texture_2d<f32>;
+
+@group(0) @binding(2)
+var input_sampler: sampler;
+
+@group(0) @binding(3)
+var output: texture_storage_2d<rgba16float, write>;
+
+// Bright pass that also downsamples the scene color to the bloom resolution.
+@compute
+@workgroup_size(16, 16, 1)
+fn threshold(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dimensions = textureDimensions(output);
+    if (global_id.x >= dimensions.x || global_id.y >= dimensions.y) {
+        return;
+    }
+
+    // Sample between texels with linear filtering to average the higher resolution input.
+    let uv = (vec2<f32>(global_id.xy) + vec2(0.5)) / vec2<f32>(dimensions);
+    let color = textureSampleLevel(input, input_sampler, uv, 0.0).rgb;
+
+    // Scale the color to keep only the part of the brightness above the threshold.
+    // This avoids harsh transitions compared to discarding dim pixels entirely.
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - settings.threshold, 0.0) / max(brightness, 0.0001);
+
+    textureStore(output, vec2<i32>(global_id.xy), vec4(color * contribution, 1.0));
+}
+
+fn blur(coords: vec2<i32>, direction: vec2<i32>) -> vec4<f32> {
+    // Separable 9 tap gaussian blur.
+    // https://www.rastergrid.com/blog/2010/09/efficient-gaussian-blur-with-linear-sampling/
+    var weights = array<f32, 5>(0.2270270270, 0.1945945946, 0.1216216216, 0.0540540541, 0.0162162162);
+
+    let max_coords = vec2<i32>(textureDimensions(input)) - vec2(1);
+
+    var color = textureLoad(input, coords, 0).rgb * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = direction * i;
+        color += textureLoad(input, clamp(coords + offset, vec2(0), max_coords), 0).rgb * weights[i];
+        color += textureLoad(input, clamp(coords - offset, vec2(0), max_coords), 0).rgb * weights[i];
+    }
+    return vec4(color, 1.0);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn blur_horizontal(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dimensions = textureDimensions(output);
+    if (global_id.x >= dimensions.x || global_id.y >= dimensions.y) {
+        return;
+    }
+
+    let coords = vec2<i32>(global_id.xy);
+    textureStore(output, coords, blur(coords, vec2(1, 0)));
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn blur_vertical(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dimensions = textureDimensions(output);
+    if (global_id.x >= dimensions.x || global_id.y >= dimensions.y) {
+        return;
+    }
+
+    let coords = vec2<i32>(global_id.xy);
+    textureStore(output, coords, blur(coords, vec2(0, 1)));
+}
@@ -6,6 +6,8 @@ struct Camera {
     p00: f32,
     p11: f32,
     frustum: vec4<f32>,
+    // The plane offsets are zero for perspective projections.
+    frustum_offset: vec4<f32>,
     view: mat4x4<f32>,
     view_projection: mat4x4<f32>,
 }
@@ -14,14 +16,11 @@ struct Camera {
 var<uniform> camera: Camera;
 
 // Mipmapped version of the depth map.
+// Texels are loaded directly instead of sampled
+// since UV coordinates don't line up with mip texels for odd sizes.
 @group(0) @binding(1)
 var depth_pyramid: texture_2d<f32>;
 
-// Assume this is a point sampler.
-// We don't want to interpolate depth values.
-@group(0) @binding(2)
-var depth_sampler: sampler;
-
 struct InstanceBounds {
     sphere: vec4<f32>,
     min_xyz: vec4<f32>,
@@ -40,12 +39,39 @@ var<storage, read_write> new_visibility: array<u32>;
 @group(1) @binding(3)
 var<storage, read> transparent: array<u32>;
 
+// 0 for culled, 1 for always visible, or 2 for always hidden.
+@group(1) @binding(4)
+var<storage, read> visibility_override: array<u32>;
+
+struct DrawIndexedIndirect {
+    vertex_count: u32,
+    instance_count: u32,
+    base_index: u32,
+    vertex_offset: i32,
+    base_instance: u32,
+}
+
+// Transparent draws are sorted by distance, so the draw index may not match the instance index.
+@group(1) @binding(5)
+var<storage, read> draws: array<DrawIndexedIndirect>;
+
 fn is_within_view_frustum(center: vec3<f32>, radius: f32) -> bool {
 	// Cull objects completely outside the viewing frustum.
-    if center.z * camera.frustum.y - abs(center.x) * camera.frustum.x < -radius {
+    if center.z * camera.frustum.y - abs(center.x) * camera.frustum.x + camera.frustum_offset.x < -radius {
+        return false;
+    }
+    if center.z * camera.frustum.w - abs(center.y) * camera.frustum.z + camera.frustum_offset.y < -radius {
+        return false;
+    }
+
+    // The view space uses a right-handed coordinate system looking down -Z.
+    // Cull objects completely behind the near plane, which includes objects behind the camera.
+    if center.z - radius > -camera.z_near {
         return false;
     }
-    if center.z * camera.frustum.w - abs(center.y) * camera.frustum.z < -radius {
+    // Cull objects completely past the far plane.
+    // This never culls anything for an infinite far plane.
+    if center.z + radius < -camera.z_far {
         return false;
     }
 
@@ -61,6 +87,15 @@ fn world_to_coords(position: vec3<f32>) -> vec3<f32> {
     return vec3(ndc_pos_xy, ndc_pos.z);
 }
 
+fn load_pyramid_depth(base_pixel: vec2<u32>, level: i32) -> f32 {
+    // Each texel reduces the texels at twice its coordinates in the previous level.
+    // The last texel of odd sized levels also reduces the extra row or column,
+    // so clamping keeps the edges of the base level covered.
+    let max_coords = textureDimensions(depth_pyramid, level) - 1u;
+    let coords = min(base_pixel >> vec2(u32(level)), max_coords);
+    return textureLoad(depth_pyramid, coords, level).x;
+}
+
 fn is_occluded(min_xyz: vec3<f32>, max_xyz: vec3<f32>) -> bool {
     // Occlusion based culling using axis aligned bounding boxes.
     // Transform the corners to the same space as the depth map.
@@ -97,23 +132,26 @@ fn is_occluded(min_xyz: vec3<f32>, max_xyz: vec3<f32>) -> bool {
     let aabb = vec4(min_xyz_final.xy, max_xyz_final.xy);
 
     // Calculate the covered area in pixels for the base mip level.
-    let aabb_size = max_xyz_final.xy - min_xyz_final.xy;
-    let aabb_size_base_level = aabb_size * vec2<f32>(textureDimensions(depth_pyramid, 0));
+    let base_dimensions = vec2<f32>(textureDimensions(depth_pyramid, 0));
+    let aabb_size_base_level = (max_xyz_final.xy - min_xyz_final.xy) * base_dimensions;
 
-    // Calculate the mip level that will be covered by at most 2x2 pixels.
-    // 4x4 pixels on the base level should use mip level 1.
-    // 3x3 pixels should also use level 1.
-    let level = ceil(log2(max(aabb_size_base_level.x, aabb_size_base_level.y))) - 1.0;
+    // Calculate the mip level where the AABB covers at most 2x2 texels.
+    // A size of up to 2^level base pixels can only overlap 2 texels in each dimension.
+    // Larger sizes could overlap 3 texels and miss the middle texel.
+    let max_level = i32(textureNumLevels(depth_pyramid)) - 1;
+    let aabb_size_max = max(max(aabb_size_base_level.x, aabb_size_base_level.y), 1.0);
+    let level = clamp(i32(ceil(log2(aabb_size_max))), 0, max_level);
 
     // Compute the min depth of the 2x2 texels for the AABB.
     // The depth pyramid also uses min for reduction.
     // This helps make the occlusion conservative.
     // The comparisons are reversed since we use a reversed-z buffer.
-    // The AABB coordinates are between 0.0 and 1.0.
-    let depth00 = textureSampleLevel(depth_pyramid, depth_sampler, aabb.xy, level).x;
-    let depth01 = textureSampleLevel(depth_pyramid, depth_sampler, aabb.zy, level).x;
-    let depth10 = textureSampleLevel(depth_pyramid, depth_sampler, aabb.xw, level).x;
-    let depth11 = textureSampleLevel(depth_pyramid, depth_sampler, aabb.zw, level).x;
+    let min_pixel = vec2<u32>(clamp(aabb.xy * base_dimensions, vec2(0.0), base_dimensions - 1.0));
+    let max_pixel = vec2<u32>(clamp(aabb.zw * base_dimensions, vec2(0.0), base_dimensions - 1.0));
+    let depth00 = load_pyramid_depth(min_pixel, level);
+    let depth01 = load_pyramid_depth(vec2(max_pixel.x, min_pixel.y), level);
+    let depth10 = load_pyramid_depth(vec2(min_pixel.x, max_pixel.y), level);
+    let depth11 = load_pyramid_depth(max_pixel, level);
     let farthest_occluder_depth = min(min(depth00, depth01), min(depth10, depth11));
 
     // Check if the closest depth of the object exceeds the farthest occluder depth.
@@ -123,9 +161,9 @@ fn is_occluded(min_xyz: vec3<f32>, max_xyz: vec3<f32>) -> bool {
     return closest_depth < farthest_occluder_depth;
 }
 
-fn is_visible(index: u32) -> bool {
+fn is_visible(instance: u32) -> bool {
     // Bounding spheres for frustum culling.
-    let bounding_sphere = instance_bounds[index].sphere;
+    let bounding_sphere = instance_bounds[instance].sphere;
     let center_view = (camera.view * vec4(bounding_sphere.xyz, 1.0)).xyz;
     // Assume no scaling in the view matrix.
     let radius = bounding_sphere.w;
@@ -135,8 +173,8 @@ fn is_visible(index: u32) -> bool {
     }
 
     // Axis-aligned bounding box for occlusion culling.
-    let min_xyz = instance_bounds[index].min_xyz.xyz;
-    let max_xyz = instance_bounds[index].max_xyz.xyz;
+    let min_xyz = instance_bounds[instance].min_xyz.xyz;
+    let max_xyz = instance_bounds[instance].max_xyz.xyz;
 
     // Use the existing state for visibility.
     if is_occluded(min_xyz, max_xyz) {
@@ -149,8 +187,19 @@ fn is_visible(index: u32) -> bool {
 @compute
 @workgroup_size(256)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    update_visibility(global_id.x, true);
+}
+
+// Skip frustum and occlusion culling to check if missing objects are culled incorrectly.
+// This doesn't use the camera or depth pyramid.
+@compute
+@workgroup_size(256)
+fn all_visible(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    update_visibility(global_id.x, false);
+}
+
+fn update_visibility(index: u32, culling_enabled: bool) {
     // Assume all the arrays have the same length.
-    let index = global_id.x;
     if index >= arrayLength(&visibility) {
         return;
     }
@@ -158,7 +207,19 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     // Set visibility for all objects based on culling.
     // This serves as a visibility estimate for next frame.
     let previously_visible = visibility[index] != 0u;
-    let visible = is_visible(index);
+    let instance = draws[index].base_instance;
+    var visible = false;
+    switch visibility_override[instance] {
+        case 1u: {
+            visible = true;
+        }
+        case 2u: {
+            visible = false;
+        }
+        default: {
+            visible = !culling_enabled || is_visible(instance);
+        }
+    }
     // Transparent objects should never be in the previously visible pass.
     // This prevents transparent objects occluding other objects.
     let is_transparent = transparent[index] != 0u;
@@ -16,7 +16,7 @@ fn loadDepth(x: i32, y: i32, dimensions: vec2<i32>) -> f32 {
 @compute
 @workgroup_size(16, 16, 1)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let output_dimensions = vec2<i32>(textureDimensions(input));
+    let output_dimensions = vec2<i32>(textureDimensions(output));
     let output_coords = vec2<i32>(global_id.xy);
     if (output_coords.x >= output_dimensions.x || output_coords.y >= output_dimensions.y) {
         return;
@@ -0,0 +1,114 @@
+struct Camera {
+    view_projection: mat4x4<f32>,
+    // The viewport width and height in pixels.
+    viewport_size: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct EdgeSettings {
+    // The line width in pixels.
+    width: f32,
+}
+
+@group(0) @binding(1)
+var<uniform> settings: EdgeSettings;
+
+// Each edge segment is expanded to a quad with 4 vertices.
+struct VertexInput {
+    @location(0) start: vec3<f32>,
+    @location(1) end: vec3<f32>,
+    // The endpoint as 0.0 for start or 1.0 for end and the side of the line as -1.0 or 1.0.
+    @location(2) corner: vec2<f32>,
+    // The packed RGBA color of the line.
+    @location(3) color: u32,
+}
+
+struct InstanceInput {
+    @location(4) model_matrix_0: vec4<f32>,
+    @location(5) model_matrix_1: vec4<f32>,
+    @location(6) model_matrix_2: vec4<f32>,
+    @location(7) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // The signed distance in pixels from the center of the line.
+    @location(0) distance: f32,
+    @location(1) color: vec4<f32>,
+}
+
+// Avoid projecting points on or behind the camera.
+const MIN_W: f32 = 0.001;
+
+fn clip_to_near(clip: vec4<f32>, other: vec4<f32>) -> vec4<f32> {
+    if clip.w < MIN_W && other.w >= MIN_W {
+        let t = (MIN_W - clip.w) / (other.w - clip.w);
+        return mix(clip, other, t);
+    }
+    return clip;
+}
+
+fn unpack_color(color: u32) -> vec4<f32> {
+    // wgpu doesn't support unpack4x8unorm for DX12.
+    let r = f32(color & 0xFFu);
+    let g = f32((color >> 8u) & 0xFFu);
+    let b = f32((color >> 16u) & 0xFFu);
+    let a = f32((color >> 24u) & 0xFFu);
+    return vec4(r, g, b, a) / 255.0;
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let model_view_projection = camera.view_projection * model_matrix;
+
+    // Clip the segment to the near plane so that both endpoints project correctly.
+    let start = model_view_projection * vec4(model.start, 1.0);
+    let end = model_view_projection * vec4(model.end, 1.0);
+    let clip_start = clip_to_near(start, end);
+    let clip_end = clip_to_near(end, start);
+
+    // Expand the line to a constant width in screen space.
+    let half_viewport = camera.viewport_size.xy * 0.5;
+    let screen_start = clip_start.xy / clip_start.w * half_viewport;
+    let screen_end = clip_end.xy / clip_end.w * half_viewport;
+
+    let delta = screen_end - screen_start;
+    let direction = select(vec2(1.0, 0.0), normalize(delta), length(delta) > 0.0001);
+    let normal = vec2(-direction.y, direction.x);
+
+    // Add a pixel for the antialiased falloff.
+    // Extending past the endpoints helps fill gaps between connected segments.
+    let half_width = settings.width * 0.5 + 1.0;
+    let side = model.corner.y;
+    let extension = model.corner.x * 2.0 - 1.0;
+    let offset = (normal * side + direction * extension) * half_width;
+
+    let clip = select(clip_start, clip_end, model.corner.x > 0.5);
+
+    var out: VertexOutput;
+    out.clip_position = vec4(clip.xy + offset / half_viewport * clip.w, clip.z, clip.w);
+    out.distance = side * half_width;
+    out.color = unpack_color(model.color);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Fade out over a single pixel at the border for smooth edges.
+    let coverage = clamp(settings.width * 0.5 + 0.5 - abs(in.distance), 0.0, 1.0);
+
+    // Premultiplied alpha.
+    let alpha = in.color.a * coverage;
+    return vec4(in.color.rgb * alpha, alpha);
+}
@@ -0,0 +1,54 @@
+struct Camera {
+    view_projection: mat4x4<f32>,
+    position: vec4<f32>
+}
+
+struct Grid {
+    color: vec4<f32>,
+    height: f32,
+    spacing: f32,
+    extent: f32,
+    // The number of lines parallel to each axis.
+    line_count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(0) @binding(1)
+var<uniform> grid: Grid;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // Generate the line endpoints without any vertex buffers.
+    // Lines parallel to the X axis come first followed by lines parallel to the Z axis.
+    let line_index = vertex_index / 2u;
+    let is_end = (vertex_index % 2u) == 1u;
+
+    let offset = f32(line_index % grid.line_count) * grid.spacing - grid.extent;
+    let along = select(-grid.extent, grid.extent, is_end);
+
+    var position = vec3(along, grid.height, offset);
+    if (line_index >= grid.line_count) {
+        position = vec3(offset, grid.height, along);
+    }
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_projection * vec4(position, 1.0);
+    out.position = position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Fade out with distance to hide the boundary of the grid.
+    let fade = 1.0 - smoothstep(grid.extent * 0.5, grid.extent, length(in.position.xz));
+    let alpha = grid.color.a * fade;
+    // Premultiplied alpha.
+    return vec4(grid.color.rgb * alpha, alpha);
+}
@@ -6,6 +6,32 @@ struct Camera {
 @group(0) @binding(0)
 var<uniform> camera: Camera;
 
+struct Light {
+    // The world space direction the light travels.
+    direction: vec4<f32>,
+    // Linear RGB with the alpha unused.
+    color: vec4<f32>,
+    ambient: f32,
+    // Manually pad to a multiple of 16 bytes.
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+
+@group(0) @binding(1)
+var<uniform> light: Light;
+
+// The RGB color for selected instances blended with the shaded color using alpha.
+@group(0) @binding(2)
+var<uniform> highlight: vec4<f32>;
+
+// Nonzero for each selected instance.
+@group(1) @binding(0)
+var<storage, read> selected: array<u32>;
+
+// A dim light from the camera keeps surfaces facing away from the key light readable.
+const FILL_INTENSITY: f32 = 0.4;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) color: u32,
@@ -19,11 +45,19 @@ struct InstanceInput {
     @location(6) model_matrix_3: vec4<f32>,
 }
 
+// The index into the instance transforms buffer from a separate per instance buffer.
+// The instance_index builtin doesn't include the base instance on all backends like DX12.
+struct InstanceIndexInput {
+    @location(7) instance_index: u32,
+}
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
-    @location(2) color: vec4<f32>
+    @location(2) color: vec4<f32>,
+    // The index into the instance transforms buffer for picking.
+    @location(3) @interpolate(flat) instance_index: u32,
 }
 
 fn unpack_color(color: u32) -> vec4<f32> {
@@ -39,6 +73,7 @@ fn unpack_color(color: u32) -> vec4<f32> {
 fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
+    index: InstanceIndexInput,
 ) -> VertexOutput {
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
@@ -53,22 +88,30 @@ fn vs_main(
     out.color = unpack_color(model.color);
     // TODO: is this always correct?
     out.normal = (model_matrix * vec4(model.normal.xyz, 0.0)).xyz;
+    out.instance_index = index.instance_index;
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     // TODO: avoid normalization?
-    // Calculate the lighting relative to the camera.
+    // Normals are in world space, so use the world space light direction.
+    let normal = normalize(in.normal.xyz);
     let viewVector = normalize(camera.position.xyz - in.position.xyz);
-    let lighting = dot(in.normal.xyz, viewVector) * 0.75 + 0.25;
+    let key = max(dot(normal, -light.direction.xyz), 0.0) * light.color.rgb;
+    let fill = max(dot(normal, viewVector), 0.0) * FILL_INTENSITY;
+    let lighting = light.ambient + (1.0 - light.ambient) * (key + fill);
     var color = in.color.rgb * lighting;
+    if selected[in.instance_index] != 0u {
+        color = mix(color, highlight.rgb, highlight.a);
+    }
     // Premultiplied alpha.
     return vec4(color * in.color.a, in.color.a);
 }
 
-// TODO: Is it better to use colors from a separate vertex buffer?
+// Write the instance plus one and the reversed-z depth bits.
+// This leaves 0 for the background when clearing.
 @fragment
-fn fs_edge_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4(0.0, 0.0, 0.0, 1.0);
-}
\ No newline at end of file
+fn fs_pick(in: VertexOutput) -> @location(0) vec2<u32> {
+    return vec2(in.instance_index + 1u, bitcast<u32>(in.clip_position.z));
+}
@@ -28,9 +28,23 @@ var<storage, read> scanned_visibility: array<u32>;
 // Use a buffer for storing the final compacted count.
 // The multi draw indirect count feature can read this buffer directly.
 // Unsupported devices will need to copy the count to the CPU.
+// There is one count for each chunk of draws.
 @group(0) @binding(7)
 var<storage, read_write> compacted_draw_count: array<u32>;
 
+// Draws for each chunk use separate vertex and index buffers and a separate draw call.
+struct DrawChunk {
+    // The index of the chunk for writing the compacted draw count.
+    chunk: u32,
+    // The index of the chunk's first draw.
+    first_draw: u32,
+    // The index of the chunk's first transparent draw or the end of the chunk.
+    transparent_start: u32,
+}
+
+@group(0) @binding(8)
+var<storage, read> draw_chunks: array<DrawChunk>;
+
 @compute
 @workgroup_size(256)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
@@ -41,7 +55,24 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         return;
     }
 
-    if (visibility[index] != 0u) {
+    // Compact each chunk separately starting from the chunk's first draw.
+    let draw_chunk = draw_chunks[index];
+    var chunk_offset = 0u;
+    if (draw_chunk.first_draw > 0u) {
+        chunk_offset = scanned_visibility[draw_chunk.first_draw - 1u];
+    }
+
+    if (index >= draw_chunk.transparent_start) {
+        // Transparent draws keep their position to draw them separately from opaque draws.
+        // Culled draws have no instances instead of being removed.
+        let instance_count = select(0u, 1u, visibility[index] != 0u);
+        var draw = draws[index];
+        draw.instance_count = instance_count;
+        compacted_draws[index] = draw;
+        var edge_draw = edge_draws[index];
+        edge_draw.instance_count = instance_count;
+        compacted_edge_draws[index] = edge_draw;
+    } else if (visibility[index] != 0u) {
         // Move each visible draw based on the number of previous visible draws.
         // This has the effect of removing empty draws and compacting the buffer.
         // For example, scanning 1 0 1 1 gives 1 1 2 3 and compacts to 1 1 1 0.
@@ -50,12 +81,18 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         if (index > 0u) {
             compacted_index = scanned_visibility[index - 1u];
         }
+        compacted_index = draw_chunk.first_draw + compacted_index - chunk_offset;
         compacted_draws[compacted_index] = draws[index];
         compacted_edge_draws[compacted_index] = edge_draws[index];
     }
 
-    if (index == 0u) {
-        // This only needs to be written once.
-        compacted_draw_count[0] = scanned_visibility[len - 1u];
+    // This only needs to be written once by the last draw in each chunk.
+    // Only opaque draws are compacted and included in the count.
+    if (index == len - 1u || draw_chunks[index + 1u].chunk != draw_chunk.chunk) {
+        var count = 0u;
+        if (draw_chunk.transparent_start > draw_chunk.first_draw) {
+            count = scanned_visibility[draw_chunk.transparent_start - 1u] - chunk_offset;
+        }
+        compacted_draw_count[draw_chunk.chunk] = count;
     }
 }
@@ -1,10 +1,11 @@
-use crate::{DEPTH_FORMAT, MSAA_SAMPLES};
+use crate::{COLOR_FORMAT, DEPTH_FORMAT, PICK_FORMAT};
 
 pub fn create_output_msaa_view(
     device: &wgpu::Device,
     width: u32,
     height: u32,
     format: wgpu::TextureFormat,
+    sample_count: u32,
 ) -> wgpu::TextureView {
     let texture = device.create_texture(&wgpu::TextureDescriptor {
         label: Some("output msaa texture"),
@@ -14,7 +15,7 @@ pub fn create_output_msaa_view(
             depth_or_array_layers: 1,
         },
         mip_level_count: 1,
-        sample_count: MSAA_SAMPLES,
+        sample_count,
         dimension: wgpu::TextureDimension::D2,
         format,
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -24,10 +25,66 @@ pub fn create_output_msaa_view(
     texture.create_view(&Default::default())
 }
 
+pub fn create_color_view(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("color texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: COLOR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+
+    texture.create_view(&Default::default())
+}
+
+pub fn create_pick_texture(device: &wgpu::Device) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("pick texture"),
+        size: wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: PICK_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+pub fn create_bloom_view(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("bloom texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+
+    texture.create_view(&Default::default())
+}
+
 pub fn create_depth_texture(
     device: &wgpu::Device,
     width: u32,
     height: u32,
+    sample_count: u32,
 ) -> (wgpu::Texture, wgpu::TextureView) {
     let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
         label: Some("depth texture"),
@@ -37,7 +94,7 @@ pub fn create_depth_texture(
             depth_or_array_layers: 1,
         },
         mip_level_count: 1,
-        sample_count: MSAA_SAMPLES,
+        sample_count,
         dimension: wgpu::TextureDimension::D2,
         format: DEPTH_FORMAT,
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -83,3 +140,49 @@ pub fn create_depth_pyramid_texture(
 
     (depth_texture, mip_views)
 }
+
+/// The bytes per row for copying a texture with 4 bytes per pixel to a buffer.
+/// Rows are padded to satisfy wgpu's alignment requirements.
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * 4).div_ceil(align) * align
+}
+
+/// Remove the row padding from data copied with [padded_bytes_per_row].
+pub fn unpadded_rows(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let padded = padded_bytes_per_row(width) as usize;
+    let unpadded = width as usize * 4;
+    data.chunks_exact(padded)
+        .take(height as usize)
+        .flat_map(|row| &row[..unpadded])
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_bytes_per_row_aligned() {
+        assert_eq!(256, padded_bytes_per_row(64));
+        assert_eq!(2048, padded_bytes_per_row(512));
+    }
+
+    #[test]
+    fn padded_bytes_per_row_unaligned() {
+        assert_eq!(256, padded_bytes_per_row(1));
+        assert_eq!(512, padded_bytes_per_row(65));
+        assert_eq!(512, padded_bytes_per_row(100));
+        assert_eq!(1024, padded_bytes_per_row(200));
+    }
+
+    #[test]
+    fn unpadded_rows_removes_padding() {
+        // 2 rows of 1 pixel each padded to 256 bytes.
+        let mut data = vec![0u8; 512];
+        data[..4].copy_from_slice(&[1, 2, 3, 4]);
+        data[256..260].copy_from_slice(&[5, 6, 7, 8]);
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], unpadded_rows(&data, 1, 2));
+    }
+}
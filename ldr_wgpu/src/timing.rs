@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+// Enough for the beginning and end of every pass in a frame.
+const MAX_QUERIES: u32 = 64;
+
+/// GPU durations for each pass measured with timestamp queries.
+///
+/// Results are read back asynchronously and are available a few frames later.
+/// Frames recorded while the previous results are still mapping are not timed.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per timestamp tick.
+    period: f32,
+    // The pass label for each pair of queries written this frame.
+    // Passes only borrow the timer immutably, so this uses a lock to keep the renderer Sync.
+    labels: Mutex<Vec<&'static str>>,
+    // Set once the readback buffer finishes mapping with whether it succeeded.
+    map_result: Option<Arc<OnceLock<bool>>>,
+    last_frame: HashMap<&'static str, Duration>,
+}
+
+impl GpuTimer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("timestamp query set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_QUERIES,
+        });
+
+        let size = MAX_QUERIES as u64 * std::mem::size_of::<u64>() as u64;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp resolve buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp readback buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            labels: Mutex::new(Vec::new()),
+            map_result: None,
+            last_frame: HashMap::new(),
+        }
+    }
+
+    /// The total duration of each pass label for the most recent timed frame.
+    pub fn last_frame(&self) -> &HashMap<&'static str, Duration> {
+        &self.last_frame
+    }
+
+    /// Read any completed results without blocking and start recording a new frame.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        if self.map_result.is_some() {
+            device.poll(wgpu::Maintain::Poll);
+            match self.map_result.as_ref().and_then(|r| r.get().copied()) {
+                Some(true) => self.read_timings(),
+                // The buffer was never mapped, so just discard this frame's timings.
+                Some(false) => (),
+                // Skip timing this frame since the readback buffer is still in use.
+                None => return,
+            }
+            self.map_result = None;
+        }
+        self.labels.lock().unwrap().clear();
+    }
+
+    pub fn compute_pass(
+        &self,
+        label: &'static str,
+    ) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        let (begin, end) = self.next_queries(label)?;
+        Some(wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(begin),
+            end_of_pass_write_index: Some(end),
+        })
+    }
+
+    pub fn render_pass(&self, label: &'static str) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        let (begin, end) = self.next_queries(label)?;
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(begin),
+            end_of_pass_write_index: Some(end),
+        })
+    }
+
+    /// Copy this frame's queries to the readback buffer after all timed passes.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        let count = self.query_count();
+        if self.map_result.is_none() && count > 0 {
+            encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                count as u64 * std::mem::size_of::<u64>() as u64,
+            );
+        }
+    }
+
+    /// Start mapping the resolved queries once the frame has been submitted.
+    pub fn map(&mut self) {
+        let count = self.query_count();
+        if self.map_result.is_some() || count == 0 {
+            return;
+        }
+
+        let map_result = Arc::new(OnceLock::new());
+        let result = map_result.clone();
+        self.readback_buffer
+            .slice(..count as u64 * std::mem::size_of::<u64>() as u64)
+            .map_async(wgpu::MapMode::Read, move |r| {
+                let _ = result.set(r.is_ok());
+            });
+        self.map_result = Some(map_result);
+    }
+
+    fn next_queries(&self, label: &'static str) -> Option<(u32, u32)> {
+        if self.map_result.is_some() {
+            return None;
+        }
+
+        let mut labels = self.labels.lock().unwrap();
+        let begin = labels.len() as u32 * 2;
+        if begin + 2 > MAX_QUERIES {
+            return None;
+        }
+        labels.push(label);
+        Some((begin, begin + 1))
+    }
+
+    fn query_count(&self) -> u32 {
+        self.labels.lock().unwrap().len() as u32 * 2
+    }
+
+    fn read_timings(&mut self) {
+        let size = self.query_count() as u64 * std::mem::size_of::<u64>() as u64;
+        let timestamps: Vec<_> = self
+            .readback_buffer
+            .slice(..size)
+            .get_mapped_range()
+            .chunks_exact(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        self.readback_buffer.unmap();
+
+        self.last_frame = pass_durations(&self.labels.lock().unwrap(), &timestamps, self.period);
+    }
+}
+
+/// Sum the durations for labels like the visibility pass that run more than once per frame.
+fn pass_durations(
+    labels: &[&'static str],
+    timestamps: &[u64],
+    period: f32,
+) -> HashMap<&'static str, Duration> {
+    let mut durations = HashMap::new();
+    for (label, pair) in labels.iter().zip(timestamps.chunks_exact(2)) {
+        // Timestamps aren't guaranteed to increase on all backends.
+        let nanos = pair[1].saturating_sub(pair[0]) as f64 * period as f64;
+        *durations.entry(*label).or_default() += Duration::from_nanos(nanos as u64);
+    }
+    durations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_durations_sum_labels() {
+        let durations = pass_durations(
+            &["Visibility", "Model", "Visibility"],
+            &[100, 150, 150, 400, 400, 410],
+            2.0,
+        );
+        assert_eq!(2, durations.len());
+        assert_eq!(Duration::from_nanos(120), durations["Visibility"]);
+        assert_eq!(Duration::from_nanos(500), durations["Model"]);
+    }
+
+    #[test]
+    fn pass_durations_decreasing() {
+        let durations = pass_durations(&["Blit"], &[200, 100], 1.0);
+        assert_eq!(Duration::ZERO, durations["Blit"]);
+    }
+}
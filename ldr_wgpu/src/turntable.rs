@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use glam::{vec3, Vec3};
+
+use crate::{calculate_camera_data, unpremultiply_alpha, Projection, RenderData, Renderer, UpAxis};
+
+/// The starting camera and number of frames for a 360 degree rotation around the up axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Turntable {
+    /// The size of each frame in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// The camera for the first frame like the results of [crate::fit_camera].
+    pub translation: Vec3,
+    pub rotation_xyz: Vec3,
+    pub pivot: Vec3,
+    pub up_axis: UpAxis,
+    pub projection: Projection,
+    pub frames: u32,
+}
+
+/// Render the frames of `turntable` and save them to `output_dir` as PNG images
+/// with the frame number appended to `name` like `3001_0000.png`.
+///
+/// Accumulation resets before each frame, so each frame averages only its own samples.
+pub fn render_turntable(
+    renderer: &mut Renderer,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    render_data: &mut RenderData,
+    turntable: &Turntable,
+    output_dir: &Path,
+    name: &str,
+) -> Result<(), image::ImageError> {
+    for frame in 0..turntable.frames {
+        let camera_data = calculate_camera_data(
+            turntable.width,
+            turntable.height,
+            turntable.translation,
+            frame_rotation(turntable, frame),
+            turntable.pivot,
+            turntable.up_axis,
+            turntable.projection,
+        );
+        renderer.update_camera(queue, &camera_data);
+        // The camera doesn't change between frames for a single frame turntable.
+        renderer.reset_accumulation();
+
+        let mut image = renderer.render_to_image(
+            device,
+            queue,
+            render_data,
+            turntable.width,
+            turntable.height,
+        );
+        unpremultiply_alpha(&mut image);
+        image.save_with_format(
+            output_dir.join(format!("{name}_{frame:04}.png")),
+            image::ImageFormat::Png,
+        )?;
+    }
+    Ok(())
+}
+
+fn frame_rotation(turntable: &Turntable, frame: u32) -> Vec3 {
+    // Rotate around the up axis without repeating the first frame at the end.
+    let angle = std::f32::consts::TAU * frame as f32 / turntable.frames as f32;
+    turntable.rotation_xyz + vec3(0.0, angle, 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rotation_full_turn() {
+        let turntable = Turntable {
+            width: 512,
+            height: 512,
+            translation: Vec3::ZERO,
+            rotation_xyz: vec3(0.5, 1.0, 0.0),
+            pivot: Vec3::ZERO,
+            up_axis: UpAxis::default(),
+            projection: Projection::default(),
+            frames: 4,
+        };
+        assert_eq!(vec3(0.5, 1.0, 0.0), frame_rotation(&turntable, 0));
+        assert_eq!(
+            vec3(0.5, 1.0 + std::f32::consts::PI, 0.0),
+            frame_rotation(&turntable, 2)
+        );
+    }
+}
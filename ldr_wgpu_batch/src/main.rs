@@ -1,13 +1,15 @@
 use std::path::Path;
 
 use futures::executor::block_on;
-use image::ImageBuffer;
 use ldr_tools::{
     glam::{vec3, Vec3},
-    GeometrySettings, StudType,
+    GeometrySettings,
 };
-use ldr_wgpu::calculate_camera_data;
-use log::info;
+use ldr_wgpu::{
+    calculate_camera_data, fit_camera, orbit_pivot, Projection, QualityPreset, Tonemap, UpAxis,
+    FOV_Y,
+};
+use log::{error, info};
 
 const WIDTH: u32 = 512;
 const HEIGHT: u32 = 512;
@@ -18,6 +20,51 @@ fn main() {
     let input_folder = &args[2];
     let output_folder = &args[3];
 
+    let quality = args
+        .iter()
+        .position(|a| a == "--quality")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse::<QualityPreset>().ok())
+        .unwrap_or_default()
+        .settings();
+
+    // Clear to transparent for clean line art exports.
+    let transparent = args.iter().any(|a| a == "--transparent");
+    // Render at a higher resolution and downsample to smooth thin edge lines.
+    let supersample = if args.iter().any(|a| a == "--supersample") {
+        quality.supersample.max(2)
+    } else {
+        quality.supersample
+    };
+    // JPEG and WebP produce much smaller files for large catalogs of thumbnails.
+    let image_format = match args
+        .iter()
+        .position(|a| a == "--format")
+        .and_then(|i| args.get(i + 1))
+        .map(|a| a.as_str())
+    {
+        Some("jpeg") | Some("jpg") => image::ImageFormat::Jpeg,
+        Some("webp") => image::ImageFormat::WebP,
+        _ => image::ImageFormat::Png,
+    };
+
+    // Simplify detailed parts since small details aren't visible in thumbnails.
+    let mut scene_settings = ldr_wgpu::SceneSettings {
+        max_triangles_per_part: args
+            .iter()
+            .position(|a| a == "--max-triangles")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|a| a.parse().ok())
+            .or(quality.scene.max_triangles_per_part),
+        // Parts are often shared between models, so caching helps even on the first run.
+        cache_dir: args
+            .iter()
+            .position(|a| a == "--cache")
+            .and_then(|i| args.get(i + 1))
+            .map(Into::into),
+        ..quality.scene.clone()
+    };
+
     // Ignore most logs to avoid flooding the console.
     simple_logger::SimpleLogger::new()
         .with_level(log::LevelFilter::Warn)
@@ -52,44 +99,42 @@ fn main() {
 
     let format = wgpu::TextureFormat::Rgba8UnormSrgb;
 
-    let size = wgpu::Extent3d {
-        width: WIDTH,
-        height: HEIGHT,
-        depth_or_array_layers: 1,
-    };
-    let texture_desc = wgpu::TextureDescriptor {
-        size,
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format,
-        usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
-        label: None,
-        view_formats: &[],
-    };
-    let output = device.create_texture(&texture_desc);
-    let output_view = output.create_view(&Default::default());
-
-    let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        size: WIDTH as u64 * HEIGHT as u64 * 4,
-        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-        label: None,
-        mapped_at_creation: false,
-    });
-
     // Weld vertices to take advantage of vertex caching/batching on the GPU.
     let settings = GeometrySettings {
         triangulate: true,
         weld_vertices: true,
-        stud_type: StudType::HighContrast,
+        stud_type: quality.stud_type,
         ..Default::default()
     };
 
-    let color_table = ldr_tools::load_color_table(ldraw_path);
+    let mut color_table = ldr_tools::load_color_table(ldraw_path);
+    // Lighter edges for dark colors and chrome use the EDGE values from LDConfig.
+    scene_settings.edge_colors =
+        std::fs::read_to_string(Path::new(ldraw_path).join("LDConfig.ldr"))
+            .map(|text| ldr_wgpu::edge_colors(&text))
+            .unwrap_or_default();
+    // Alternate color definitions like LDCfgalt.ldr can look more realistic.
+    if let Some(ldconfig) = args
+        .iter()
+        .position(|a| a == "--ldconfig")
+        .and_then(|i| args.get(i + 1))
+    {
+        let text = std::fs::read_to_string(ldconfig).unwrap();
+        ldr_wgpu::apply_color_config(&mut color_table, &text);
+        scene_settings
+            .edge_colors
+            .extend(ldr_wgpu::edge_colors(&text));
+    }
 
-    let translation = vec3(0.0, -0.5, -200.0);
-    let rotation_xyz = Vec3::ZERO;
-    let camera_data = calculate_camera_data(WIDTH, HEIGHT, translation, rotation_xyz);
+    let camera_data = calculate_camera_data(
+        WIDTH,
+        HEIGHT,
+        vec3(0.0, -0.5, -200.0),
+        Vec3::ZERO,
+        Vec3::ZERO,
+        UpAxis::default(),
+        Projection::default(),
+    );
 
     let mut renderer = ldr_wgpu::Renderer::new(
         &device,
@@ -97,100 +142,242 @@ fn main() {
         HEIGHT,
         &camera_data,
         format,
+        ldr_wgpu::SampleCount::default(),
         supported_features,
     );
+    if transparent {
+        renderer.set_clear_color(wgpu::Color::TRANSPARENT);
+    }
+    renderer.set_quality(&queue, &quality);
+    // The renderer downsamples to the output size.
+    renderer.set_supersample_factor(&device, supersample as f32);
+    // Average jittered frames for smoother edges without the memory cost of supersampling.
+    renderer.set_accumulation(
+        &device,
+        args.iter()
+            .position(|a| a == "--accumulate")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|a| a.parse().ok()),
+    );
+    // Compress bright highlights instead of clipping them in 8-bit images.
+    let tonemap = args
+        .iter()
+        .position(|a| a == "--tonemap")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse::<Tonemap>().ok())
+        .unwrap_or_default();
+    renderer.set_tonemap(&queue, tonemap);
 
     let start = std::time::Instant::now();
 
-    globwalk::GlobWalkerBuilder::from_patterns(input_folder, &["*.{dat}"])
+    let paths: Vec<_> = globwalk::GlobWalkerBuilder::from_patterns(input_folder, &["*.{dat}"])
         .max_depth(1)
         .build()
         .unwrap()
-        .for_each(|entry| {
-            let path = entry.as_ref().unwrap().path();
-            println!("{path:?}");
+        .map(|entry| entry.unwrap().into_path())
+        .collect();
 
-            let start = std::time::Instant::now();
-            let scene =
-                ldr_tools::load_file_instanced(path.to_str().unwrap(), ldraw_path, &[], &settings);
-            info!("Load scene: {:?}", start.elapsed());
+    // Render all parts side by side in a single scene for comparing similar parts.
+    if let Some(spacing) = args
+        .iter()
+        .position(|a| a == "--combine")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse::<f32>().ok())
+    {
+        let scenes: Vec<_> = paths
+            .iter()
+            .filter_map(|path| {
+                match ldr_wgpu::load_scene(path.to_str().unwrap(), ldraw_path, &settings) {
+                    Ok(scene) => Some(scene),
+                    Err(e) => {
+                        error!("Failed to load {path:?}: {e}");
+                        None
+                    }
+                }
+            })
+            .collect();
+        let layouts = ldr_wgpu::grid_layout(scenes.len(), spacing);
+        let scenes: Vec<_> = scenes.iter().zip(layouts).collect();
 
-            let mut render_data = ldr_wgpu::RenderData::new(&device, &scene, &color_table);
+        let mut render_data = match ldr_wgpu::RenderData::new_combined(
+            &device,
+            &scenes,
+            &color_table,
+            &scene_settings,
+        ) {
+            Ok(render_data) => render_data,
+            Err(e) => {
+                error!("Failed to load combined scene: {e}");
+                return;
+            }
+        };
 
-            renderer.render(&device, &queue, &mut render_data, &output_view);
+        let bounds = render_data.scene().bounds();
+        let (translation, rotation_xyz) = fit_camera(bounds, WIDTH, HEIGHT, FOV_Y);
+        let camera_data = calculate_camera_data(
+            WIDTH,
+            HEIGHT,
+            translation,
+            rotation_xyz,
+            orbit_pivot(bounds),
+            UpAxis::default(),
+            Projection::default(),
+        );
+        renderer.update_camera(&queue, &camera_data);
 
-            let file_name = path.with_extension("png");
-            let file_name = file_name.file_name().unwrap();
-            let output_path = Path::new(output_folder).join(file_name);
+        let image = renderer.render_to_image(&device, &queue, &mut render_data, WIDTH, HEIGHT);
 
-            let encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("PNG Render Encoder"),
-            });
+        let file_name = Path::new("combined").with_extension(image_format.extensions_str()[0]);
+        save_image(
+            image,
+            Path::new(output_folder).join(file_name),
+            image_format,
+        );
 
-            save_screenshot(
+        println!("{:?}", start.elapsed());
+        return;
+    }
+
+    // Arrange all parts in a single image with one auto framed part per cell.
+    let sheet_columns = args
+        .iter()
+        .position(|a| a == "--contact-sheet")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse::<u32>().ok())
+        .filter(|c| *c > 0);
+    let mut contact_sheet = sheet_columns.map(|columns| {
+        let rows = (paths.len() as u32).div_ceil(columns);
+        image::RgbaImage::new(columns * WIDTH, rows * HEIGHT)
+    });
+    let sheet_columns = sheet_columns.unwrap_or(1);
+
+    // Also save a numbered sequence of frames rotating around each part.
+    let turntable_frames = args
+        .iter()
+        .position(|a| a == "--turntable")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|a| a.parse::<u32>().ok())
+        .filter(|f| *f > 0);
+
+    paths.iter().enumerate().for_each(|(i, path)| {
+        println!("{path:?}");
+
+        let start = std::time::Instant::now();
+        let scene = match ldr_wgpu::load_scene(path.to_str().unwrap(), ldraw_path, &settings) {
+            Ok(scene) => scene,
+            Err(e) => {
+                error!("Failed to load {path:?}: {e}");
+                return;
+            }
+        };
+        info!("Load scene: {:?}", start.elapsed());
+
+        let mut render_data =
+            match ldr_wgpu::RenderData::new(&device, &scene, &color_table, &scene_settings) {
+                Ok(render_data) => render_data,
+                Err(e) => {
+                    error!("Failed to load {path:?}: {e}");
+                    return;
+                }
+            };
+        info!("{:?}", render_data.scene().memory_usage());
+
+        // Frame each model since part sizes vary widely.
+        let bounds = render_data.scene().bounds();
+        let (translation, rotation_xyz) = fit_camera(bounds, WIDTH, HEIGHT, FOV_Y);
+        let camera_data = calculate_camera_data(
+            WIDTH,
+            HEIGHT,
+            translation,
+            rotation_xyz,
+            orbit_pivot(bounds),
+            UpAxis::default(),
+            Projection::default(),
+        );
+        renderer.update_camera(&queue, &camera_data);
+        // Parts framed with the same camera shouldn't reuse samples from the previous part.
+        renderer.reset_accumulation();
+
+        let image = renderer.render_to_image(&device, &queue, &mut render_data, WIDTH, HEIGHT);
+
+        match &mut contact_sheet {
+            Some(sheet) => {
+                // Fill the cells in row major order.
+                let x = (i as u32 % sheet_columns) * WIDTH;
+                let y = (i as u32 / sheet_columns) * HEIGHT;
+                image::imageops::replace(sheet, &image, x as i64, y as i64);
+            }
+            None => {
+                let file_name = path.with_extension(image_format.extensions_str()[0]);
+                let file_name = file_name.file_name().unwrap();
+                let output_path = Path::new(output_folder).join(file_name);
+                save_image(image, output_path, image_format);
+            }
+        }
+
+        if let Some(frames) = turntable_frames {
+            let turntable = ldr_wgpu::Turntable {
+                width: WIDTH,
+                height: HEIGHT,
+                translation,
+                rotation_xyz,
+                pivot: orbit_pivot(bounds),
+                up_axis: UpAxis::default(),
+                projection: Projection::default(),
+                frames,
+            };
+            let name = path.file_stem().unwrap().to_string_lossy();
+            if let Err(e) = ldr_wgpu::render_turntable(
+                &mut renderer,
                 &device,
                 &queue,
-                encoder,
-                &output,
-                &output_buffer,
-                size,
-                output_path,
-            );
-
-            // Clean up resources.
-            queue.submit(std::iter::empty());
-            device.poll(wgpu::Maintain::Wait);
-        });
+                &mut render_data,
+                &turntable,
+                Path::new(output_folder),
+                &name,
+            ) {
+                error!("Failed to save turntable frames for {path:?}: {e}");
+            }
+        }
+
+        // Clean up resources.
+        render_data.release();
+        queue.submit(std::iter::empty());
+        device.poll(wgpu::Maintain::Wait);
+    });
+
+    if let Some(sheet) = contact_sheet {
+        let file_name = Path::new("contact_sheet").with_extension(image_format.extensions_str()[0]);
+        save_image(
+            sheet,
+            Path::new(output_folder).join(file_name),
+            image_format,
+        );
+    }
 
     println!("{:?}", start.elapsed());
 }
 
-fn save_screenshot(
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    mut encoder: wgpu::CommandEncoder,
-    output: &wgpu::Texture,
-    output_buffer: &wgpu::Buffer,
-    size: wgpu::Extent3d,
+/// Save a premultiplied `buffer` to `output_path`.
+fn save_image(
+    mut buffer: image::RgbaImage,
     output_path: std::path::PathBuf,
+    format: image::ImageFormat,
 ) {
-    encoder.copy_texture_to_buffer(
-        wgpu::ImageCopyTexture {
-            aspect: wgpu::TextureAspect::All,
-            texture: output,
-            mip_level: 0,
-            origin: wgpu::Origin3d::ZERO,
-        },
-        wgpu::ImageCopyBuffer {
-            buffer: output_buffer,
-            layout: wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(WIDTH * 4),
-                rows_per_image: Some(HEIGHT),
-            },
-        },
-        size,
-    );
-    queue.submit([encoder.finish()]);
-
-    // Save the output texture.
-    // Adapted from WGPU Example https://github.com/gfx-rs/wgpu/tree/master/wgpu/examples/capture
-    {
-        // TODO: Find ways to optimize this?
-        let buffer_slice = output_buffer.slice(..);
-
-        // TODO: Reuse the channel?
-        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
-        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-            tx.send(result).unwrap();
-        });
-        device.poll(wgpu::Maintain::Wait);
-        block_on(rx.receive()).unwrap().unwrap();
-
-        let data = buffer_slice.get_mapped_range();
-        let buffer =
-            ImageBuffer::<image::Rgba<u8>, _>::from_raw(WIDTH, HEIGHT, data.to_owned()).unwrap();
-        buffer.save(output_path).unwrap();
+    if format == image::ImageFormat::Jpeg {
+        // JPEG has no alpha, so composite over white using premultiplied alpha.
+        for pixel in buffer.pixels_mut() {
+            let [r, g, b, a] = pixel.0;
+            let over = |c: u8| c.saturating_add(255 - a);
+            pixel.0 = [over(r), over(g), over(b), 255];
+        }
+        image::DynamicImage::ImageRgba8(buffer)
+            .to_rgb8()
+            .save_with_format(output_path, format)
+            .unwrap();
+    } else {
+        // The renderer outputs premultiplied alpha, but PNG and WebP expect straight alpha.
+        ldr_wgpu::unpremultiply_alpha(&mut buffer);
+        buffer.save_with_format(output_path, format).unwrap();
     }
-    output_buffer.unmap();
 }